@@ -10,3 +10,5 @@ const GBA_AUDIO_VOLUME_MAX: i16 = 0x100; // Max volume
 #[allow(dead_code)]
 static CLOCKS_PER_FRAME: u32 = 0x800; // For syncing CPU clock time with Audio
 
+mod sound;
+pub use sound::{directsound, dmg};
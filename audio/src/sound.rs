@@ -5,6 +5,9 @@
 mod noise;
 mod pitch;
 mod amplify;
+mod volume_envelope;
+pub mod dmg;
+pub mod directsound;
 extern crate rodio;
 extern crate sample;
 use rodio::Sink;
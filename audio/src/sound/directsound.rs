@@ -0,0 +1,130 @@
+// DirectSound channels A and B
+// 8 bit PCM samples are pushed into 32 byte FIFOs, usually by DMA1/2, and each channel plays
+// its next sample whenever the timer it follows (timer 0 or 1) overflows.
+use super::dmg::SAMPLE_RATE;
+use std::collections::VecDeque;
+
+pub const FIFO_A: u32 = 0x040000A0;
+pub const FIFO_B: u32 = 0x040000A4;
+const SOUNDCNT_H: u32 = 0x04000082;
+const FIFO_SIZE: usize = 32;
+// a DMA refill is requested once half the FIFO was played
+const FIFO_LOW: usize = 16;
+const CLOCK_SPEED: u64 = 16_777_216;
+
+#[derive(Default)]
+struct Fifo {
+    samples: VecDeque<i8>,
+    current: i8, // sample being played
+}
+
+impl Fifo {
+    fn push(&mut self, value: u16) {
+        for &sample in value.to_le_bytes().iter() {
+            if self.samples.len() < FIFO_SIZE {
+                self.samples.push_back(sample as i8);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.samples.clear();
+        self.current = 0;
+    }
+}
+
+pub struct DirectSound {
+    fifos: [Fifo; 2],
+    control: u16, // SOUNDCNT_H
+    sample_timer: u64,
+    samples: Vec<i16>, // Interleaved left/right at SAMPLE_RATE
+}
+
+impl DirectSound {
+    pub fn create() -> DirectSound {
+        DirectSound {
+            fifos: Default::default(),
+            control: 0,
+            sample_timer: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Handles a write to the FIFOs or SOUNDCNT_H, as drained from the memory's sound_writes
+    pub fn write(&mut self, address: u32, value: u16) {
+        match address {
+            0x040000A0 | 0x040000A2 => self.fifos[0].push(value),
+            0x040000A4 | 0x040000A6 => self.fifos[1].push(value),
+            SOUNDCNT_H => {
+                self.control = value;
+                if value & 0x0800 != 0 {
+                    self.fifos[0].reset();
+                }
+                if value & 0x8000 != 0 {
+                    self.fifos[1].reset();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Timer 0 or 1 overflowed, the channels following it play their next sample.
+    /// Returns the FIFOs running low, their DMA should be started with `dma_sound_fifo`.
+    pub fn timer_overflow(&mut self, timer: usize) -> [Option<u32>; 2] {
+        let mut refill = [None; 2];
+        for (channel, &fifo_address) in [FIFO_A, FIFO_B].iter().enumerate() {
+            // timer select, bit 10 for A and bit 14 for B
+            let channel_timer = (self.control >> (10 + channel * 4)) as usize & 1;
+            if channel_timer != timer {
+                continue;
+            }
+
+            let fifo = &mut self.fifos[channel];
+            if let Some(sample) = fifo.samples.pop_front() {
+                fifo.current = sample;
+            }
+            if fifo.samples.len() <= FIFO_LOW {
+                refill[channel] = Some(fifo_address);
+            }
+        }
+        refill
+    }
+
+    /// Current output of both channels as (left, right)
+    pub fn output(&self) -> (i16, i16) {
+        let mut left = 0;
+        let mut right = 0;
+        for channel in 0..2 {
+            // 50% or 100% volume
+            let volume = if self.control & (0b100 << channel) != 0 { 2 } else { 1 };
+            let sample = (self.fifos[channel].current as i16 * volume) << 6;
+
+            let enable = self.control >> (8 + channel * 4);
+            if enable & 0b01 != 0 {
+                right += sample;
+            }
+            if enable & 0b10 != 0 {
+                left += sample;
+            }
+        }
+        (left, right)
+    }
+
+    /// Run for the given number of cycles, sampling the output at SAMPLE_RATE
+    pub fn step(&mut self, cycles: u32) {
+        self.sample_timer += cycles as u64 * SAMPLE_RATE as u64;
+        while self.sample_timer >= CLOCK_SPEED {
+            self.sample_timer -= CLOCK_SPEED;
+            let (left, right) = self.output();
+            self.samples.push(left);
+            self.samples.push(right);
+        }
+    }
+
+    /// Hand over the samples produced so far
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+pub mod tests;
@@ -0,0 +1,57 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    // channel A on timer 0 and B on timer 1, both at full volume on both sides
+    const BOTH_CHANNELS: u16 = 0x730C;
+
+    #[test]
+    fn test_directsound_fifo() {
+        let mut sound = DirectSound::create();
+        sound.write(SOUNDCNT_H, BOTH_CHANNELS);
+        sound.write(FIFO_A, 0xFE01);
+        assert_eq!(sound.output(), (0, 0));
+
+        // samples play from the low byte on
+        sound.timer_overflow(0);
+        assert_eq!(sound.output(), (2 << 6, 2 << 6));
+        sound.timer_overflow(0);
+        assert_eq!(sound.output(), (-4 << 6, -4 << 6));
+
+        // timer 1 only clocks channel B, an empty FIFO keeps its last sample
+        sound.write(FIFO_B, 0x0003);
+        sound.timer_overflow(1);
+        assert_eq!(sound.output(), (2 << 6, 2 << 6));
+        sound.timer_overflow(0);
+        assert_eq!(sound.output(), (2 << 6, 2 << 6));
+    }
+
+    #[test]
+    fn test_directsound_refill() {
+        let mut sound = DirectSound::create();
+        sound.write(SOUNDCNT_H, BOTH_CHANNELS);
+        for _ in 0..FIFO_SIZE / 2 {
+            sound.write(FIFO_A, 0x0101);
+        }
+
+        // a full FIFO asks for more once half of it was played
+        for _ in 0..FIFO_SIZE - FIFO_LOW - 1 {
+            assert_eq!(sound.timer_overflow(0), [None, None]);
+        }
+        assert_eq!(sound.timer_overflow(0), [Some(FIFO_A), None]);
+        assert_eq!(sound.timer_overflow(1), [None, Some(FIFO_B)]);
+
+        // the reset bit empties the FIFO
+        sound.write(SOUNDCNT_H, BOTH_CHANNELS | 0x0800);
+        sound.timer_overflow(0);
+        assert_eq!(sound.output(), (0, 0));
+    }
+
+    #[test]
+    fn test_directsound_samples() {
+        let mut sound = DirectSound::create();
+        sound.step(CLOCK_SPEED as u32 / 2);
+        assert_eq!(sound.take_samples().len(), SAMPLE_RATE as usize);
+        assert!(sound.take_samples().is_empty());
+    }
+}
@@ -0,0 +1,532 @@
+// Game Boy sound registers (0xFF10-0xFF3F), used when running GB cartridges.
+// The memory map records every write to the sound registers, feed them in order through `Apu::write`.
+use super::volume_envelope::VolumeEnvelope;
+use rodio::buffer::SamplesBuffer;
+use rodio::Sink;
+
+pub const SAMPLE_RATE: u32 = 44100;
+const CLOCK_SPEED: u32 = 4_194_304;     // Sound runs off the normal speed clock, even in CGB double speed
+const SEQUENCER_PERIOD: u32 = 8192;     // 512Hz frame sequencer
+
+const DUTY: [u8; 4] = [
+    0b0000_0001,    // 12.5%
+    0b1000_0001,    // 25%
+    0b1000_0111,    // 50%
+    0b0111_1110,    // 75%
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Pulse channels 1 and 2, channel 1 adds the frequency sweep
+struct Square {
+    base: u16,              // Address of NRx0, NR20 does not exist
+    enabled: bool,
+    dac: bool,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,         // [0-2047] F(hz)=131072/(2048-X)
+    timer: u32,
+    length: u16,
+    length_enabled: bool,
+    envelope: VolumeEnvelope,
+
+    sweep: bool,            // Only channel 1 has a sweep unit
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_timer: u8,
+    sweep_enabled: bool,
+    shadow_frequency: u16,
+}
+
+impl Square {
+    fn create(base: u16, sweep: bool) -> Square {
+        Square {
+            base,
+            enabled: false,
+            dac: false,
+            duty: 2,
+            duty_step: 0,
+            frequency: 0,
+            timer: 8192,
+            length: 0,
+            length_enabled: false,
+            envelope: VolumeEnvelope::create(),
+            sweep,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_timer: 0,
+            sweep_enabled: false,
+            shadow_frequency: 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.envelope.rw_reset(address, value);
+
+        match address - self.base {
+            0 if self.sweep => {
+                self.sweep_period = (value >> 4) & 0b111;
+                self.sweep_negate = value & 0x8 != 0;
+                self.sweep_shift = value & 0b111;
+            }
+            1 => {
+                self.duty = value >> 6;
+                self.length = 64 - (value & 0x3F) as u16;
+            }
+            2 => {
+                self.dac = value & 0xF8 != 0;
+                self.enabled &= self.dac;
+            }
+            3 => self.frequency = self.frequency & 0x700 | value as u16,
+            4 => {
+                self.frequency = self.frequency & 0xFF | ((value & 0b111) as u16) << 8;
+                self.length_enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac;
+        if self.length == 0 {
+            self.length = 64;
+        }
+        self.timer = (2048 - self.frequency as u32) * 4;
+
+        if self.sweep {
+            self.shadow_frequency = self.frequency;
+            self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+            self.sweep_enabled = self.sweep_period != 0 || self.sweep_shift != 0;
+            if self.sweep_shift != 0 {
+                self.sweep_frequency();
+            }
+        }
+    }
+
+    // Next frequency of the sweep, turns the channel off when it overflows
+    fn sweep_frequency(&mut self) -> u16 {
+        let delta = self.shadow_frequency >> self.sweep_shift;
+        let frequency = if self.sweep_negate {
+            self.shadow_frequency.wrapping_sub(delta)
+        } else {
+            self.shadow_frequency + delta
+        };
+
+        if frequency > 2047 {
+            self.enabled = false;
+        }
+        frequency
+    }
+
+    fn step_sweep(&mut self) {
+        if self.sweep_timer > 0 {
+            self.sweep_timer -= 1;
+        }
+        if self.sweep_timer != 0 {
+            return;
+        }
+
+        self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+        if self.sweep_enabled && self.sweep_period != 0 {
+            let frequency = self.sweep_frequency();
+            if frequency <= 2047 && self.sweep_shift != 0 {
+                self.shadow_frequency = frequency;
+                self.frequency = frequency;
+                self.sweep_frequency();     // Checked twice, the second result is thrown away
+            }
+        }
+    }
+
+    fn step_length(&mut self) {
+        if self.length_enabled && self.length > 0 {
+            self.length -= 1;
+            if self.length == 0 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn clock(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = (2048 - self.frequency as u32) * 4;
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && DUTY[self.duty as usize] >> self.duty_step & 1 != 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+// Channel 3 plays back the 32 4-bit samples in wave RAM
+struct Wave {
+    enabled: bool,
+    dac: bool,
+    volume_shift: u8,       // 0: mute, 1: 100%, 2: 50%, 3: 25%
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    length: u16,
+    length_enabled: bool,
+    ram: [u8; 16],
+}
+
+impl Wave {
+    fn create() -> Wave {
+        Wave {
+            enabled: false,
+            dac: false,
+            volume_shift: 0,
+            frequency: 0,
+            timer: 4096,
+            position: 0,
+            length: 0,
+            length_enabled: false,
+            ram: [0; 16],
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF1A => {
+                self.dac = value & 0x80 != 0;
+                self.enabled &= self.dac;
+            }
+            0xFF1B => self.length = 256 - value as u16,
+            0xFF1C => self.volume_shift = (value >> 5) & 0b11,
+            0xFF1D => self.frequency = self.frequency & 0x700 | value as u16,
+            0xFF1E => {
+                self.frequency = self.frequency & 0xFF | ((value & 0b111) as u16) << 8;
+                self.length_enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.enabled = self.dac;
+                    if self.length == 0 {
+                        self.length = 256;
+                    }
+                    self.timer = (2048 - self.frequency as u32) * 2;
+                    self.position = 0;
+                }
+            }
+            0xFF30..=0xFF3F => self.ram[(address - 0xFF30) as usize] = value,
+            _ => (),
+        }
+    }
+
+    fn step_length(&mut self) {
+        if self.length_enabled && self.length > 0 {
+            self.length -= 1;
+            if self.length == 0 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn clock(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = (2048 - self.frequency as u32) * 2;
+            self.position = (self.position + 1) % 32;
+        }
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled || self.volume_shift == 0 {
+            return 0;
+        }
+
+        let byte = self.ram[self.position as usize / 2];
+        let sample = if self.position & 1 == 0 { byte >> 4 } else { byte & 0xF };
+        sample >> (self.volume_shift - 1)
+    }
+}
+
+// Channel 4, a linear feedback shift register
+struct Noise {
+    enabled: bool,
+    dac: bool,
+    shift: u8,
+    narrow: bool,           // 7 stage instead of 15 stage register
+    divisor: u8,
+    timer: u32,
+    lfsr: u16,
+    length: u16,
+    length_enabled: bool,
+    envelope: VolumeEnvelope,
+}
+
+impl Noise {
+    fn create() -> Noise {
+        Noise {
+            enabled: false,
+            dac: false,
+            shift: 0,
+            narrow: false,
+            divisor: 0,
+            timer: 8,
+            lfsr: 0x7FFF,
+            length: 0,
+            length_enabled: false,
+            envelope: VolumeEnvelope::create(),
+        }
+    }
+
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[self.divisor as usize] << self.shift
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.envelope.rw_reset(address, value);
+
+        match address {
+            0xFF20 => self.length = 64 - (value & 0x3F) as u16,
+            0xFF21 => {
+                self.dac = value & 0xF8 != 0;
+                self.enabled &= self.dac;
+            }
+            0xFF22 => {
+                self.shift = value >> 4;
+                self.narrow = value & 0x8 != 0;
+                self.divisor = value & 0b111;
+            }
+            0xFF23 => {
+                self.length_enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.enabled = self.dac;
+                    if self.length == 0 {
+                        self.length = 64;
+                    }
+                    self.timer = self.period();
+                    self.lfsr = 0x7FFF;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn step_length(&mut self) {
+        if self.length_enabled && self.length > 0 {
+            self.length -= 1;
+            if self.length == 0 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn clock(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+
+            let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | feedback << 14;
+            if self.narrow {
+                self.lfsr = self.lfsr & !(1 << 6) | feedback << 6;
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 1 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+/// The four Game Boy sound channels and their mixer
+pub struct Apu {
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+
+    enabled: bool,          // NR52 bit 7
+    master_volume: u8,      // NR50
+    panning: u8,            // NR51
+    sequencer_timer: u32,
+    sequencer_step: u8,
+    sample_timer: u32,
+    samples: Vec<i16>,      // Interleaved left/right at SAMPLE_RATE
+}
+
+impl Apu {
+    pub fn create() -> Apu {
+        Apu {
+            square1: Square::create(0xFF10, true),
+            square2: Square::create(0xFF15, false),
+            wave: Wave::create(),
+            noise: Noise::create(),
+            enabled: true,
+            master_volume: 0x77,
+            panning: 0xF3,
+            sequencer_timer: SEQUENCER_PERIOD,
+            sequencer_step: 0,
+            sample_timer: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Handle a write to one of the sound registers
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF26 => {
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    // Powering off clears every register but wave RAM
+                    let ram = self.wave.ram;
+                    *self = Apu { enabled: false, samples: std::mem::take(&mut self.samples), ..Apu::create() };
+                    self.wave.ram = ram;
+                }
+            }
+            0xFF30..=0xFF3F => self.wave.write(address, value),
+            _ if !self.enabled => (),
+            0xFF10..=0xFF14 => self.square1.write(address, value),
+            0xFF15..=0xFF19 => self.square2.write(address, value),
+            0xFF1A..=0xFF1E => self.wave.write(address, value),
+            0xFF1F..=0xFF23 => self.noise.write(address, value),
+            0xFF24 => self.master_volume = value,
+            0xFF25 => self.panning = value,
+            _ => (),
+        }
+    }
+
+    /// Lower bits of NR52, set while a channel is playing
+    pub fn status(&self) -> u8 {
+        self.square1.enabled as u8
+            | (self.square2.enabled as u8) << 1
+            | (self.wave.enabled as u8) << 2
+            | (self.noise.enabled as u8) << 3
+    }
+
+    /// Run for the given number of normal speed clocks
+    pub fn step(&mut self, cycles: u32) {
+        if !self.enabled {
+            self.push_samples(cycles);
+            return;
+        }
+
+        self.square1.clock(cycles);
+        self.square2.clock(cycles);
+        self.wave.clock(cycles);
+        self.noise.clock(cycles);
+
+        if cycles >= self.sequencer_timer {
+            self.sequencer_timer += SEQUENCER_PERIOD;
+            self.step_sequencer();
+        }
+        self.sequencer_timer -= cycles;
+
+        self.push_samples(cycles);
+    }
+
+    // Lengths at 256Hz, sweep at 128Hz and envelopes at 64Hz
+    fn step_sequencer(&mut self) {
+        if self.sequencer_step & 1 == 0 {
+            self.square1.step_length();
+            self.square2.step_length();
+            self.wave.step_length();
+            self.noise.step_length();
+        }
+        if self.sequencer_step == 2 || self.sequencer_step == 6 {
+            self.square1.step_sweep();
+        }
+        if self.sequencer_step == 7 {
+            self.square1.envelope.step();
+            self.square2.envelope.step();
+            self.noise.envelope.step();
+        }
+        self.sequencer_step = (self.sequencer_step + 1) % 8;
+    }
+
+    fn push_samples(&mut self, cycles: u32) {
+        self.sample_timer += cycles * SAMPLE_RATE;
+        while self.sample_timer >= CLOCK_SPEED {
+            self.sample_timer -= CLOCK_SPEED;
+            let (left, right) = self.mix();
+            self.samples.push(left);
+            self.samples.push(right);
+        }
+    }
+
+    fn mix(&self) -> (i16, i16) {
+        if !self.enabled {
+            return (0, 0);
+        }
+
+        let outputs = [
+            (self.square1.output(), self.square1.dac),
+            (self.square2.output(), self.square2.dac),
+            (self.wave.output(), self.wave.dac),
+            (self.noise.output(), self.noise.dac),
+        ];
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (channel, &(output, dac)) in outputs.iter().enumerate() {
+            if !dac {
+                continue;
+            }
+            // Each DAC maps 0-15 to -1.0 to 1.0
+            let analog = output as f32 / 7.5 - 1.0;
+            if self.panning & (0x10 << channel) != 0 {
+                left += analog;
+            }
+            if self.panning & (1 << channel) != 0 {
+                right += analog;
+            }
+        }
+
+        let left_volume = ((self.master_volume >> 4) & 0b111) as f32 + 1.0;
+        let right_volume = (self.master_volume & 0b111) as f32 + 1.0;
+        let scale = i16::MAX as f32 / 4.0 / 8.0 * 0.5;      // Four channels at volume 8, at half the range to leave headroom
+        (
+            (left * left_volume * scale) as i16,
+            (right * right_volume * scale) as i16,
+        )
+    }
+
+    /// Hand over the samples produced so far
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+/// Buffers waiting to be played past which new ones are dropped, a few frames worth
+const MAX_QUEUED_BUFFERS: usize = 4;
+
+/// Plays the APU output on the default audio device
+pub struct Speaker {
+    sink: Sink,
+}
+
+impl Speaker {
+    pub fn create() -> Result<Speaker, String> {
+        let device = match rodio::default_output_device() {
+            Some(device) => device,
+            None => return Err("No audio output device available".to_string()),
+        };
+        Ok(Speaker { sink: Sink::new(&device) })
+    }
+
+    /// Queue samples to play. They are dropped while the device is behind, as it is when the
+    /// emulator runs faster than real time.
+    pub fn play(&self, samples: Vec<i16>) {
+        if !samples.is_empty() && self.sink.len() < MAX_QUEUED_BUFFERS {
+            self.sink.append(SamplesBuffer::new(2, SAMPLE_RATE, samples));
+        }
+    }
+}
@@ -1,13 +1,13 @@
-struct VolumeEnvelope {
+pub(crate) struct VolumeEnvelope {
     time : u8,         //Current time/position of the envelope
     mode : bool,       //Decrease or Increase volume
     step_time : u8,    //Amount increased or decreased per step
     initial : u8,      //Initial Volume of the Envelope; 0-15
-    volume : u8,       //Current Volume of the Envelope          
+    pub(crate) volume : u8,       //Current Volume of the Envelope          
 }
 
 impl VolumeEnvelope {
-    fn create() -> VolumeEnvelope {
+    pub(crate) fn create() -> VolumeEnvelope {
         VolumeEnvelope {
             time: 0,       // Start at T=0
             mode: false,   // Decrease volume
@@ -17,7 +17,7 @@ impl VolumeEnvelope {
         }
     }
 
-    fn rw_reset(&mut self, address: u16, value: u8) { //Read+Write Reset
+    pub(crate) fn rw_reset(&mut self, address: u16, value: u8) { //Read+Write Reset
         match address {
             0xFF12 | 0xFF17 | 0xFF21 => {
                 self.time= value & 0x7;            //Reset Envelope Registers (Pulse A, Pulse B, and Noise)
@@ -33,7 +33,7 @@ impl VolumeEnvelope {
         }
     }
 
-    fn step(&mut self) {
+    pub(crate) fn step(&mut self) {
         if self.step_time > 1 {                                // If the envelope step time is more than 1, decrease the envelope step time
             self.step_time -= 1;
         }
@@ -1,4 +1,7 @@
-use crate::{constants::default_cpu, cpu::CPU};
+use crate::{
+    constants::{default_cpu, registers},
+    cpu::CPU,
+};
 
 use crate::enums::{InstructionType, MnemonicARM, ProcessorMode, ShiftType};
 use std::{collections::VecDeque, default::Default};
@@ -196,6 +199,36 @@ impl ARM7TDMI {
             }
         }
     }
+
+    /// Saved program status register of the current mode, user and system mode have none
+    pub fn spsr_mut(&mut self) -> Option<&mut PSR> {
+        match self.cpsr.mode {
+            ProcessorMode::User | ProcessorMode::System => None,
+            ProcessorMode::FIQ => Some(&mut self.spsr_fiq),
+            ProcessorMode::IRQ => Some(&mut self.spsr_irq),
+            ProcessorMode::Supervisor => Some(&mut self.spsr_svc),
+            ProcessorMode::Abort => Some(&mut self.spsr_abt),
+            ProcessorMode::Undefined => Some(&mut self.spsr_und),
+        }
+    }
+
+    /// Enters an exception: banks the CPSR into the SPSR of the new mode, stores the return
+    /// address into its link register and jumps to the vector in ARM state with IRQs disabled.
+    pub fn enter_exception(&mut self, mode: ProcessorMode, vector: u32, return_address: i32) {
+        let cpsr = self.cpsr.clone();
+        self.cpsr.mode = mode.clone();
+        if let Some(spsr) = self.spsr_mut() {
+            *spsr = cpsr;
+        }
+
+        self.store_register(registers::LINK_REGISTER, return_address);
+        self.store_register(registers::PROGRAM_COUNTER, vector as i32);
+        self.cpsr.thumb_mode = false;
+        self.cpsr.disable_irq = true;
+        if mode == ProcessorMode::FIQ {
+            self.cpsr.disable_fiq = true;
+        }
+    }
 }
 
 impl Default for ARM7TDMI {
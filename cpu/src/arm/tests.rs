@@ -317,4 +317,38 @@ mod tests {
 
         assert_eq!(result, BaseInstruction::Multiply);
     }
+
+    #[test]
+    fn test_arm_irq_entry() {
+        use crate::constants::registers::{LINK_REGISTER, PROGRAM_COUNTER};
+        use crate::enums::ProcessorMode;
+        use memory::io::{interrupts, registers};
+
+        let mut cpu = CPU::default();
+        cpu.rom = vec![0; 0x100];
+        cpu.arm.cpsr.mode = ProcessorMode::System;
+        // the instruction at 0x80 was fetched
+        cpu.arm.store_register(PROGRAM_COUNTER, 0x84);
+        cpu.mmu.store16(registers::IE, interrupts::VBLANK);
+        cpu.mmu.store16(registers::IME, 1);
+        cpu.mmu.request_interrupt(interrupts::VBLANK);
+
+        // masked by CPSR.I
+        crate::cpu::interrupt(&mut cpu);
+        assert_eq!(cpu.arm.cpsr.mode, ProcessorMode::System);
+
+        cpu.arm.cpsr.disable_irq = false;
+        crate::cpu::interrupt(&mut cpu);
+        assert_eq!(cpu.arm.cpsr.mode, ProcessorMode::IRQ);
+        assert!(cpu.arm.cpsr.disable_irq);
+        assert_eq!(cpu.arm.spsr_irq.mode, ProcessorMode::System);
+        assert!(!cpu.arm.spsr_irq.disable_irq);
+        assert_eq!(cpu.arm.load_register(LINK_REGISTER), 0x84);
+        // the vector was fetched
+        assert_eq!(cpu.arm.load_register(PROGRAM_COUNTER), 0x1C);
+
+        // the link register is banked
+        cpu.arm.cpsr.mode = ProcessorMode::System;
+        assert_eq!(cpu.arm.load_register(LINK_REGISTER), 0);
+    }
 }
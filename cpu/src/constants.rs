@@ -45,6 +45,17 @@ pub mod registers {
     pub const PROGRAM_COUNTER: usize = 15;
 }
 
+/// Addresses the ARM jumps to when taking an exception
+pub mod exception_vectors {
+    pub const RESET: u32 = 0x00;
+    pub const UNDEFINED: u32 = 0x04;
+    pub const SOFTWARE_INTERRUPT: u32 = 0x08;
+    pub const PREFETCH_ABORT: u32 = 0x0C;
+    pub const DATA_ABORT: u32 = 0x10;
+    pub const IRQ: u32 = 0x18;
+    pub const FIQ: u32 = 0x1C;
+}
+
 /// Default ARM registers
 pub mod default_cpu {
     pub const MMU_DISPLAY: u32 = 1;
@@ -219,3 +230,25 @@ pub mod thumb_bitmasks {
     pub const LONG_BRANCH_OP_MASK: u16 = 0b1111_1000_0000_0000;
     pub const LONG_BRANCH_ADDR_MASK: u16 = 0b0000_0111_1111_1111;
 }
+
+/// LR35902 flag register bits.
+pub mod gb_flags {
+    pub const ZERO: u8 = 0b1000_0000;
+    pub const SUBTRACT: u8 = 0b0100_0000;
+    pub const HALF_CARRY: u8 = 0b0010_0000;
+    pub const CARRY: u8 = 0b0001_0000;
+}
+
+/// LR35902 interrupt sources, in priority order, and their registers.
+pub mod gb_interrupts {
+    pub const VBLANK: u8 = 0b0_0001;
+    pub const LCD_STAT: u8 = 0b0_0010;
+    pub const TIMER: u8 = 0b0_0100;
+    pub const SERIAL: u8 = 0b0_1000;
+    pub const JOYPAD: u8 = 0b1_0000;
+
+    pub const IF: u16 = 0xFF0F;
+    pub const IE: u16 = 0xFFFF;
+    /// Handlers start at 0x40 and are 8 bytes apart
+    pub const VECTOR_BASE: u16 = 0x0040;
+}
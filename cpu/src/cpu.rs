@@ -1,16 +1,24 @@
+use memory::cartridge::Cartridge;
+use memory::dmg::DMG;
+use memory::gpio::{self, Clock, Gpio};
+use memory::power::Power;
+use memory::save::{self, Save, SaveType};
+use memory::tilt;
 use memory::MMU;
 
 use std::collections::VecDeque;
 use std::default::Default;
+use std::path::Path;
 
 use crate::arm::decode_arm;
 use crate::thumb::decode_thumb;
 use crate::{arm, gb};
 
 use crate::constants;
-use crate::enums::InstructionType;
+use crate::enums::{Core, InstructionType, ProcessorMode};
 
-use crate::utils;
+#[cfg(feature = "jit")]
+use crate::jit;
 
 /// This will handle all the memory operations, fetching, decoding and execution of
 /// instructions.
@@ -20,10 +28,20 @@ pub struct CPU {
     pub rom: Vec<u8>,
     pub arm: arm::ARM7TDMI,
     pub lr: gb::LR35902,
+    /// Core that cycle drives, the other one sits idle
+    pub core: Core,
+    /// Game Boy memory map used by the LR35902
+    pub gb_memory: DMG,
     pub should_exit: bool,
+    /// Save chip to use instead of the detected one
+    pub save_type: Option<SaveType>,
+    /// Cycles left until DMA gives the bus back
+    pub dma_stall: u32,
     pub fetched_instruction: InstructionType,
     pub decoded_instruction: InstructionType,
     pub execution_queue: VecDeque<fn(&mut CPU)>,
+    #[cfg(feature = "jit")]
+    pub jit: jit::JIT,
 }
 
 impl Default for CPU {
@@ -34,34 +52,146 @@ impl Default for CPU {
             rom: Vec::new(),
             arm: Default::default(),
             lr: Default::default(),
+            core: Default::default(),
+            gb_memory: Default::default(),
             should_exit: false,
+            save_type: None,
+            dma_stall: 0,
             fetched_instruction: InstructionType::Thumb(0), // 0 is no-op
             decoded_instruction: InstructionType::Thumb(0),
             execution_queue: VecDeque::new(),
+            #[cfg(feature = "jit")]
+            jit: Default::default(),
         }
     }
 }
 
+/// Insert a GBA cartridge, its save chip is detected unless save_type was set. The devices on
+/// the cartridge are found from the game, a real-time clock follows the host time.
+pub fn load_cartridge(cpu: &mut CPU, cartridge: &Cartridge) {
+    cpu.rom = cartridge.rom.clone();
+    cpu.mmu.load_rom(&cartridge.rom);
+    cpu.mmu.gpio = Gpio::new(gpio::detect(&cartridge.rom, Clock::Host));
+    cpu.mmu.tilt = tilt::detect(&cartridge.rom);
+
+    let save_type = cpu
+        .save_type
+        .unwrap_or_else(|| save::detect(&cartridge.rom));
+    match Save::open(&cartridge.save_path(), save_type.chip()) {
+        Ok(save) => cpu.mmu.save = save,
+        Err(error) => eprintln!("{}, the game will not be saved", error),
+    }
+}
+
 // MUST FIX FOR CYCLE ACCURACY!!!
 /// Cycle through memory until it gets signalized to exit.
 pub fn run_rom_max_cycle(cpu: &mut CPU, rom_path: &str) {
-    cpu.rom = utils::read_rom_to_memory(rom_path).unwrap();
+    match Cartridge::load(Path::new(rom_path), None) {
+        Ok(cartridge) => load_cartridge(cpu, &cartridge),
+        Err(error) => return eprintln!("{}", error),
+    }
+
     while !cpu.should_exit {
         cycle(cpu);
+        // there is nothing to play the sound on
+        cpu.mmu.sound_writes.clear();
+        cpu.mmu.take_fifo_ticks();
+    }
+
+    if let Err(error) = cpu.mmu.save.flush() {
+        eprintln!("{}", error);
     }
 }
 
+/// Switch to the LR35902 and insert a Game Boy cartridge
+pub fn load_gb_rom(cpu: &mut CPU, rom: Vec<u8>) -> Result<(), String> {
+    cpu.gb_memory = DMG::new(rom)?;
+    cpu.lr = Default::default();
+    if cpu.gb_memory.cgb {
+        cpu.lr.registers = gb::Registers::cgb();
+    }
+    cpu.core = Core::LR35902;
+    Ok(())
+}
+
 // MUST FIX FOR CYCLE ACCURACY!!!
 /// Run F->D->E cycle.
 pub fn cycle(cpu: &mut CPU) {
+    if cpu.core == Core::LR35902 {
+        cpu.lr.step(&mut cpu.gb_memory);
+        return;
+    }
+
+    // transfers clocked by a partner go on in STOP
+    cpu.mmu.step_serial(1);
+    // the save file is written once the game stops writing to it
+    if let Err(error) = cpu.mmu.save.step(1) {
+        eprintln!("{}", error);
+    }
+
+    // STOP freezes everything else until a keypad, serial or game pak interrupt
+    if cpu.mmu.power() == Power::Stopped && cpu.mmu.sleeping() {
+        return;
+    }
+
+    cpu.mmu.step_timers(1);
+
+    // DMA takes the bus, the CPU waits until the transfers are done
+    if cpu.mmu.dma_pending() {
+        cpu.dma_stall += cpu.mmu.run_dma();
+    }
+    if cpu.dma_stall > 0 {
+        cpu.dma_stall -= 1;
+        return;
+    }
+
+    // HALT only stops the CPU, until an interrupt is requested
+    if cpu.mmu.sleeping() {
+        return;
+    }
+
+    if cpu.execution_queue.is_empty() {
+        interrupt(cpu);
+    }
+
     execute(cpu);
     if cpu.execution_queue.is_empty() {
+        interrupt(cpu);
+
+        // the fetched instruction starts a compiled block, which runs in place of decoding it
+        #[cfg(feature = "jit")]
+        {
+            if jit::run(cpu) {
+                cpu.fetched_instruction = fetch(cpu);
+                return;
+            }
+        }
+
         let queue = decode(cpu);
         cpu.execution_queue = queue;
         cpu.fetched_instruction = fetch(cpu);
     }
 }
 
+/// Takes the IRQ exception if the interrupt controller asserts it and CPSR.I is clear.
+/// Only called between instructions, while the fetched one has not started yet.
+pub(crate) fn interrupt(cpu: &mut CPU) {
+    if cpu.arm.cpsr.disable_irq || !cpu.mmu.irq_line() {
+        return;
+    }
+
+    // the fetched instruction is thrown away and runs after the handler returns with
+    // SUBS PC, LR, #4
+    let size = if is_thumb_mode(cpu) { 2 } else { 4 };
+    let next = cpu.arm.load_register(constants::registers::PROGRAM_COUNTER) - size;
+    cpu.arm.enter_exception(
+        ProcessorMode::IRQ,
+        constants::exception_vectors::IRQ,
+        next + 4,
+    );
+    cpu.fetched_instruction = fetch(cpu);
+}
+
 /// Check if a function is in thumb mode
 #[inline]
 fn is_thumb_mode(cpu: &CPU) -> bool {
@@ -69,26 +199,35 @@ fn is_thumb_mode(cpu: &CPU) -> bool {
 }
 
 /// Get next instruction.
-fn fetch(cpu: &mut CPU) -> InstructionType {
+pub(crate) fn fetch(cpu: &mut CPU) -> InstructionType {
     let index = constants::registers::PROGRAM_COUNTER;
     let program_counter = cpu.arm.load_register(index) as usize;
     if is_thumb_mode(cpu) {
         // fetches 16-bit half-word
         cpu.arm
             .store_register(index, cpu.arm.clone().load_register(index) + 2);
-        InstructionType::Thumb(
-            ((cpu.rom[program_counter] as u16) << 8) | (cpu.rom[program_counter + 1] as u16),
-        )
+        let opcode = read_instruction(cpu, program_counter, true);
+        cpu.mmu.set_prefetched(opcode, true);
+        InstructionType::Thumb(opcode as u16)
     } else {
         // fetches 32-bit word
         cpu.arm
             .store_register(index, cpu.arm.clone().load_register(index) + 4);
-        InstructionType::ARM(arm::ARMInstruction::new_fetched(
-            ((cpu.rom[program_counter] as u32) << 24)
-                | ((cpu.rom[program_counter + 1] as u32) << 16)
-                | ((cpu.rom[program_counter + 2] as u32) << 8)
-                | (cpu.rom[program_counter + 3] as u32),
-        ))
+        let opcode = read_instruction(cpu, program_counter, false);
+        cpu.mmu.set_prefetched(opcode, false);
+        InstructionType::ARM(arm::ARMInstruction::new_fetched(opcode))
+    }
+}
+
+/// Reads the raw instruction at addr without touching the pipeline.
+pub(crate) fn read_instruction(cpu: &CPU, addr: usize, thumb: bool) -> u32 {
+    if thumb {
+        ((cpu.rom[addr] as u32) << 8) | (cpu.rom[addr + 1] as u32)
+    } else {
+        ((cpu.rom[addr] as u32) << 24)
+            | ((cpu.rom[addr + 1] as u32) << 16)
+            | ((cpu.rom[addr + 2] as u32) << 8)
+            | (cpu.rom[addr + 3] as u32)
     }
 }
 
@@ -108,3 +108,17 @@ impl Default for ProcessorMode {
         Self::User
     }
 }
+
+/// Processor core the CPU runs on
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Core {
+    ARM7TDMI,
+    LR35902,
+}
+
+impl Default for Core {
+    #[inline]
+    fn default() -> Self {
+        Self::ARM7TDMI
+    }
+}
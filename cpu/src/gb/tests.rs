@@ -0,0 +1,151 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    /// Runs a program from address 0 until it executes HALT
+    fn run_program(program: &[u8]) -> (LR35902, FlatMemory) {
+        let mut core = LR35902::default();
+        core.registers.pc = 0;
+        let mut memory = FlatMemory::with_program(program);
+        while !core.halted {
+            core.step(&mut memory);
+        }
+        (core, memory)
+    }
+
+    #[test]
+    fn test_gb_register_pairs() {
+        let mut registers = Registers::default();
+        assert_eq!(registers.af(), 0x01B0);
+        assert_eq!(registers.hl(), 0x014D);
+
+        registers.set_af(0x12FF);
+        assert_eq!(registers.af(), 0x12F0);
+        registers.set_de(0xBEEF);
+        assert_eq!((registers.d, registers.e), (0xBE, 0xEF));
+    }
+
+    #[test]
+    fn test_gb_alu_flags() {
+        let (core, _) = run_program(&[
+            0x3E, 0x0F, // ld a, 0x0f
+            0xC6, 0x01, // add a, 1
+            0x76, // halt
+        ]);
+        assert_eq!(core.registers.a, 0x10);
+        assert_eq!(core.registers.f, gb_flags::HALF_CARRY);
+
+        let (core, _) = run_program(&[
+            0x3E, 0x10, // ld a, 0x10
+            0xD6, 0x20, // sub a, 0x20
+            0x76, // halt
+        ]);
+        assert_eq!(core.registers.a, 0xF0);
+        assert_eq!(core.registers.f, gb_flags::SUBTRACT | gb_flags::CARRY);
+    }
+
+    #[test]
+    fn test_gb_daa() {
+        let (core, _) = run_program(&[
+            0x3E, 0x45, // ld a, 0x45
+            0xC6, 0x38, // add a, 0x38
+            0x27, // daa
+            0x76, // halt
+        ]);
+        assert_eq!(core.registers.a, 0x83);
+        assert!(!core.registers.flag(gb_flags::CARRY));
+    }
+
+    #[test]
+    fn test_gb_cb_ops() {
+        let (core, _) = run_program(&[
+            0x06, 0x81, // ld b, 0x81
+            0xCB, 0x00, // rlc b
+            0xCB, 0x37, // swap a
+            0xCB, 0x78, // bit 7, b
+            0x76, // halt
+        ]);
+        assert_eq!(core.registers.b, 0x03);
+        assert_eq!(core.registers.a, 0x10);
+        assert!(core.registers.flag(gb_flags::ZERO));
+    }
+
+    #[test]
+    fn test_gb_call_ret_stack() {
+        let (core, memory) = run_program(&[
+            0x31, 0x00, 0xD0, // ld sp, 0xd000
+            0xCD, 0x08, 0x00, // call 0x0008
+            0x76, // halt
+            0x00, // nop
+            0x3E, 0x42, // ld a, 0x42
+            0xC9, // ret
+        ]);
+        assert_eq!(core.registers.a, 0x42);
+        assert_eq!(core.registers.sp, 0xD000);
+        assert_eq!(core.registers.pc, 0x0007);
+        // return address left on the stack
+        assert_eq!(memory.memory[0xCFFE], 0x06);
+    }
+
+    #[test]
+    fn test_gb_instruction_timing() {
+        let mut core = LR35902::default();
+        core.registers.pc = 0;
+        let mut memory = FlatMemory::with_program(&[
+            0x00, // nop
+            0xC3, 0x00, 0x10, // jp 0x1000
+        ]);
+        assert_eq!(core.step(&mut memory), 4);
+        assert_eq!(core.step(&mut memory), 16);
+        assert_eq!(core.registers.pc, 0x1000);
+    }
+
+    #[test]
+    fn test_gb_interrupt_dispatch() {
+        let mut core = LR35902::default();
+        core.registers.pc = 0x0100;
+        let mut memory = FlatMemory::default();
+        memory.memory[0x0100] = 0xFB; // ei
+        memory.memory[0x0101] = 0x00; // nop
+        memory.write8(gb_interrupts::IE, gb_interrupts::TIMER);
+        memory.write8(gb_interrupts::IF, gb_interrupts::TIMER);
+
+        core.step(&mut memory);
+        // EI only takes effect after the next instruction
+        core.step(&mut memory);
+        assert_eq!(core.registers.pc, 0x0102);
+
+        assert_eq!(core.step(&mut memory), 20);
+        assert_eq!(core.registers.pc, 0x0050);
+        assert!(!core.ime);
+        assert_eq!(memory.read8(gb_interrupts::IF) & 0x1F, 0);
+    }
+
+    #[test]
+    fn test_gb_halt_wakes_without_ime() {
+        let mut core = LR35902::default();
+        core.registers.pc = 0;
+        let mut memory = FlatMemory::with_program(&[0x76, 0x04]); // halt, inc b
+        memory.write8(gb_interrupts::IE, gb_interrupts::VBLANK);
+
+        core.step(&mut memory);
+        core.step(&mut memory);
+        assert!(core.halted);
+
+        memory.write8(gb_interrupts::IF, gb_interrupts::VBLANK);
+        core.step(&mut memory);
+        assert!(!core.halted);
+        assert_eq!(core.registers.b, 1);
+    }
+
+    #[test]
+    fn test_gb_illegal_opcode_locks() {
+        let mut core = LR35902::default();
+        core.registers.pc = 0;
+        let mut memory = FlatMemory::with_program(&[0xD3, 0x04]);
+        core.step(&mut memory);
+        assert!(core.locked);
+        core.step(&mut memory);
+        assert_eq!(core.registers.pc, 1);
+    }
+}
@@ -0,0 +1,675 @@
+use crate::constants::{cond_arm, dp_opcodes::*, registers};
+use crate::cpu::{self, CPU};
+use crate::enums::ShiftType;
+
+use std::collections::HashMap;
+
+mod emitter;
+use emitter::*;
+
+// The jit translates straight runs of ALU instructions into native code. Anything it
+// does not understand (memory access, branches, conditional execution, PC writes, ...)
+// ends the block and is left to the interpreter, which also runs blocks until they get hot.
+
+/// Longest run of instructions compiled into a single block
+const MAX_BLOCK_LENGTH: usize = 32;
+/// Size of the executable arena. It gets flushed when full.
+const CODE_MEMORY_SIZE: usize = 0x10_0000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mode {
+    /// Always interpret
+    Disabled,
+    /// Run compiled blocks once they are hot
+    Enabled,
+    /// Run compiled blocks and check each one against the interpreter on a copy of the CPU
+    Lockstep,
+}
+
+/// Block cache and code generator
+pub struct JIT {
+    pub mode: Mode,
+    /// Number of times a block must be reached before it gets compiled
+    pub threshold: u32,
+
+    memory: Option<ExecutableMemory>,
+    blocks: HashMap<u32, Block>,
+    heat: HashMap<u32, u32>,
+}
+
+/// A compiled run of instructions.
+/// A block with no code marks an address the jit could not translate.
+struct Block {
+    entry: Option<usize>,
+    instructions: u32,
+    start: u32,
+    end: u32,
+    // the instructions the block was translated from
+    source: Vec<u8>,
+    // write generation of every page the block was read from
+    generations: Vec<u32>,
+}
+
+impl Default for JIT {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Enabled,
+            threshold: 32,
+            memory: None,
+            blocks: HashMap::new(),
+            heat: HashMap::new(),
+        }
+    }
+}
+
+impl Clone for JIT {
+    /// Native code is not shared, the clone starts with an empty cache
+    fn clone(&self) -> Self {
+        Self {
+            mode: self.mode,
+            threshold: self.threshold,
+            ..Default::default()
+        }
+    }
+}
+
+impl JIT {
+    /// Drop every compiled block
+    pub fn flush(&mut self) {
+        self.blocks.clear();
+        self.heat.clear();
+        if let Some(memory) = &mut self.memory {
+            memory.clear();
+        }
+    }
+
+    /// Number of blocks currently in the cache, including untranslatable markers
+    pub fn cached_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn compile(&mut self, ops: &[Op]) -> Option<usize> {
+        if self.memory.is_none() {
+            match ExecutableMemory::new(CODE_MEMORY_SIZE) {
+                Ok(memory) => self.memory = Some(memory),
+                Err(error) => {
+                    eprintln!("{}, falling back to the interpreter", error);
+                    self.mode = Mode::Disabled;
+                    return None;
+                }
+            }
+        }
+
+        let mut emitter = Emitter::default();
+        for op in ops {
+            emit(&mut emitter, op);
+        }
+        emitter.ret();
+
+        let memory = self.memory.as_mut().unwrap();
+        if let Some(entry) = memory.push(&emitter.code) {
+            return Some(entry);
+        }
+
+        // out of space, start over
+        self.blocks.clear();
+        let memory = self.memory.as_mut().unwrap();
+        memory.clear();
+        memory.push(&emitter.code)
+    }
+}
+
+/// Runs the compiled block at the current instruction, if there is one.
+/// Returns false when the interpreter should handle the instruction instead.
+pub fn run(cpu: &mut CPU) -> bool {
+    if cpu.jit.mode == Mode::Disabled {
+        return false;
+    }
+
+    let thumb = cpu.arm.cpsr.thumb_mode;
+    let size = if thumb { 2 } else { 4 };
+    // the instruction to execute was already fetched
+    let pc = (cpu.arm.load_register(registers::PROGRAM_COUNTER) as u32).wrapping_sub(size);
+    let key = pc | thumb as u32;
+
+    if let Some(block) = cpu.jit.blocks.get(&key) {
+        if is_stale(cpu, block) {
+            cpu.jit.blocks.remove(&key);
+        } else {
+            return match block.entry {
+                Some(entry) => {
+                    let instructions = block.instructions;
+                    execute(cpu, entry, pc, instructions, size);
+                    true
+                }
+                None => false,
+            };
+        }
+    }
+
+    let heat = cpu.jit.heat.entry(key).or_insert(0);
+    *heat += 1;
+    if *heat < cpu.jit.threshold {
+        return false;
+    }
+    cpu.jit.heat.remove(&key);
+
+    let ops = translate(cpu, pc, thumb);
+    let end = pc + ops.len().max(1) as u32 * size;
+    let entry = if ops.is_empty() {
+        None
+    } else {
+        cpu.jit.compile(&ops)
+    };
+
+    let generations = (pc >> memory::WRITE_TRACKING_PAGE_SHIFT
+        ..=(end - 1) >> memory::WRITE_TRACKING_PAGE_SHIFT)
+        .map(|page| {
+            cpu.mmu
+                .page_generation(page << memory::WRITE_TRACKING_PAGE_SHIFT)
+        })
+        .collect();
+    let source = source(cpu, pc, end).to_vec();
+
+    cpu.jit.blocks.insert(
+        key,
+        Block {
+            entry,
+            instructions: ops.len() as u32,
+            start: pc,
+            end,
+            source,
+            generations,
+        },
+    );
+
+    match entry {
+        Some(entry) => {
+            execute(cpu, entry, pc, ops.len() as u32, size);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Instructions between start and end, from the memory they are fetched from
+fn source(cpu: &CPU, start: u32, end: u32) -> &[u8] {
+    cpu.rom.get(start as usize..end as usize).unwrap_or(&[])
+}
+
+/// A block is stale once the instructions it was compiled from have changed,
+/// or any page it was compiled from has been written to through the MMU
+fn is_stale(cpu: &CPU, block: &Block) -> bool {
+    if source(cpu, block.start, block.end) != block.source.as_slice() {
+        return true;
+    }
+
+    let first_page = block.start >> memory::WRITE_TRACKING_PAGE_SHIFT;
+    block
+        .generations
+        .iter()
+        .enumerate()
+        .any(|(i, &generation)| {
+            let page = first_page + i as u32;
+            cpu.mmu
+                .page_generation(page << memory::WRITE_TRACKING_PAGE_SHIFT)
+                != generation
+        })
+}
+
+fn execute(cpu: &mut CPU, entry: usize, pc: u32, instructions: u32, size: u32) {
+    let mut reference = if cpu.jit.mode == Mode::Lockstep {
+        let mut reference = cpu.clone();
+        reference.jit.mode = Mode::Disabled;
+        interpret(&mut reference, instructions);
+        Some(reference)
+    } else {
+        None
+    };
+
+    let mut state = capture(cpu);
+    unsafe {
+        let block = cpu.jit.memory.as_ref().unwrap().function(entry);
+        block(&mut state);
+    }
+    restore(cpu, &state);
+    cpu.arm.store_register(
+        registers::PROGRAM_COUNTER,
+        (pc + instructions * size) as i32,
+    );
+
+    if let Some(reference) = &mut reference {
+        compare(cpu, reference, pc);
+    }
+}
+
+fn capture(cpu: &mut CPU) -> GuestState {
+    let mut state = GuestState::default();
+    for r in 0..16 {
+        state.registers[r] = cpu.arm.load_register(r);
+    }
+    state.flags = [
+        cpu.arm.cpsr.negative as u8,
+        cpu.arm.cpsr.zero as u8,
+        cpu.arm.cpsr.carry as u8,
+        cpu.arm.cpsr.overflow as u8,
+    ];
+    state
+}
+
+fn restore(cpu: &mut CPU, state: &GuestState) {
+    // the program counter is never written by compiled code
+    for r in 0..registers::PROGRAM_COUNTER {
+        cpu.arm.store_register(r, state.registers[r]);
+    }
+    cpu.arm.cpsr.negative = state.flags[0] != 0;
+    cpu.arm.cpsr.zero = state.flags[1] != 0;
+    cpu.arm.cpsr.carry = state.flags[2] != 0;
+    cpu.arm.cpsr.overflow = state.flags[3] != 0;
+}
+
+/// Step the interpreter through a number of whole instructions
+fn interpret(cpu: &mut CPU, instructions: u32) {
+    // an instruction is done when the one after it gets decoded
+    let mut decoded = 0;
+    while decoded <= instructions {
+        if cpu.execution_queue.len() <= 1 {
+            decoded += 1;
+        }
+        cpu::cycle(cpu);
+    }
+}
+
+/// Checks the registers, CPSR and memory left by a block against the interpreter's.
+/// The program counter is skipped as the interpreter has already moved on to the next fetch.
+fn compare(cpu: &mut CPU, reference: &mut CPU, pc: u32) {
+    let jit = capture(cpu);
+    let interpreter = capture(reference);
+
+    let mut differences = Vec::new();
+    for r in 0..registers::PROGRAM_COUNTER {
+        if jit.registers[r] != interpreter.registers[r] {
+            differences.push(format!(
+                "r{}: jit {:#x}, interpreter {:#x}",
+                r, jit.registers[r], interpreter.registers[r]
+            ));
+        }
+    }
+    for (i, flag) in ["N", "Z", "C", "V"].iter().enumerate() {
+        if jit.flags[i] != interpreter.flags[i] {
+            differences.push(format!(
+                "{}: jit {}, interpreter {}",
+                flag, jit.flags[i], interpreter.flags[i]
+            ));
+        }
+    }
+    let (ours, theirs) = (&cpu.arm.cpsr, &reference.arm.cpsr);
+    if ours.thumb_mode != theirs.thumb_mode
+        || ours.disable_irq != theirs.disable_irq
+        || ours.disable_fiq != theirs.disable_fiq
+        || ours.mode != theirs.mode
+    {
+        differences.push(format!(
+            "CPSR: jit T={} I={} F={} {:?}, interpreter T={} I={} F={} {:?}",
+            ours.thumb_mode as u8,
+            ours.disable_irq as u8,
+            ours.disable_fiq as u8,
+            ours.mode,
+            theirs.thumb_mode as u8,
+            theirs.disable_irq as u8,
+            theirs.disable_fiq as u8,
+            theirs.mode
+        ));
+    }
+    if let Some(addr) = cpu.mmu.first_difference(&reference.mmu) {
+        differences.push(format!(
+            "[{:#x}]: jit {:#x}, interpreter {:#x}",
+            addr,
+            cpu.mmu.load8(addr),
+            reference.mmu.load8(addr)
+        ));
+    }
+
+    if !differences.is_empty() {
+        panic!(
+            "jit lockstep mismatch in block at {:#x}:\n{}",
+            pc,
+            differences.join("\n")
+        );
+    }
+}
+
+/// Second operand of an ALU operation
+#[derive(Debug, PartialEq, Clone)]
+enum Operand {
+    /// immediate value and the shifter carry it produces, if any
+    Imm(u32, Option<bool>),
+    /// register shifted by an immediate amount. 32 is used for LSR/ASR #32
+    Reg(u8, ShiftType, u8),
+}
+
+/// A translated ALU instruction, in ARM terms
+#[derive(Debug, PartialEq, Clone)]
+struct Op {
+    opcode: u8,
+    rd: u8,
+    rn: u8,
+    operand: Operand,
+    set_cond: bool,
+}
+
+/// Collects every instruction starting at pc up to the first one the jit can't handle
+fn translate(cpu: &CPU, pc: u32, thumb: bool) -> Vec<Op> {
+    let size = if thumb { 2 } else { 4 };
+    let mut ops = Vec::new();
+
+    while ops.len() < MAX_BLOCK_LENGTH {
+        let addr = (pc + ops.len() as u32 * size) as usize;
+        if addr + size as usize > cpu.rom.len() {
+            break;
+        }
+
+        let instruction = cpu::read_instruction(cpu, addr, thumb);
+        let op = if thumb {
+            translate_thumb(instruction as u16)
+        } else {
+            translate_arm(instruction)
+        };
+
+        match op {
+            Some(op) => ops.push(op),
+            None => break,
+        }
+    }
+
+    ops
+}
+
+#[inline]
+fn is_logical(opcode: u8) -> bool {
+    matches!(opcode, AND | EOR | TST | TEQ | ORR | MOV | BIC | MVN)
+}
+
+#[inline]
+fn is_test(opcode: u8) -> bool {
+    matches!(opcode, TST | TEQ | CMP | CMN)
+}
+
+fn translate_arm(instruction: u32) -> Option<Op> {
+    if (instruction >> 28) as u8 != cond_arm::AL {
+        return None;
+    }
+
+    // only data processing
+    if (instruction >> 26) & 0b11 != 0 {
+        return None;
+    }
+
+    let imm = instruction & (1 << 25) != 0;
+    // register specified shifts share their encoding space with multiplies and halfword transfers
+    if !imm && instruction & (1 << 4) != 0 {
+        return None;
+    }
+
+    let opcode = ((instruction >> 21) & 0xF) as u8;
+    let set_cond = instruction & (1 << 20) != 0;
+    // tests without the S bit are PSR transfers
+    if is_test(opcode) && !set_cond {
+        return None;
+    }
+
+    let rn = ((instruction >> 16) & 0xF) as u8;
+    let rd = ((instruction >> 12) & 0xF) as u8;
+    let uses_rn = opcode != MOV && opcode != MVN;
+    if (!is_test(opcode) && rd as usize == registers::PROGRAM_COUNTER)
+        || (uses_rn && rn as usize == registers::PROGRAM_COUNTER)
+    {
+        return None;
+    }
+
+    let operand = if imm {
+        let rotate = ((instruction >> 8) & 0xF) * 2;
+        let value = (instruction & 0xFF).rotate_right(rotate);
+        let carry = if rotate != 0 {
+            Some(value >> 31 != 0)
+        } else {
+            None
+        };
+        Operand::Imm(value, carry)
+    } else {
+        let rm = (instruction & 0xF) as u8;
+        if rm as usize == registers::PROGRAM_COUNTER {
+            return None;
+        }
+
+        let amount = ((instruction >> 7) & 0x1F) as u8;
+        let shift_type = match (instruction >> 5) & 0b11 {
+            0 => ShiftType::LSL,
+            1 => ShiftType::LSR,
+            2 => ShiftType::ASR,
+            _ => ShiftType::ROR,
+        };
+
+        match (&shift_type, amount) {
+            // RRX
+            (ShiftType::ROR, 0) => return None,
+            (ShiftType::LSR, 0) | (ShiftType::ASR, 0) if set_cond => return None,
+            (ShiftType::LSR, 0) | (ShiftType::ASR, 0) => Operand::Reg(rm, shift_type, 32),
+            _ => Operand::Reg(rm, shift_type, amount),
+        }
+    };
+
+    Some(Op {
+        opcode,
+        rd,
+        rn,
+        operand,
+        set_cond,
+    })
+}
+
+fn translate_thumb(instruction: u16) -> Option<Op> {
+    let low = (instruction & 0b111) as u8;
+    let mid = ((instruction >> 3) & 0b111) as u8;
+
+    // thumb 2: add/subtract
+    if instruction >> 11 == 0b00011 {
+        let value = ((instruction >> 6) & 0b111) as u8;
+        let operand = if instruction & (1 << 10) != 0 {
+            Operand::Imm(value as u32, None)
+        } else {
+            Operand::Reg(value, ShiftType::LSL, 0)
+        };
+        let opcode = if instruction & (1 << 9) != 0 {
+            SUB
+        } else {
+            ADD
+        };
+        return Some(Op {
+            opcode,
+            rd: low,
+            rn: mid,
+            operand,
+            set_cond: true,
+        });
+    }
+
+    // thumb 1: move shifted register
+    if instruction >> 13 == 0b000 {
+        let amount = ((instruction >> 6) & 0x1F) as u8;
+        let shift_type = match (instruction >> 11) & 0b11 {
+            0 => ShiftType::LSL,
+            1 => ShiftType::LSR,
+            _ => ShiftType::ASR,
+        };
+        // LSR/ASR #0 mean #32, which sets the carry
+        if shift_type != ShiftType::LSL && amount == 0 {
+            return None;
+        }
+        return Some(Op {
+            opcode: MOV,
+            rd: low,
+            rn: 0,
+            operand: Operand::Reg(mid, shift_type, amount),
+            set_cond: true,
+        });
+    }
+
+    // thumb 3: move/compare/add/subtract immediate
+    if instruction >> 13 == 0b001 {
+        let rd = ((instruction >> 8) & 0b111) as u8;
+        let opcode = match (instruction >> 11) & 0b11 {
+            0 => MOV,
+            1 => CMP,
+            2 => ADD,
+            _ => SUB,
+        };
+        return Some(Op {
+            opcode,
+            rd,
+            rn: rd,
+            operand: Operand::Imm((instruction & 0xFF) as u32, None),
+            set_cond: true,
+        });
+    }
+
+    // thumb 4: ALU operations
+    if instruction >> 10 == 0b010000 {
+        let register = Operand::Reg(mid, ShiftType::LSL, 0);
+        let (opcode, rn, operand) = match (instruction >> 6) & 0xF {
+            0x0 => (AND, low, register),
+            0x1 => (EOR, low, register),
+            0x5 => (ADC, low, register),
+            0x6 => (SBC, low, register),
+            0x8 => (TST, low, register),
+            // NEG is RSB rd, rs, #0
+            0x9 => (RSB, mid, Operand::Imm(0, None)),
+            0xA => (CMP, low, register),
+            0xB => (CMN, low, register),
+            0xC => (ORR, low, register),
+            0xE => (BIC, low, register),
+            0xF => (MVN, low, register),
+            // shifts by register and multiplies
+            _ => return None,
+        };
+        return Some(Op {
+            opcode,
+            rd: low,
+            rn,
+            operand,
+            set_cond: true,
+        });
+    }
+
+    None
+}
+
+fn emit(e: &mut Emitter, op: &Op) {
+    let logical_flags = op.set_cond && is_logical(op.opcode);
+
+    // second operand goes through the barrel shifter into eax
+    match &op.operand {
+        Operand::Imm(value, carry) => {
+            e.mov_eax_imm(*value);
+            if let (true, Some(carry)) = (logical_flags, carry) {
+                e.set_flag_imm(FLAG_C, *carry);
+            }
+        }
+        Operand::Reg(rm, shift_type, amount) => {
+            e.load(Scratch::Eax, *rm);
+            match (shift_type, *amount) {
+                (_, 0) => (),
+                (ShiftType::LSR, 32) => e.clear_eax(),
+                // fills the register with the sign bit
+                (ShiftType::ASR, 32) => e.shift_eax(&ShiftType::ASR, 31),
+                (shift_type, amount) => {
+                    e.shift_eax(shift_type, amount);
+                    if logical_flags {
+                        e.set_flag(Condition::Carry, FLAG_C);
+                    }
+                }
+            }
+        }
+    }
+
+    if op.opcode != MOV && op.opcode != MVN {
+        e.load(Scratch::Ecx, op.rn);
+    }
+
+    let result = match op.opcode {
+        AND | TST => {
+            e.and();
+            Scratch::Ecx
+        }
+        EOR | TEQ => {
+            e.xor();
+            Scratch::Ecx
+        }
+        ORR => {
+            e.or();
+            Scratch::Ecx
+        }
+        BIC => {
+            e.not_eax();
+            e.and();
+            Scratch::Ecx
+        }
+        ADD | CMN => {
+            e.add();
+            Scratch::Ecx
+        }
+        ADC => {
+            e.carry_in();
+            e.adc();
+            Scratch::Ecx
+        }
+        SUB | CMP => {
+            e.sub(false);
+            Scratch::Ecx
+        }
+        SBC => {
+            e.borrow_in();
+            e.sbb(false);
+            Scratch::Ecx
+        }
+        RSB => {
+            e.sub(true);
+            Scratch::Eax
+        }
+        RSC => {
+            e.borrow_in();
+            e.sbb(true);
+            Scratch::Eax
+        }
+        MOV => Scratch::Eax,
+        MVN => {
+            e.not_eax();
+            Scratch::Eax
+        }
+        _ => unreachable!(),
+    };
+
+    if op.set_cond {
+        if is_logical(op.opcode) {
+            e.test(result);
+            e.set_flag(Condition::Sign, FLAG_N);
+            e.set_flag(Condition::Zero, FLAG_Z);
+        } else {
+            e.set_flag(Condition::Sign, FLAG_N);
+            e.set_flag(Condition::Zero, FLAG_Z);
+            // ARM's carry is the inverse of x86's borrow
+            let carry = match op.opcode {
+                ADD | ADC | CMN => Condition::Carry,
+                _ => Condition::NotCarry,
+            };
+            e.set_flag(carry, FLAG_C);
+            e.set_flag(Condition::Overflow, FLAG_V);
+        }
+    }
+
+    if !is_test(op.opcode) {
+        e.store(op.rd, result);
+    }
+}
+
+pub mod tests;
@@ -0,0 +1,257 @@
+// Minimal x86-64 encoder, only the handful of instructions the jit needs live here.
+//
+// Register usage of the generated code:
+//  - rdi points at the GuestState
+//  - eax holds the second operand (the barrel shifter output)
+//  - ecx holds the first operand and most results
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+compile_error!("The jit feature requires an x86-64 Linux host");
+
+use crate::enums::ShiftType;
+use std::ffi::c_void as void;
+
+/// Offsets of the condition flags inside GuestState
+pub const FLAG_N: u8 = 64;
+pub const FLAG_Z: u8 = 65;
+pub const FLAG_C: u8 = 66;
+pub const FLAG_V: u8 = 67;
+
+/// Registers and flags as seen by the generated code
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct GuestState {
+    pub registers: [i32; 16],
+    // N, Z, C, V stored as 0 or 1
+    pub flags: [u8; 4],
+}
+
+/// x86 condition codes used with SETcc
+#[derive(Clone, Copy)]
+pub enum Condition {
+    Overflow = 0x0,
+    Carry = 0x2,
+    NotCarry = 0x3,
+    Zero = 0x4,
+    Sign = 0x8,
+}
+
+/// The two scratch registers the jit works with
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scratch {
+    Eax = 0,
+    Ecx = 1,
+}
+
+#[derive(Default)]
+pub struct Emitter {
+    pub code: Vec<u8>,
+}
+
+impl Emitter {
+    /// mov scratch, [rdi + reg * 4]
+    pub fn load(&mut self, dest: Scratch, reg: u8) {
+        self.code
+            .extend_from_slice(&[0x8B, 0x47 | (dest as u8) << 3, reg * 4]);
+    }
+
+    /// mov [rdi + reg * 4], scratch
+    pub fn store(&mut self, reg: u8, source: Scratch) {
+        self.code
+            .extend_from_slice(&[0x89, 0x47 | (source as u8) << 3, reg * 4]);
+    }
+
+    /// mov eax, imm32
+    pub fn mov_eax_imm(&mut self, value: u32) {
+        self.code.push(0xB8);
+        self.code.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// xor eax, eax
+    pub fn clear_eax(&mut self) {
+        self.code.extend_from_slice(&[0x31, 0xC0]);
+    }
+
+    /// Shift eax by an immediate amount between 1 and 31.
+    /// Like the ARM barrel shifter this leaves the last bit shifted out in the carry flag.
+    pub fn shift_eax(&mut self, shift_type: &ShiftType, amount: u8) {
+        let modrm = match shift_type {
+            ShiftType::LSL => 0xE0,
+            ShiftType::LSR => 0xE8,
+            ShiftType::ASR => 0xF8,
+            ShiftType::ROR => 0xC8,
+        };
+        self.code.extend_from_slice(&[0xC1, modrm, amount]);
+    }
+
+    /// not eax
+    pub fn not_eax(&mut self) {
+        self.code.extend_from_slice(&[0xF7, 0xD0]);
+    }
+
+    /// and ecx, eax
+    pub fn and(&mut self) {
+        self.code.extend_from_slice(&[0x21, 0xC1]);
+    }
+
+    /// xor ecx, eax
+    pub fn xor(&mut self) {
+        self.code.extend_from_slice(&[0x31, 0xC1]);
+    }
+
+    /// or ecx, eax
+    pub fn or(&mut self) {
+        self.code.extend_from_slice(&[0x09, 0xC1]);
+    }
+
+    /// add ecx, eax
+    pub fn add(&mut self) {
+        self.code.extend_from_slice(&[0x01, 0xC1]);
+    }
+
+    /// adc ecx, eax
+    pub fn adc(&mut self) {
+        self.code.extend_from_slice(&[0x11, 0xC1]);
+    }
+
+    /// sub ecx, eax or, reversed, sub eax, ecx
+    pub fn sub(&mut self, reversed: bool) {
+        self.code
+            .extend_from_slice(&[0x29, if reversed { 0xC8 } else { 0xC1 }]);
+    }
+
+    /// sbb ecx, eax or, reversed, sbb eax, ecx
+    pub fn sbb(&mut self, reversed: bool) {
+        self.code
+            .extend_from_slice(&[0x19, if reversed { 0xC8 } else { 0xC1 }]);
+    }
+
+    /// test scratch, scratch
+    pub fn test(&mut self, reg: Scratch) {
+        let reg = reg as u8;
+        self.code.extend_from_slice(&[0x85, 0xC0 | reg << 3 | reg]);
+    }
+
+    /// Loads the guest carry into the host carry: bt dword [rdi + FLAG_N], 16
+    pub fn carry_in(&mut self) {
+        self.code
+            .extend_from_slice(&[0x0F, 0xBA, 0x67, FLAG_N, (FLAG_C - FLAG_N) * 8]);
+    }
+
+    /// Loads the inverted guest carry (the borrow) into the host carry: cmp byte [rdi + FLAG_C], 1
+    pub fn borrow_in(&mut self) {
+        self.code.extend_from_slice(&[0x80, 0x7F, FLAG_C, 1]);
+    }
+
+    /// setcc byte [rdi + flag]
+    pub fn set_flag(&mut self, condition: Condition, flag: u8) {
+        self.code
+            .extend_from_slice(&[0x0F, 0x90 | condition as u8, 0x47, flag]);
+    }
+
+    /// mov byte [rdi + flag], value
+    pub fn set_flag_imm(&mut self, flag: u8, value: bool) {
+        self.code
+            .extend_from_slice(&[0xC6, 0x47, flag, value as u8]);
+    }
+
+    /// ret
+    pub fn ret(&mut self) {
+        self.code.push(0xC3);
+    }
+}
+
+/// A compiled block, called with a pointer to the guest state
+pub type BlockFn = unsafe extern "sysv64" fn(*mut GuestState);
+
+/// Executable memory arena holding every compiled block.
+/// Pages are kept read+execute and only made writable while a block is copied in.
+pub struct ExecutableMemory {
+    ptr: *mut u8,
+    len: usize,
+    used: usize,
+}
+
+impl ExecutableMemory {
+    pub fn new(len: usize) -> Result<Self, String> {
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null(),
+                len,
+                PROT_READ | PROT_EXEC,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+
+        if ptr as isize == -1 {
+            return Err("Unable to map executable memory for the jit".to_string());
+        }
+
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+            used: 0,
+        })
+    }
+
+    /// Copies code into the arena and returns its offset, or None when the arena is full
+    pub fn push(&mut self, code: &[u8]) -> Option<usize> {
+        if self.used + code.len() > self.len {
+            return None;
+        }
+
+        let offset = self.used;
+        unsafe {
+            if mprotect(self.ptr as *mut void, self.len, PROT_READ | PROT_WRITE) != 0 {
+                return None;
+            }
+            std::ptr::copy_nonoverlapping(code.as_ptr(), self.ptr.add(offset), code.len());
+            mprotect(self.ptr as *mut void, self.len, PROT_READ | PROT_EXEC);
+        }
+
+        // keep blocks 16 byte aligned
+        self.used = (offset + code.len() + 15) & !15;
+        Some(offset)
+    }
+
+    /// Throws away every block in the arena
+    pub fn clear(&mut self) {
+        self.used = 0;
+    }
+
+    /// Get the block starting at offset.
+    /// The offset must come from push on this arena, and the arena must not have been cleared since.
+    pub unsafe fn function(&self, offset: usize) -> BlockFn {
+        std::mem::transmute::<*const u8, BlockFn>(self.ptr.add(offset))
+    }
+}
+
+impl Drop for ExecutableMemory {
+    fn drop(&mut self) {
+        unsafe { munmap(self.ptr as *mut void, self.len) };
+    }
+}
+
+// Syscall definitions
+extern "C" {
+    fn mmap(
+        addr: *const void,
+        length: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: isize,
+    ) -> *mut void;
+    fn mprotect(addr: *mut void, length: usize, prot: i32) -> i32;
+    fn munmap(addr: *mut void, length: usize) -> i32;
+}
+
+// mmap constants
+const PROT_READ: i32 = 0x1;
+const PROT_WRITE: i32 = 0x2;
+const PROT_EXEC: i32 = 0x4;
+
+const MAP_PRIVATE: i32 = 0x02;
+const MAP_ANONYMOUS: i32 = 0x20;
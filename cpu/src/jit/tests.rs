@@ -0,0 +1,188 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::enums::InstructionType;
+
+    /// Builds a CPU that will run the given instructions from address 0 as soon as they are reached
+    fn cpu_with_code(code: &[u32], thumb: bool) -> CPU {
+        let mut cpu = CPU::default();
+        for &instruction in code {
+            if thumb {
+                cpu.rom
+                    .extend_from_slice(&[(instruction >> 8) as u8, instruction as u8]);
+            } else {
+                cpu.rom.extend_from_slice(&instruction.to_be_bytes());
+            }
+        }
+
+        cpu.arm.cpsr.thumb_mode = thumb;
+        cpu.arm
+            .store_register(registers::PROGRAM_COUNTER, if thumb { 2 } else { 4 });
+        cpu.jit.mode = Mode::Enabled;
+        cpu.jit.threshold = 1;
+        cpu
+    }
+
+    #[test]
+    fn test_jit_arm_alu() {
+        let mut cpu = cpu_with_code(
+            &[
+                0xE3A0_0005, // mov r0, #5
+                0xE280_1003, // add r1, r0, #3
+                0xE041_2000, // sub r2, r1, r0
+                0xE1A0_3101, // mov r3, r1, lsl #2
+                0xE3E0_40FF, // mvn r4, #0xFF
+                0xEAFF_FFFE, // b . (not compiled)
+            ],
+            false,
+        );
+
+        assert!(run(&mut cpu));
+        assert_eq!(cpu.arm.load_register(0), 5);
+        assert_eq!(cpu.arm.load_register(1), 8);
+        assert_eq!(cpu.arm.load_register(2), 3);
+        assert_eq!(cpu.arm.load_register(3), 32);
+        assert_eq!(cpu.arm.load_register(4), !0xFF);
+        assert_eq!(cpu.arm.load_register(registers::PROGRAM_COUNTER), 20);
+
+        // the branch is left to the interpreter, once it has been fetched
+        cpu.arm.store_register(registers::PROGRAM_COUNTER, 24);
+        assert!(!run(&mut cpu));
+    }
+
+    #[test]
+    fn test_jit_arm_flags() {
+        let mut cpu = cpu_with_code(
+            &[
+                0xE3B0_0000, // movs r0, #0
+                0xE250_1001, // subs r1, r0, #1
+                0xE291_2001, // adds r2, r1, #1
+                0xEAFF_FFFE, // b .
+            ],
+            false,
+        );
+
+        assert!(run(&mut cpu));
+        assert_eq!(cpu.arm.load_register(1), -1);
+        assert_eq!(cpu.arm.load_register(2), 0);
+        assert!(!cpu.arm.cpsr.negative);
+        assert!(cpu.arm.cpsr.zero);
+        assert!(cpu.arm.cpsr.carry);
+        assert!(!cpu.arm.cpsr.overflow);
+    }
+
+    #[test]
+    fn test_jit_thumb_alu() {
+        let mut cpu = cpu_with_code(
+            &[
+                0x20C8, // mov r0, #200
+                0x0081, // lsl r1, r0, #2
+                0x1A0A, // sub r2, r1, r0
+                0x4252, // neg r2, r2
+                0xE7FE, // b .
+            ],
+            true,
+        );
+
+        assert!(run(&mut cpu));
+        assert_eq!(cpu.arm.load_register(0), 200);
+        assert_eq!(cpu.arm.load_register(1), 800);
+        assert_eq!(cpu.arm.load_register(2), -600);
+        assert!(cpu.arm.cpsr.negative);
+        assert_eq!(cpu.arm.load_register(registers::PROGRAM_COUNTER), 8);
+    }
+
+    #[test]
+    fn test_jit_invalidation() {
+        let mut cpu = cpu_with_code(&[0xE3A0_0005, 0xEAFF_FFFE], false);
+        assert!(run(&mut cpu));
+        assert_eq!(cpu.arm.load_register(0), 5);
+        assert_eq!(cpu.jit.cached_blocks(), 1);
+
+        // mov r0, #7
+        cpu.rom[3] = 7;
+
+        cpu.arm.store_register(registers::PROGRAM_COUNTER, 4);
+        assert!(run(&mut cpu));
+        assert_eq!(cpu.arm.load_register(0), 7);
+    }
+
+    #[test]
+    fn test_jit_store_invalidation() {
+        let mut cpu = cpu_with_code(&[0xE3A0_0005, 0xEAFF_FFFE], false);
+        assert!(run(&mut cpu));
+        assert_eq!(cpu.jit.cached_blocks(), 1);
+
+        // a store into the block drops it, it has to get hot again to be recompiled
+        cpu.jit.threshold = 2;
+        cpu.mmu.store32(0, 0xE3A0_0005);
+        cpu.arm.store_register(registers::PROGRAM_COUNTER, 4);
+        assert!(!run(&mut cpu));
+        assert_eq!(cpu.jit.cached_blocks(), 0);
+        assert!(run(&mut cpu));
+        assert_eq!(cpu.jit.cached_blocks(), 1);
+        assert_eq!(cpu.arm.load_register(0), 5);
+    }
+
+    #[test]
+    fn test_jit_lockstep() {
+        let mut cpu = cpu_with_code(
+            &[
+                0x0000, // lsl r0, r0, #0
+                0xE7FE, // b .
+            ],
+            true,
+        );
+        // room for the interpreter to fetch past the block
+        cpu.rom.resize(0x40, 0);
+        cpu.arm.cpsr.zero = true;
+        cpu.jit.mode = Mode::Lockstep;
+
+        assert!(run(&mut cpu));
+        assert_eq!(cpu.arm.load_register(0), 0);
+        assert!(cpu.arm.cpsr.zero);
+    }
+
+    #[test]
+    #[should_panic(expected = "jit lockstep mismatch in block at 0x0")]
+    fn test_jit_lockstep_mismatch() {
+        let mut cpu = cpu_with_code(&[0xE3A0_0005], false);
+        let mut reference = cpu.clone();
+        reference.arm.store_register(1, 9);
+        reference.arm.cpsr.carry = true;
+        reference.mmu.store8(0x0300_0000, 1);
+        compare(&mut cpu, &mut reference, 0);
+    }
+
+    #[test]
+    fn test_jit_cycle() {
+        let mut cpu = cpu_with_code(
+            &[
+                0x2005, // mov r0, #5
+                0x1C41, // add r1, r0, #1
+                0xE7FE, // b .
+            ],
+            true,
+        );
+
+        // the block runs in place of the first instruction, then the branch after it is fetched
+        cpu::cycle(&mut cpu);
+        assert_eq!(cpu.arm.load_register(0), 5);
+        assert_eq!(cpu.arm.load_register(1), 6);
+        assert_eq!(cpu.arm.load_register(registers::PROGRAM_COUNTER), 6);
+        assert!(matches!(
+            cpu.fetched_instruction,
+            InstructionType::Thumb(0xE7FE)
+        ));
+        assert_eq!(cpu.jit.cached_blocks(), 1);
+    }
+
+    #[test]
+    fn test_jit_unsupported() {
+        // ldr r0, [r1]
+        let mut cpu = cpu_with_code(&[0xE591_0000], false);
+        assert!(!run(&mut cpu));
+        assert!(!run(&mut cpu));
+        assert_eq!(cpu.jit.cached_blocks(), 1);
+    }
+}
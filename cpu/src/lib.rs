@@ -3,7 +3,6 @@ pub use memory;
 
 pub mod constants;
 pub mod enums;
-pub mod utils;
 
 #[macro_use]
 pub mod macros;
@@ -14,4 +13,6 @@ pub mod micro_ops;
 
 pub mod arm;
 pub mod gb;
+#[cfg(feature = "jit")]
+pub mod jit;
 pub mod thumb;
@@ -0,0 +1,54 @@
+//! Runs Blargg's cpu_instrs test ROMs on the LR35902.
+//! Point GB_TEST_ROMS at the directory holding the individual ROMs (01-special.gb, ...)
+//! and run the ignored tests: `GB_TEST_ROMS=<dir> cargo test -- --ignored`.
+
+use cpu::gb::{FlatMemory, LR35902};
+
+use std::fs;
+use std::path::Path;
+
+const ROMS: [&str; 11] = [
+    "01-special.gb",
+    "02-interrupts.gb",
+    "03-op sp,hl.gb",
+    "04-op r,imm.gb",
+    "05-op rp.gb",
+    "06-ld r,r.gb",
+    "07-jr,jp,call,ret,rst.gb",
+    "08-misc instrs.gb",
+    "09-op r,r.gb",
+    "10-bit ops.gb",
+    "11-op a,(hl).gb",
+];
+
+// the slowest ROM needs around 30 emulated seconds
+const MAX_CYCLES: u64 = 4_194_304 * 60;
+
+fn run(path: &Path) -> String {
+    let rom = fs::read(path).unwrap();
+    let mut memory = FlatMemory::with_program(&rom);
+    let mut core = LR35902::default();
+
+    let mut cycles = 0;
+    while cycles < MAX_CYCLES {
+        cycles += core.step(&mut memory) as u64;
+        let output = &memory.serial_output;
+        if output.contains("Passed") || output.contains("Failed") {
+            break;
+        }
+    }
+
+    memory.serial_output
+}
+
+#[test]
+#[ignore = "needs GB_TEST_ROMS set to the directory of Blargg's cpu_instrs ROMs"]
+fn test_blargg_cpu_instrs() {
+    let dir = std::env::var("GB_TEST_ROMS")
+        .expect("GB_TEST_ROMS should be set to the directory of Blargg's cpu_instrs ROMs");
+
+    for name in ROMS.iter() {
+        let output = run(&Path::new(&dir).join(name));
+        assert!(output.contains("Passed"), "{}: {}", name, output);
+    }
+}
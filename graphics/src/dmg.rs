@@ -0,0 +1,364 @@
+// Game Boy and Game Boy Color picture processing unit, used when running GB cartridges.
+// Renders whole scanlines when they enter HBlank which is good enough for nearly every game.
+
+use memory::dmg::{interrupts, registers, DMG};
+
+use crate::frontend::{BGR555, RGBA};
+use crate::{Display, State};
+
+pub const GB_SCREEN_WIDTH: usize = 160;
+pub const GB_SCREEN_HEIGHT: usize = 144;
+
+const DOTS_PER_LINE: u32 = 456;
+const LINES_PER_FRAME: u8 = 154;
+
+/// Shades of the original Game Boy, from lightest to darkest
+const DMG_SHADES: [BGR555; 4] = [
+    BGR555(0x7FFF),
+    BGR555(0x56B5),
+    BGR555(0x294A),
+    BGR555(0x0000),
+];
+
+/// How the 160x144 Game Boy picture is fitted onto the 240x160 GBA screen, like the GBA itself offers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScreenMode {
+    /// Original size, centered with a black border
+    Bordered,
+    /// Scaled to fill the whole screen
+    Stretched,
+}
+
+impl Default for ScreenMode {
+    #[inline]
+    fn default() -> Self {
+        Self::Bordered
+    }
+}
+
+pub struct PPU {
+    /// Set once a frame has been fully drawn, clear it after presenting
+    pub frame_ready: bool,
+
+    frame: Box<[BGR555]>,
+    // position in the current line
+    dots: u32,
+    // the window keeps its own line counter, it only advances on lines where it is drawn
+    window_line: u8,
+    // STAT interrupts fire on the rising edge of all sources combined
+    stat_line: bool,
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        Self {
+            frame_ready: false,
+            frame: vec![DMG_SHADES[0]; GB_SCREEN_WIDTH * GB_SCREEN_HEIGHT].into_boxed_slice(),
+            dots: 0,
+            window_line: 0,
+            stat_line: false,
+        }
+    }
+}
+
+/// Pixel of the background or window layer
+#[derive(Clone, Copy, Default)]
+struct BackgroundPixel {
+    colour: u8,
+    palette: u8,
+    // CGB attribute, wins over sprites
+    priority: bool,
+}
+
+impl PPU {
+    /// Advance by the given number of dots, which run at the normal speed clock
+    pub fn step(&mut self, memory: &mut DMG, cycles: u32) {
+        if memory.io(registers::LCDC) & 0x80 == 0 {
+            // the LCD is off, hold at the start of the frame
+            self.dots = 0;
+            self.window_line = 0;
+            self.stat_line = false;
+            memory.set_io(registers::LY, 0);
+            memory.set_io(registers::STAT, memory.io(registers::STAT) & !0b111);
+            return;
+        }
+
+        self.dots += cycles;
+        if self.dots >= DOTS_PER_LINE {
+            self.dots -= DOTS_PER_LINE;
+
+            let ly = (memory.io(registers::LY) + 1) % LINES_PER_FRAME;
+            memory.set_io(registers::LY, ly);
+            if ly == GB_SCREEN_HEIGHT as u8 {
+                memory.request_interrupt(interrupts::VBLANK);
+                self.frame_ready = true;
+            } else if ly == 0 {
+                self.window_line = 0;
+            }
+        }
+
+        let ly = memory.io(registers::LY);
+        let mode = if ly >= GB_SCREEN_HEIGHT as u8 {
+            1
+        } else if self.dots < 80 {
+            2
+        } else if self.dots < 252 {
+            3
+        } else {
+            0
+        };
+
+        let stat = memory.io(registers::STAT);
+        if mode == 0 && stat & 0b11 != 0 {
+            self.render_line(memory, ly);
+            memory.hblank();
+        }
+
+        let coincidence = ly == memory.io(registers::LYC);
+        memory.set_io(
+            registers::STAT,
+            stat & !0b111 | (coincidence as u8) << 2 | mode,
+        );
+
+        let line = (coincidence && stat & 0x40 != 0)
+            || match mode {
+                0 => stat & 0x08 != 0,
+                1 => stat & 0x10 != 0,
+                2 => stat & 0x20 != 0,
+                _ => false,
+            };
+        if line && !self.stat_line {
+            memory.request_interrupt(interrupts::LCD_STAT);
+        }
+        self.stat_line = line;
+    }
+
+    /// The last finished picture, 160x144
+    pub fn frame(&self) -> &[BGR555] {
+        &self.frame
+    }
+
+    fn render_line(&mut self, memory: &DMG, ly: u8) {
+        let lcdc = memory.io(registers::LCDC);
+        let mut background = [BackgroundPixel::default(); GB_SCREEN_WIDTH];
+
+        // on the CGB bit 0 only takes away the background priority
+        if memory.cgb || lcdc & 0x01 != 0 {
+            self.render_background(memory, ly, &mut background);
+        }
+
+        let row = ly as usize * GB_SCREEN_WIDTH;
+        for (x, pixel) in background.iter().enumerate() {
+            self.frame[row + x] = self.colour(memory, false, pixel.palette, pixel.colour);
+        }
+
+        if lcdc & 0x02 != 0 {
+            self.render_sprites(memory, ly, &background);
+        }
+    }
+
+    fn render_background(&mut self, memory: &DMG, ly: u8, line: &mut [BackgroundPixel]) {
+        let lcdc = memory.io(registers::LCDC);
+        let scx = memory.io(registers::SCX);
+        let scy = memory.io(registers::SCY);
+        let wx = memory.io(registers::WX) as i32 - 7;
+        let window = lcdc & 0x20 != 0 && memory.io(registers::WY) <= ly && wx < 160;
+
+        let mut window_drawn = false;
+        for (x, pixel) in line.iter_mut().enumerate() {
+            let (map, map_x, map_y) = if window && x as i32 >= wx {
+                window_drawn = true;
+                let map = if lcdc & 0x40 != 0 { 0x1C00 } else { 0x1800 };
+                (map, (x as i32 - wx) as u8, self.window_line)
+            } else {
+                let map = if lcdc & 0x08 != 0 { 0x1C00 } else { 0x1800 };
+                (map, scx.wrapping_add(x as u8), scy.wrapping_add(ly))
+            };
+
+            let map_index = map + (map_y as usize / 8) * 32 + map_x as usize / 8;
+            let tile = memory.vram(0)[map_index];
+            // CGB tile attributes live in the second bank
+            let attributes = if memory.cgb {
+                memory.vram(1)[map_index]
+            } else {
+                0
+            };
+
+            let mut tile_x = map_x % 8;
+            let mut tile_y = map_y % 8;
+            if attributes & 0x20 != 0 {
+                tile_x = 7 - tile_x;
+            }
+            if attributes & 0x40 != 0 {
+                tile_y = 7 - tile_y;
+            }
+
+            let tile_address = if lcdc & 0x10 != 0 {
+                tile as usize * 16
+            } else {
+                (0x1000 + (tile as i8 as isize) * 16) as usize
+            };
+            let bank = (attributes >> 3) as usize & 1;
+
+            *pixel = BackgroundPixel {
+                colour: tile_pixel(memory.vram(bank), tile_address, tile_x, tile_y),
+                palette: attributes & 0b111,
+                priority: attributes & 0x80 != 0,
+            };
+        }
+
+        if window_drawn {
+            self.window_line += 1;
+        }
+    }
+
+    fn render_sprites(&mut self, memory: &DMG, ly: u8, background: &[BackgroundPixel]) {
+        let lcdc = memory.io(registers::LCDC);
+        let height = if lcdc & 0x04 != 0 { 16 } else { 8 };
+        let oam = memory.oam();
+
+        // only the first 10 sprites on a line are drawn
+        let mut sprites: Vec<usize> = (0..40)
+            .filter(|&i| {
+                let y = oam[i * 4] as i32 - 16;
+                y <= ly as i32 && (ly as i32) < y + height
+            })
+            .take(10)
+            .collect();
+
+        // the DMG favours the lowest X, the CGB only looks at the OAM order.
+        // Draw the lowest priority first so the others paint over it.
+        if !memory.cgb {
+            sprites.sort_by_key(|&i| (oam[i * 4 + 1], i));
+        }
+
+        let row = ly as usize * GB_SCREEN_WIDTH;
+        for &i in sprites.iter().rev() {
+            let y = oam[i * 4] as i32 - 16;
+            let x = oam[i * 4 + 1] as i32 - 8;
+            let mut tile = oam[i * 4 + 2];
+            let attributes = oam[i * 4 + 3];
+
+            let mut tile_y = (ly as i32 - y) as u8;
+            if attributes & 0x40 != 0 {
+                tile_y = height as u8 - 1 - tile_y;
+            }
+            if height == 16 {
+                tile = (tile & 0xFE) | (tile_y / 8);
+            }
+
+            let bank = if memory.cgb {
+                (attributes >> 3) as usize & 1
+            } else {
+                0
+            };
+            let palette = if memory.cgb {
+                attributes & 0b111
+            } else {
+                (attributes >> 4) & 1
+            };
+
+            for tile_x in 0..8 {
+                let screen_x = x + tile_x as i32;
+                if !(0..GB_SCREEN_WIDTH as i32).contains(&screen_x) {
+                    continue;
+                }
+
+                let pixel_x = if attributes & 0x20 != 0 {
+                    7 - tile_x
+                } else {
+                    tile_x
+                };
+                let colour = tile_pixel(memory.vram(bank), tile as usize * 16, pixel_x, tile_y % 8);
+                if colour == 0 {
+                    continue;
+                }
+
+                let below = background[screen_x as usize];
+                let background_wins = below.colour != 0
+                    && (attributes & 0x80 != 0 || below.priority)
+                    && (!memory.cgb || lcdc & 0x01 != 0);
+                if background_wins {
+                    continue;
+                }
+
+                self.frame[row + screen_x as usize] = self.colour(memory, true, palette, colour);
+            }
+        }
+    }
+
+    /// Resolve a 2 bit colour through the palettes
+    fn colour(&self, memory: &DMG, sprite: bool, palette: u8, colour: u8) -> BGR555 {
+        if memory.cgb {
+            let palettes = if sprite {
+                memory.obj_palettes()
+            } else {
+                memory.bg_palettes()
+            };
+            let index = (palette as usize * 4 + colour as usize) * 2;
+            return BGR555(u16::from_le_bytes([palettes[index], palettes[index + 1]]) & 0x7FFF);
+        }
+
+        let register = match (sprite, palette) {
+            (false, _) => registers::BGP,
+            (true, 0) => registers::OBP0,
+            (true, _) => registers::OBP1,
+        };
+        let shade = (memory.io(register) >> (colour * 2)) & 0b11;
+        DMG_SHADES[shade as usize]
+    }
+}
+
+/// 2 bit colour of a pixel in an 8x8 tile stored as interleaved bit planes
+#[inline]
+fn tile_pixel(vram: &[u8], tile_address: usize, x: u8, y: u8) -> u8 {
+    let low = vram[tile_address + y as usize * 2];
+    let high = vram[tile_address + y as usize * 2 + 1];
+    let bit = 7 - x;
+    ((high >> bit) & 1) << 1 | (low >> bit) & 1
+}
+
+impl Display {
+    /// Draw the finished Game Boy frame onto the GBA screen and get user input.
+    /// Returns the pressed keys in the KEYINPUT order, as expected by `DMG::set_keys`.
+    pub fn present_dmg(&mut self, ppu: &PPU, mode: ScreenMode) -> (State, u8) {
+        const WIDTH: usize = crate::SCREEN_WIDTH;
+        const HEIGHT: usize = crate::SCREEN_HEIGHT;
+        const BORDER_X: usize = (WIDTH - GB_SCREEN_WIDTH) / 2;
+        const BORDER_Y: usize = (HEIGHT - GB_SCREEN_HEIGHT) / 2;
+
+        let input = self.poll_input().clone();
+        let frame = ppu.frame();
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let pixel = match mode {
+                    ScreenMode::Stretched => {
+                        frame[y * GB_SCREEN_HEIGHT / HEIGHT * GB_SCREEN_WIDTH
+                            + x * GB_SCREEN_WIDTH / WIDTH]
+                    }
+                    ScreenMode::Bordered => {
+                        if (BORDER_X..BORDER_X + GB_SCREEN_WIDTH).contains(&x)
+                            && (BORDER_Y..BORDER_Y + GB_SCREEN_HEIGHT).contains(&y)
+                        {
+                            frame[(y - BORDER_Y) * GB_SCREEN_WIDTH + x - BORDER_X]
+                        } else {
+                            BGR555(0)
+                        }
+                    }
+                };
+
+                self.draw_pixel((x, y), RGBA::from(pixel));
+            }
+        }
+
+        let state = if input.exit {
+            State::Exited
+        } else {
+            State::Running
+        };
+        (state, input.to_u16() as u8)
+    }
+}
+
+pub mod tests;
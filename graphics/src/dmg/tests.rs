@@ -0,0 +1,52 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_dmg_tile_pixel() {
+        // one row: low plane 0b1010_0000, high plane 0b1100_0000
+        let vram = [0b1010_0000, 0b1100_0000];
+        assert_eq!(tile_pixel(&vram, 0, 0, 0), 3);
+        assert_eq!(tile_pixel(&vram, 0, 1, 0), 2);
+        assert_eq!(tile_pixel(&vram, 0, 2, 0), 1);
+        assert_eq!(tile_pixel(&vram, 0, 3, 0), 0);
+    }
+
+    #[test]
+    fn test_dmg_vblank_timing() {
+        let mut memory = DMG::default();
+        let mut ppu = PPU::default();
+        memory.set_io(registers::IF, 0);
+
+        let mut cycles = 0;
+        while !ppu.frame_ready {
+            ppu.step(&mut memory, 4);
+            cycles += 4;
+        }
+
+        assert_eq!(cycles, 144 * DOTS_PER_LINE);
+        assert_eq!(memory.io(registers::STAT) & 0b11, 1);
+        assert_ne!(memory.io(registers::IF) & interrupts::VBLANK, 0);
+    }
+
+    #[test]
+    fn test_dmg_background_palette() {
+        let mut memory = DMG::default();
+        let mut ppu = PPU::default();
+
+        // tile 0, first row, every pixel colour 3, BGP maps 3 to black
+        memory.store8(0x8000, 0xFF);
+        memory.store8(0x8001, 0xFF);
+        memory.store8(registers::BGP, 0b1110_0100);
+
+        while memory.io(registers::STAT) & 0b11 != 0 || memory.io(registers::LY) != 0 {
+            ppu.step(&mut memory, 4);
+        }
+        assert_eq!(ppu.frame()[0], DMG_SHADES[3]);
+        // the second row of the tile is still blank
+        while memory.io(registers::LY) != 1 || memory.io(registers::STAT) & 0b11 != 0 {
+            ppu.step(&mut memory, 4);
+        }
+        assert_eq!(ppu.frame()[GB_SCREEN_WIDTH], DMG_SHADES[0]);
+    }
+}
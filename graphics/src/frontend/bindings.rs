@@ -0,0 +1,378 @@
+// Host keys bound to the GBA keys and emulator hotkeys, shared by the frontends.
+// Keys are named as SDL names them ("Z", "Return", "Left Shift", "F5"), ignoring case.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Emulator controls that are not GBA keys
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hotkey {
+    Exit,
+    Pause,
+    /// Held to run as fast as possible
+    FastForward,
+    Screenshot,
+}
+
+/// Directions to tilt the GBA in, for cartridges with a tilt sensor or gyro
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tilt {
+    Left,
+    Right,
+    /// The top of the GBA away from the player
+    Up,
+    Down,
+}
+
+/// What a bound key does
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// A GBA key, as its bit in KEYINPUT
+    Key(u16),
+    Hotkey(Hotkey),
+    Tilt(Tilt),
+}
+
+/// Names of the actions in the config file
+pub const ACTIONS: [(&str, Action); 18] = [
+    ("a", Action::Key(1)),
+    ("b", Action::Key(1 << 1)),
+    ("select", Action::Key(1 << 2)),
+    ("start", Action::Key(1 << 3)),
+    ("right", Action::Key(1 << 4)),
+    ("left", Action::Key(1 << 5)),
+    ("up", Action::Key(1 << 6)),
+    ("down", Action::Key(1 << 7)),
+    ("r", Action::Key(1 << 8)),
+    ("l", Action::Key(1 << 9)),
+    ("exit", Action::Hotkey(Hotkey::Exit)),
+    ("pause", Action::Hotkey(Hotkey::Pause)),
+    ("fast_forward", Action::Hotkey(Hotkey::FastForward)),
+    ("screenshot", Action::Hotkey(Hotkey::Screenshot)),
+    ("tilt_left", Action::Tilt(Tilt::Left)),
+    ("tilt_right", Action::Tilt(Tilt::Right)),
+    ("tilt_up", Action::Tilt(Tilt::Up)),
+    ("tilt_down", Action::Tilt(Tilt::Down)),
+];
+
+/// Keys bound when the config file does not say otherwise
+const DEFAULTS: [(&str, &str); 18] = [
+    ("a", "X"),
+    ("b", "Z"),
+    ("select", "Backspace"),
+    ("start", "Return"),
+    ("right", "Right"),
+    ("left", "Left"),
+    ("up", "Up"),
+    ("down", "Down"),
+    ("r", "S"),
+    ("l", "A"),
+    ("exit", "Escape"),
+    ("pause", "P"),
+    ("fast_forward", "Tab"),
+    ("screenshot", "F12"),
+    ("tilt_left", "J"),
+    ("tilt_right", "L"),
+    ("tilt_up", "I"),
+    ("tilt_down", "K"),
+];
+
+fn action_named(name: &str) -> Option<Action> {
+    ACTIONS
+        .iter()
+        .find(|(action, _)| action.eq_ignore_ascii_case(name))
+        .map(|&(_, action)| action)
+}
+
+/// Inputs bound to actions, by lowercase name
+#[derive(Clone, Debug, Default)]
+struct Map(HashMap<String, Action>);
+
+impl Map {
+    fn with_defaults(defaults: &[(&str, &str)]) -> Self {
+        let mut map = Self::default();
+        for &(action, input) in defaults.iter() {
+            map.bind(input, action_named(action).unwrap());
+        }
+        map
+    }
+
+    fn bind(&mut self, input: &str, action: Action) {
+        self.0.insert(input.to_lowercase(), action);
+    }
+
+    /// Replace the inputs bound to action with a comma separated list
+    fn rebind(&mut self, action: Action, inputs: &str) {
+        self.0.retain(|_, bound| *bound != action);
+        for input in inputs
+            .split(',')
+            .map(str::trim)
+            .filter(|input| !input.is_empty())
+        {
+            self.bind(input, action);
+        }
+    }
+
+    fn action(&self, input: &str) -> Option<Action> {
+        self.0.get(&input.to_lowercase()).copied()
+    }
+}
+
+/// Controller inputs bound when the config file does not say otherwise. Buttons are named as
+/// SDL names them, stick directions are the axis followed by - or +, triggers are their axis.
+const CONTROLLER_DEFAULTS: [(&str, &str); 22] = [
+    ("a", "a"),
+    ("b", "b"),
+    ("select", "back"),
+    ("start", "start"),
+    ("right", "dpright"),
+    ("left", "dpleft"),
+    ("up", "dpup"),
+    ("down", "dpdown"),
+    ("right", "leftx+"),
+    ("left", "leftx-"),
+    ("up", "lefty-"),
+    ("down", "lefty+"),
+    ("r", "rightshoulder"),
+    ("l", "leftshoulder"),
+    ("exit", "guide"),
+    ("pause", "y"),
+    ("fast_forward", "righttrigger"),
+    ("screenshot", "x"),
+    ("tilt_left", "rightx-"),
+    ("tilt_right", "rightx+"),
+    ("tilt_up", "righty-"),
+    ("tilt_down", "righty+"),
+];
+
+/// Axis values from the centre up to this are ignored, out of 32767
+pub const DEFAULT_DEADZONE: i16 = 8000;
+
+/// Bindings of a game controller
+#[derive(Clone, Debug)]
+pub struct ControllerBindings {
+    inputs: Map,
+    /// Axis values from the centre up to this are ignored
+    pub deadzone: i16,
+}
+
+impl Default for ControllerBindings {
+    fn default() -> Self {
+        Self {
+            inputs: Map::with_defaults(&CONTROLLER_DEFAULTS),
+            deadzone: DEFAULT_DEADZONE,
+        }
+    }
+}
+
+impl ControllerBindings {
+    /// Bind a button or axis direction to action, replacing what it was bound to
+    pub fn bind(&mut self, input: &str, action: Action) {
+        self.inputs.bind(input, action)
+    }
+
+    pub fn action(&self, input: &str) -> Option<Action> {
+        self.inputs.action(input)
+    }
+}
+
+/// Which bindings the lines of the config file apply to
+enum Section {
+    Keyboard,
+    Controller,
+    /// A controller by its SDL GUID
+    Guid(String),
+}
+
+/// Map from host keys and controller inputs to actions
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    keys: Map,
+    controller: ControllerBindings,
+    controllers: HashMap<String, ControllerBindings>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self {
+            keys: Map::with_defaults(&DEFAULTS),
+            controller: Default::default(),
+            controllers: HashMap::new(),
+        }
+    }
+}
+
+impl Bindings {
+    /// Parse a config file of `action = key, key` lines, `#` starts a comment.
+    /// Lines after `[controller]` bind the inputs of every controller and the ones after
+    /// `[controller <guid>]` a single controller, starting from the `[controller]` bindings
+    /// read so far. `deadzone = <value>` sets the deadzone of the sticks. `[keyboard]` goes back
+    /// to binding keys. Actions that are not listed keep their default keys.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut bindings = Self::default();
+        let mut section = Section::Keyboard;
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                let mut words = line[1..line.len() - 1].split_whitespace();
+                section = match (words.next(), words.next()) {
+                    (Some("keyboard"), None) => Section::Keyboard,
+                    (Some("controller"), None) => Section::Controller,
+                    (Some("controller"), Some(guid)) => {
+                        let guid = guid.to_lowercase();
+                        let controller = bindings.controller.clone();
+                        bindings
+                            .controllers
+                            .entry(guid.clone())
+                            .or_insert(controller);
+                        Section::Guid(guid)
+                    }
+                    _ => return Err(error(format!("unknown section {}", line))),
+                };
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap_or_default().trim();
+            let inputs = match parts.next() {
+                Some(inputs) => inputs.trim(),
+                None => return Err(error("expected action = keys".to_string())),
+            };
+
+            let controller = match section {
+                Section::Keyboard => None,
+                Section::Controller => Some(&mut bindings.controller),
+                Section::Guid(ref guid) => bindings.controllers.get_mut(guid),
+            };
+            match (controller, action_named(name)) {
+                (Some(controller), _) if name == "deadzone" => {
+                    controller.deadzone = inputs
+                        .parse()
+                        .map_err(|_| error(format!("invalid deadzone {}", inputs)))?
+                }
+                (Some(controller), Some(action)) => controller.inputs.rebind(action, inputs),
+                (None, Some(action)) => bindings.keys.rebind(action, inputs),
+                (_, None) => return Err(error(format!("unknown action {}", name))),
+            }
+        }
+        Ok(bindings)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("Unable to read {}: {}", path.display(), error))?;
+        Self::parse(&text).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    /// Where the bindings are looked for when none are given, in the user's config directory
+    pub fn default_path() -> Option<PathBuf> {
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config.join("velera").join("bindings.cfg"))
+    }
+
+    /// Bind key to action, replacing what it was bound to
+    pub fn bind(&mut self, key: &str, action: Action) {
+        self.keys.bind(key, action)
+    }
+
+    pub fn action(&self, key: &str) -> Option<Action> {
+        self.keys.action(key)
+    }
+
+    /// Bindings of the controller with this GUID, the ones shared by all controllers unless
+    /// it has its own
+    pub fn controller(&self, guid: &str) -> &ControllerBindings {
+        self.controllers
+            .get(&guid.to_lowercase())
+            .unwrap_or(&self.controller)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bindings_defaults() {
+        let bindings = Bindings::default();
+        assert_eq!(bindings.action("x"), Some(Action::Key(1)));
+        assert_eq!(bindings.action("Return"), Some(Action::Key(1 << 3)));
+        assert_eq!(
+            bindings.action("F12"),
+            Some(Action::Hotkey(Hotkey::Screenshot))
+        );
+        assert_eq!(bindings.action("Q"), None);
+    }
+
+    #[test]
+    fn test_bindings_parse() {
+        let bindings = Bindings::parse(
+            "# swap A and B\n\
+             a = Z\n\
+             B = x, Right Shift # either\n\
+             \n\
+             fast_forward = Space\n",
+        )
+        .unwrap();
+
+        assert_eq!(bindings.action("Z"), Some(Action::Key(1)));
+        assert_eq!(bindings.action("X"), Some(Action::Key(1 << 1)));
+        assert_eq!(bindings.action("right shift"), Some(Action::Key(1 << 1)));
+        // rebinding an action drops its default key, the others are kept
+        assert_eq!(bindings.action("Tab"), None);
+        assert_eq!(
+            bindings.action("Space"),
+            Some(Action::Hotkey(Hotkey::FastForward))
+        );
+        assert_eq!(bindings.action("Return"), Some(Action::Key(1 << 3)));
+    }
+
+    #[test]
+    fn test_bindings_parse_errors() {
+        assert!(Bindings::parse("turbo = T").is_err());
+        assert!(Bindings::parse("a Z").is_err());
+        assert!(Bindings::parse("[mouse]").is_err());
+        assert!(Bindings::parse("[controller]\ndeadzone = far").is_err());
+        // the deadzone is only a controller setting
+        assert!(Bindings::parse("deadzone = 100").is_err());
+    }
+
+    #[test]
+    fn test_bindings_controllers() {
+        const GUID: &str = "030000005e0400008e02000010010000";
+        let bindings = Bindings::parse(&format!(
+            "a = X\n\
+             [controller]\n\
+             deadzone = 4000\n\
+             a = b\n\
+             [controller {}]\n\
+             b = a, x\n\
+             [keyboard]\n\
+             b = C\n",
+            GUID.to_uppercase()
+        ))
+        .unwrap();
+
+        assert_eq!(bindings.action("C"), Some(Action::Key(1 << 1)));
+
+        let any = bindings.controller("0300000000000000");
+        assert_eq!(any.deadzone, 4000);
+        assert_eq!(any.action("b"), Some(Action::Key(1)));
+        assert_eq!(any.action("a"), None);
+        assert_eq!(any.action("leftx-"), Some(Action::Key(1 << 5)));
+
+        // a controller starts from the shared bindings
+        let own = bindings.controller(GUID);
+        assert_eq!(own.deadzone, 4000);
+        assert_eq!(own.action("b"), Some(Action::Key(1)));
+        assert_eq!(own.action("x"), Some(Action::Key(1 << 1)));
+        assert_eq!(own.action("a"), Some(Action::Key(1 << 1)));
+    }
+}
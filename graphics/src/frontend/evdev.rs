@@ -0,0 +1,301 @@
+// Input read straight from the Linux event devices, so keyboards and game controllers work
+// without a TTY in MEDIUMRAW mode. Based on linux/input.h and linux/input-event-codes.h
+
+use super::bindings::Bindings;
+use super::linux_framebuffer::{ioctl, key_name};
+use super::InputStates;
+
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const INPUT_DIRECTORY: &str = "/dev/input";
+/// How often plugged in devices are looked for
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+const O_NONBLOCK: i32 = 0o4000;
+
+// Event types
+const EV_KEY: u16 = 0x01;
+const EV_ABS: u16 = 0x03;
+
+// ioctls
+const EVIOCGID: u64 = 0x8008_4502;
+/// EVIOCGABS of the first axis, the axis is added to it
+const EVIOCGABS: u64 = 0x8018_4540;
+
+/// Size of struct input_event, a timeval followed by the type, code and value
+const EVENT_SIZE: usize = 2 * std::mem::size_of::<std::os::raw::c_long>() + 8;
+
+#[derive(Default)]
+#[repr(C)]
+struct input_id {
+    bustype: u16,
+    vendor: u16,
+    product: u16,
+    version: u16,
+}
+
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct input_absinfo {
+    value: i32,
+    minimum: i32,
+    maximum: i32,
+    fuzz: i32,
+    flat: i32,
+    resolution: i32,
+}
+
+/// Controller button of a BTN_ code, as SDL names it so the controller bindings are shared
+fn button_name(code: u16) -> Option<&'static str> {
+    Some(match code {
+        0x130 => "a",
+        0x131 => "b",
+        0x133 => "x",
+        0x134 => "y",
+        0x136 => "leftshoulder",
+        0x137 => "rightshoulder",
+        // digital triggers
+        0x138 => "lefttrigger",
+        0x139 => "righttrigger",
+        0x13A => "back",
+        0x13B => "start",
+        0x13C => "guide",
+        0x13D => "leftstick",
+        0x13E => "rightstick",
+        0x220 => "dpup",
+        0x221 => "dpdown",
+        0x222 => "dpleft",
+        0x223 => "dpright",
+        _ => return None,
+    })
+}
+
+/// Controller axis of an ABS_ code, as SDL names it
+fn axis_name(code: u16) -> Option<&'static str> {
+    Some(match code {
+        0x00 => "leftx",
+        0x01 => "lefty",
+        0x02 => "lefttrigger",
+        0x03 => "rightx",
+        0x04 => "righty",
+        0x05 => "righttrigger",
+        _ => return None,
+    })
+}
+
+/// ABS_HAT0X and ABS_HAT0Y, the d-pad of many controllers
+const HAT_X: u16 = 0x10;
+const HAT_Y: u16 = 0x11;
+
+/// An opened event device
+struct Device {
+    file: File,
+    path: PathBuf,
+    /// SDL style GUID built from the bus, vendor, product and version ids
+    guid: String,
+    /// Ranges of the axes
+    axes: [input_absinfo; 6],
+    /// Controller buttons and axis directions currently held
+    held: HashSet<String>,
+}
+
+impl Device {
+    fn open(path: PathBuf) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(O_NONBLOCK)
+            .open(&path)?;
+        let fd = file.as_raw_fd();
+
+        let mut id = input_id::default();
+        if unsafe { ioctl(fd, EVIOCGID, &mut id as *mut input_id) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let guid = [id.bustype, 0, id.vendor, 0, id.product, 0, id.version, 0]
+            .iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        // the axes a device does not have stay zeroed
+        let mut axes = [input_absinfo::default(); 6];
+        for (axis, info) in axes.iter_mut().enumerate() {
+            if unsafe { ioctl(fd, EVIOCGABS + axis as u64, info as *mut input_absinfo) } < 0 {
+                *info = input_absinfo::default();
+            }
+        }
+
+        Ok(Self {
+            file,
+            path,
+            guid,
+            axes,
+            held: HashSet::new(),
+        })
+    }
+
+    /// A controller button or axis direction went down or up
+    fn set(&mut self, input: &str, pressed: bool, bindings: &Bindings, states: &mut InputStates) {
+        let changed = if pressed {
+            self.held.insert(input.to_string())
+        } else {
+            self.held.remove(input)
+        };
+        if changed {
+            if let Some(action) = bindings.controller(&self.guid).action(input) {
+                states.update(action, pressed);
+            }
+        }
+    }
+
+    /// Releases what the device was holding, so no key stays stuck down once it is unplugged
+    fn release(&mut self, bindings: &Bindings, states: &mut InputStates) {
+        for input in self.held.drain() {
+            if let Some(action) = bindings.controller(&self.guid).action(&input) {
+                states.update(action, false);
+            }
+        }
+    }
+
+    fn event(
+        &mut self,
+        kind: u16,
+        code: u16,
+        value: i32,
+        bindings: &Bindings,
+        states: &mut InputStates,
+    ) {
+        match kind {
+            // value 2 is the key repeating
+            EV_KEY if value == 2 => (),
+            EV_KEY if code < 0x80 => {
+                let action = key_name(code as u8).and_then(|name| bindings.action(name));
+                if let Some(action) = action {
+                    states.update(action, value != 0);
+                }
+            }
+            EV_KEY => {
+                if let Some(name) = button_name(code) {
+                    self.set(name, value != 0, bindings, states);
+                }
+            }
+            EV_ABS if code == HAT_X || code == HAT_Y => {
+                let (negative, positive) = if code == HAT_X {
+                    ("dpleft", "dpright")
+                } else {
+                    ("dpup", "dpdown")
+                };
+                self.set(negative, value < 0, bindings, states);
+                self.set(positive, value > 0, bindings, states);
+            }
+            EV_ABS => {
+                let name = match axis_name(code) {
+                    Some(name) => name,
+                    None => return,
+                };
+                let info = self.axes[code as usize];
+                let range = (info.maximum as i64 - info.minimum as i64).max(1);
+                let deadzone = bindings.controller(&self.guid).deadzone as i64;
+
+                // scaled to the -32768 to 32767 of SDL, triggers from 0 to 32767
+                let offset = value as i64 - info.minimum as i64;
+                if name.ends_with("trigger") {
+                    self.set(name, offset * 32767 / range > deadzone, bindings, states);
+                } else {
+                    let value = offset * 65535 / range - 32768;
+                    self.set(&format!("{}-", name), value < -deadzone, bindings, states);
+                    self.set(&format!("{}+", name), value > deadzone, bindings, states);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Handles the queued events, returns false once the device is gone
+    fn poll(&mut self, bindings: &Bindings, states: &mut InputStates) -> bool {
+        let mut buffer = [0; EVENT_SIZE * 32];
+        loop {
+            let len = match self.file.read(&mut buffer) {
+                Ok(len) => len,
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => return true,
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => return false,
+            };
+
+            for event in buffer[..len].chunks_exact(EVENT_SIZE) {
+                let event = &event[EVENT_SIZE - 8..];
+                let kind = u16::from_ne_bytes([event[0], event[1]]);
+                let code = u16::from_ne_bytes([event[2], event[3]]);
+                let value = i32::from_ne_bytes([event[4], event[5], event[6], event[7]]);
+                self.event(kind, code, value, bindings, states);
+            }
+            if len < buffer.len() {
+                return true;
+            }
+        }
+    }
+}
+
+/// Keyboards and controllers from /dev/input, plugged in devices are picked up as they appear
+pub struct Input {
+    devices: Vec<Device>,
+    last_scan: Instant,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        let mut input = Self {
+            devices: Vec::new(),
+            last_scan: Instant::now(),
+        };
+        input.scan();
+        if input.devices.is_empty() {
+            eprintln!(
+                "Unable to open any device in {}, access to it is usually given by the input group",
+                INPUT_DIRECTORY
+            );
+        }
+        input
+    }
+
+    /// Open the event devices that are not open yet
+    fn scan(&mut self) {
+        self.last_scan = Instant::now();
+        let entries = match std::fs::read_dir(INPUT_DIRECTORY) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let is_event = entry.file_name().to_string_lossy().starts_with("event");
+            let path = entry.path();
+            if is_event && !self.devices.iter().any(|device| device.path == path) {
+                if let Ok(device) = Device::open(path) {
+                    self.devices.push(device);
+                }
+            }
+        }
+    }
+
+    /// Apply the events of every device to states
+    pub fn poll(&mut self, bindings: &Bindings, states: &mut InputStates) {
+        if self.last_scan.elapsed() >= SCAN_INTERVAL {
+            self.scan();
+        }
+
+        let mut index = 0;
+        while index < self.devices.len() {
+            if self.devices[index].poll(bindings, states) {
+                index += 1;
+            } else {
+                self.devices.remove(index).release(bindings, states);
+            }
+        }
+    }
+}
@@ -1,6 +1,8 @@
 // Linux framebuffer graphics frontend
 
 use super::*;
+use super::bindings::Bindings;
+use super::evdev;
 
 #[cfg(not(target_os = "linux"))]
 compile_error!("This feature requires Linux system calls");
@@ -11,6 +13,12 @@ pub struct Frontend {
 
     fb_info: fb_var_screeninfo,
     scale: usize,
+
+    bindings: Bindings,
+    states: InputStates,
+    input: evdev::Input,
+    /// Keys are also read from the terminal in MEDIUMRAW mode
+    raw_keyboard: bool,
 }
 
 /// You must ensure that this structure gets dropped before program termination
@@ -62,7 +70,6 @@ impl Frontend {
             eprintln!("Failed to close /dev/fb0. Please file a bug report."); // Should only fail if program is terminating, in which case this should not print
         }
 
-        unsafe { Self::kd_raw()? };
         unsafe { signal(SIGINT, catch_signal) };
         unsafe { signal(SIGKILL, catch_signal) };
 
@@ -71,6 +78,11 @@ impl Frontend {
 
             fb_info,
             scale: scale as usize,
+
+            bindings: Bindings::default(),
+            states: InputStates::new(),
+            input: evdev::Input::new(),
+            raw_keyboard: false,
         })
     }
 
@@ -106,6 +118,24 @@ impl Frontend {
         Ok(())
     }
 
+    /// The framebuffer has no title bar
+    pub fn set_title(&mut self, _title: &str) {}
+
+    /// The input devices are only read, controllers do not rumble
+    pub fn set_rumble(&mut self, _on: bool) {}
+
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.bindings = bindings;
+    }
+
+    /// Read the keyboard from the terminal in MEDIUMRAW mode, for when the event devices
+    /// cannot be opened. The terminal needs restoring if the emulator crashes.
+    pub fn use_raw_keyboard(&mut self) -> Result<(), String> {
+        unsafe { Self::kd_raw()? };
+        self.raw_keyboard = true;
+        Ok(())
+    }
+
     /// Set the pixel at (x,y) to colour
     pub fn draw_pixel(&mut self, position: (usize, usize), colour: super::RGBA) {
         const FB_WIDTH: usize = 4;
@@ -120,25 +150,61 @@ impl Frontend {
 
     /// Get input from the user
     pub fn get_input(&mut self) -> InputStates {
-        let mut states = InputStates::new();
-
-        if unsafe { SIG_END } { states.exit = true }
+        if unsafe { SIG_END } { self.states.exit = true }
 
         use std::io::Read;
 
-        // TODO: Allow rebinding as not all keyboards are alike
+        // MEDIUMRAW gives a keycode per byte, with the top bit set when the key is released
         let mut key = [0];
-        while let Ok(_) = std::io::stdin().read(&mut key[..]) {
-            match key[0] {
-                1 => states.exit = true,
-                _ => ()
+        while self.raw_keyboard {
+            if let Ok(1) = std::io::stdin().read(&mut key[..]) {
+                let pressed = key[0] & 0x80 == 0;
+                let action = key_name(key[0] & 0x7F).and_then(|name| self.bindings.action(name));
+                if let Some(action) = action {
+                    self.states.update(action, pressed);
+                }
+            } else {
+                break;
             }
         };
+        self.input.poll(&self.bindings, &mut self.states);
 
-        states
+        self.states.take()
     }
 }
 
+/// Name of a Linux keycode, as SDL names the key, so both frontends share bindings
+pub(super) fn key_name(code: u8) -> Option<&'static str> {
+    const ROW_NAMES: [&str; 58] = [
+        "", "Escape", "1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "-", "=", "Backspace",
+        "Tab", "Q", "W", "E", "R", "T", "Y", "U", "I", "O", "P", "[", "]", "Return",
+        "Left Ctrl", "A", "S", "D", "F", "G", "H", "J", "K", "L", ";", "'", "`",
+        "Left Shift", "\\", "Z", "X", "C", "V", "B", "N", "M", ",", ".", "/", "Right Shift",
+        "Keypad *", "Left Alt", "Space",
+    ];
+
+    Some(match code {
+        1..=57 => ROW_NAMES[code as usize],
+        59..=68 => ["F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9", "F10"][code as usize - 59],
+        87 => "F11",
+        88 => "F12",
+        96 => "Keypad Enter",
+        97 => "Right Ctrl",
+        100 => "Right Alt",
+        102 => "Home",
+        103 => "Up",
+        104 => "PageUp",
+        105 => "Left",
+        106 => "Right",
+        107 => "End",
+        108 => "Down",
+        109 => "PageDown",
+        110 => "Insert",
+        111 => "Delete",
+        _ => return None,
+    })
+}
+
 // I would not consider this use unsafe because of how it is used
 /// set when an SIGINT or SIGKILL was recieved
 static mut SIG_END: bool = false;
@@ -166,6 +232,10 @@ impl Drop for Frontend {
                 ioctl(STDIN, KDSKBMODE, kb_mode);
                 INIT_KB_MODE = None;
             }
+            // the keys read from the event devices also reached the terminal, drop them
+            if !self.raw_keyboard {
+                tcflush(STDIN, TCIOFLUSH);
+            }
         }
 
         let ptr = self.framebuffer.as_mut().unwrap().as_ptr() as *mut void;
@@ -7,6 +7,11 @@ pub type Frontend = sdl2::Frontend;
 mod linux_framebuffer;
 #[cfg(feature = "fbdev")]
 pub type Frontend = linux_framebuffer::Frontend;
+#[cfg(feature = "fbdev")]
+mod evdev;
+
+pub mod bindings;
+use bindings::{Action, Hotkey, Tilt};
 
 /// A BGR555 colour
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -118,6 +123,7 @@ impl std::ops::Deref for RGBA {
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct InputStates {
     // GBA keys
     pub a:      bool,
@@ -133,6 +139,17 @@ pub struct InputStates {
 
     // Emulator keys
     pub exit:   bool,
+    pub pause:  bool,
+    pub fast_forward: bool,
+    pub screenshot: bool,
+
+    // Tilting the GBA, for cartridges with a tilt sensor or gyro
+    pub tilt_left:  bool,
+    pub tilt_right: bool,
+    pub tilt_up:    bool,
+    pub tilt_down:  bool,
+    /// Tilt given with the mouse, while its button is held
+    pub pointer_tilt: Option<(i16, i16)>,
 }
 
 impl InputStates {
@@ -150,6 +167,15 @@ impl InputStates {
             l:      false,
 
             exit:   false,
+            pause:  false,
+            fast_forward: false,
+            screenshot: false,
+
+            tilt_left:  false,
+            tilt_right: false,
+            tilt_up:    false,
+            tilt_down:  false,
+            pointer_tilt: None,
         }
     }
 
@@ -166,7 +192,7 @@ impl InputStates {
             r:      raw & (1 << 8) != 0,
             l:      raw & (1 << 9) != 0,
 
-            exit:   false,
+            ..Self::new()
         }
     }
 
@@ -182,6 +208,62 @@ impl InputStates {
             | (self.r as u16) << 8
             | (self.l as u16) << 9
     }
+
+    /// A bound key went down or up. GBA keys and fast forward are held,
+    /// the other hotkeys stay set until taken.
+    pub fn update(&mut self, action: Action, pressed: bool) {
+        let state = match action {
+            Action::Key(bit) => match bit.trailing_zeros() {
+                0 => &mut self.a,
+                1 => &mut self.b,
+                2 => &mut self.select,
+                3 => &mut self.start,
+                4 => &mut self.right,
+                5 => &mut self.left,
+                6 => &mut self.up,
+                7 => &mut self.down,
+                8 => &mut self.r,
+                9 => &mut self.l,
+                _ => return,
+            },
+            Action::Hotkey(Hotkey::FastForward) => &mut self.fast_forward,
+            Action::Tilt(Tilt::Left) => &mut self.tilt_left,
+            Action::Tilt(Tilt::Right) => &mut self.tilt_right,
+            Action::Tilt(Tilt::Up) => &mut self.tilt_up,
+            Action::Tilt(Tilt::Down) => &mut self.tilt_down,
+            Action::Hotkey(_) if !pressed => return,
+            Action::Hotkey(Hotkey::Exit) => &mut self.exit,
+            Action::Hotkey(Hotkey::Pause) => &mut self.pause,
+            Action::Hotkey(Hotkey::Screenshot) => &mut self.screenshot,
+        };
+        *state = pressed;
+    }
+
+    /// How far the GBA is tilted, from -32768 to 32767 to the right and down.
+    /// Keys tilt it all the way, the mouse is used while no key is held.
+    pub fn tilt(&self) -> (i16, i16) {
+        let axis = |negative: bool, positive: bool| match (negative, positive) {
+            (true, false) => -i16::MAX,
+            (false, true) => i16::MAX,
+            _ => 0,
+        };
+        let keys = (
+            axis(self.tilt_left, self.tilt_right),
+            axis(self.tilt_up, self.tilt_down),
+        );
+        match self.pointer_tilt {
+            Some(tilt) if keys == (0, 0) => tilt,
+            _ => keys,
+        }
+    }
+
+    /// The states since the last poll, clearing the hotkeys that fire once
+    pub fn take(&mut self) -> Self {
+        let states = self.clone();
+        self.pause = false;
+        self.screenshot = false;
+        states
+    }
 }
 
 #[cfg(test)]
@@ -200,4 +282,44 @@ mod tests {
         assert_eq!(RGBA::from(BGR555(0b0000001111100000)), RGBA(0x00FF00));
         assert_eq!(RGBA::from(BGR555(0b0000000000011111)), RGBA(0xFF0000));
     }
+
+    #[test]
+    fn test_input_states_update() {
+        use super::bindings::{Action, Hotkey};
+        use super::InputStates;
+
+        let mut states = InputStates::new();
+        states.update(Action::Key(1 << 3), true);
+        states.update(Action::Hotkey(Hotkey::FastForward), true);
+        states.update(Action::Hotkey(Hotkey::Screenshot), true);
+        states.update(Action::Hotkey(Hotkey::Screenshot), false);
+
+        let taken = states.take();
+        assert_eq!(taken.to_u16(), 1 << 3);
+        assert!(taken.fast_forward && taken.screenshot);
+
+        // held keys stay down, hotkeys fire once
+        let taken = states.take();
+        assert!(taken.start && taken.fast_forward && !taken.screenshot);
+
+        states.update(Action::Key(1 << 3), false);
+        assert_eq!(states.take().to_u16(), 0);
+    }
+
+    #[test]
+    fn test_input_states_tilt() {
+        use super::bindings::{Action, Tilt};
+        use super::InputStates;
+
+        let mut states = InputStates::new();
+        states.pointer_tilt = Some((100, -200));
+        assert_eq!(states.tilt(), (100, -200));
+
+        // keys win over the mouse
+        states.update(Action::Tilt(Tilt::Left), true);
+        states.update(Action::Tilt(Tilt::Down), true);
+        assert_eq!(states.tilt(), (-i16::MAX, i16::MAX));
+        states.update(Action::Tilt(Tilt::Right), true);
+        assert_eq!(states.tilt(), (0, i16::MAX));
+    }
 }
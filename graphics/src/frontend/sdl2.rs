@@ -2,24 +2,55 @@ extern crate sdl2;
 use sdl2::IntegerOrSdlError::*;
 
 use sdl2::{
+    controller::{Axis, GameController},
     event::Event,
+    haptic::Haptic,
+    mouse::MouseButton,
     pixels::Color,
     render::WindowCanvas,
-    EventPump, Sdl, VideoSubsystem,
+    EventPump, GameControllerSubsystem, HapticSubsystem, JoystickSubsystem, Sdl, VideoSubsystem,
 };
 
+use std::collections::{HashMap, HashSet};
+
 #[cfg(feature = "vulkan")]
 extern crate vulkano;
 
 use super::*;
+use super::bindings::Bindings;
 
 pub struct Frontend {
     context: Sdl,
     video: VideoSubsystem,
     canvas: WindowCanvas,
     event_pump: EventPump,
+    joystick: JoystickSubsystem,
+    game_controller: GameControllerSubsystem,
+    /// Missing where SDL has no force feedback support
+    haptic: Option<HapticSubsystem>,
+
+    bindings: Bindings,
+    states: InputStates,
+    /// Connected controllers by joystick instance id
+    controllers: HashMap<i32, Controller>,
+}
+
+/// A connected game controller
+struct Controller {
+    // closes the controller when dropped
+    _controller: GameController,
+    guid: String,
+    /// Buttons and axis directions currently held
+    held: HashSet<String>,
+    /// Rumble of the controller, when it has it
+    haptic: Option<Haptic>,
 }
 
+/// Rumble strength, out of 1
+const RUMBLE_STRENGTH: f32 = 0.75;
+/// SDL_HAPTIC_INFINITY, the rumble goes on until stopped
+const RUMBLE_DURATION: u32 = u32::MAX;
+
 impl Frontend {
     pub fn setup(scale: u32) -> Result<Self, String> {
         let context = sdl2::init()?;
@@ -43,6 +74,10 @@ impl Frontend {
             Err(SdlError(error)) => return Err(error),
         };
         let event_pump = context.event_pump()?;
+        // controllers that are already plugged in are added through events too
+        let joystick = context.joystick()?;
+        let game_controller = context.game_controller()?;
+        let haptic = context.haptic().ok();
 
         // Initialise the window
         // TODO: Draw the logo to act as loading splash?
@@ -56,9 +91,24 @@ impl Frontend {
             video,
             canvas,
             event_pump,
+            joystick,
+            game_controller,
+            haptic,
+
+            bindings: Bindings::default(),
+            states: InputStates::new(),
+            controllers: HashMap::new(),
         })
     }
 
+    pub fn set_title(&mut self, title: &str) {
+        self.canvas.window_mut().set_title(title).ok();
+    }
+
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.bindings = bindings;
+    }
+
     /// Set the pixel at (x,y) to colour
     pub fn draw_pixel(&mut self, position: (usize, usize), colour: RGBA) {
         self.canvas.set_draw_color::<(u8, u8, u8)>(colour.into());
@@ -73,15 +123,145 @@ impl Frontend {
 
     /// Get input from the user
     pub fn get_input(&mut self) -> InputStates {
-        let mut states = InputStates::new();
-        for event in self.event_pump.poll_iter() {
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+        for event in events {
             match event {
-                Event::Quit {..} => states.exit = true,
-                _ => ()
+                Event::Quit {..} => self.states.exit = true,
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                    self.key(&keycode.name(), true)
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } => self.key(&keycode.name(), false),
+
+                Event::ControllerDeviceAdded { which, .. } => self.add_controller(which),
+                Event::ControllerDeviceRemoved { which, .. } => self.remove_controller(which),
+                Event::ControllerButtonDown { which, button, .. } => {
+                    self.controller_input(which, button.string(), true)
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    self.controller_input(which, button.string(), false)
+                }
+                Event::ControllerAxisMotion { which, axis, value, .. } => {
+                    self.axis_motion(which, axis, value)
+                }
+
+                // the mouse tilts the GBA while its left button is held
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                    self.pointer_tilt(x, y)
+                }
+                Event::MouseMotion { mousestate, x, y, .. } if mousestate.left() => {
+                    self.pointer_tilt(x, y)
+                }
+                Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => {
+                    self.states.pointer_tilt = None
+                }
+                _ => (),
+            }
+        }
+
+        self.states.take()
+    }
+
+    /// Turn the rumble of the controllers on or off
+    pub fn set_rumble(&mut self, on: bool) {
+        for haptic in self.controllers.values_mut().filter_map(|controller| controller.haptic.as_mut()) {
+            if on {
+                haptic.rumble_play(RUMBLE_STRENGTH, RUMBLE_DURATION);
+            } else {
+                haptic.rumble_stop();
+            }
+        }
+    }
+
+    /// Tilt from where the mouse is, from the centre of the window to its edges
+    fn pointer_tilt(&mut self, x: i32, y: i32) {
+        let (width, height) = self.canvas.window().size();
+        let axis = |position: i32, size: u32| {
+            let half = (size as i32 / 2).max(1);
+            let max = i16::MAX as i32;
+            ((position - half) * max / half).clamp(-max, max) as i16
+        };
+        self.states.pointer_tilt = Some((axis(x, width), axis(y, height)));
+    }
+
+    fn key(&mut self, name: &str, pressed: bool) {
+        if let Some(action) = self.bindings.action(name) {
+            self.states.update(action, pressed);
+        }
+    }
+
+    fn add_controller(&mut self, index: u32) {
+        let controller = match self.game_controller.open(index) {
+            Ok(controller) => controller,
+            Err(error) => return eprintln!("Unable to open controller {}: {}", index, error),
+        };
+        let guid = match self.joystick.device_guid(index) {
+            Ok(guid) => guid.string(),
+            Err(_) => String::new(),
+        };
+        eprintln!("Connected {} ({})", controller.name(), guid);
+        let haptic = self
+            .haptic
+            .as_ref()
+            .and_then(|haptic| haptic.open_from_joystick_id(index).ok());
+
+        let id = controller.instance_id();
+        let controller = Controller {
+            _controller: controller,
+            guid,
+            held: HashSet::new(),
+            haptic,
+        };
+        self.controllers.insert(id, controller);
+    }
+
+    /// Releases what the controller was holding, so no key stays stuck down
+    fn remove_controller(&mut self, id: i32) {
+        if let Some(controller) = self.controllers.remove(&id) {
+            let bindings = self.bindings.controller(&controller.guid);
+            for input in controller.held.iter() {
+                if let Some(action) = bindings.action(input) {
+                    self.states.update(action, false);
+                }
             }
         }
+    }
 
-        states
+    /// A button or axis direction of a controller went down or up
+    fn controller_input(&mut self, id: i32, input: String, pressed: bool) {
+        let controller = match self.controllers.get_mut(&id) {
+            Some(controller) => controller,
+            None => return,
+        };
+        let changed = if pressed {
+            controller.held.insert(input.clone())
+        } else {
+            controller.held.remove(&input)
+        };
+        if !changed {
+            return;
+        }
+        let action = self.bindings.controller(&controller.guid).action(&input);
+        if let Some(action) = action {
+            self.states.update(action, pressed);
+        }
+    }
+
+    /// Sticks are split into a direction for each side of the deadzone, triggers only go one way
+    fn axis_motion(&mut self, id: i32, axis: Axis, value: i16) {
+        let deadzone = match self.controllers.get(&id) {
+            Some(controller) => self.bindings.controller(&controller.guid).deadzone,
+            None => return,
+        };
+        let name = axis.string();
+        match axis {
+            Axis::TriggerLeft | Axis::TriggerRight => {
+                self.controller_input(id, name, value > deadzone)
+            }
+            _ => {
+                self.controller_input(id, format!("{}-", name), value < -deadzone);
+                self.controller_input(id, format!("{}+", name), value > deadzone);
+            }
+        }
     }
 }
 
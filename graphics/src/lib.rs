@@ -2,6 +2,10 @@ use memory;
 
 mod frontend;
 use frontend::*;
+pub use frontend::{bindings, InputStates};
+
+pub mod dmg;
+pub use dmg::ScreenMode;
 
 /// Indicates when an interrupt is fired
 pub struct Interrupt {
@@ -28,6 +32,18 @@ impl Interrupt {
     pub fn hblank(&mut self) {
         self.hblank = true
     }
+
+    /// Sets the fired interrupts in IF
+    pub fn request(&self, memory: &mut memory::MMU) {
+        use memory::io::interrupts;
+
+        let flags = (self.vblank as u16 * interrupts::VBLANK)
+            | (self.hblank as u16 * interrupts::HBLANK)
+            | (self.vcounter as u16 * interrupts::VCOUNTER);
+        if flags != 0 {
+            memory.request_interrupt(flags);
+        }
+    }
 }
 
 /// Propogates meta state to the CPU module
@@ -60,13 +76,68 @@ pub struct Display {
 
     // There is no register for this so count here
     hcount: usize,
+
+    /// Input from the last poll
+    input: InputStates,
+    /// What was last drawn, kept for screenshots
+    frame: Box<[RGBA]>,
 }
 
 impl Display {
     pub fn init(scale: u32) -> Result<Self, String> {
         let frontend = frontend::Frontend::setup(scale)?;
 
-        Ok(Self { frontend, hcount: 0 })
+        Ok(Self {
+            frontend,
+            hcount: 0,
+            input: InputStates::new(),
+            frame: vec![RGBA(0); SCREEN_WIDTH * SCREEN_HEIGHT].into_boxed_slice(),
+        })
+    }
+
+    /// Use these key bindings instead of the defaults
+    pub fn set_bindings(&mut self, bindings: bindings::Bindings) {
+        self.frontend.set_bindings(bindings)
+    }
+
+    /// Also read the keyboard from the terminal in MEDIUMRAW mode, for when the input devices
+    /// cannot be opened
+    #[cfg(feature = "fbdev")]
+    pub fn use_raw_keyboard(&mut self) -> Result<(), String> {
+        self.frontend.use_raw_keyboard()
+    }
+
+    /// Poll the frontend for input, also done by every cycle.
+    /// Hotkeys that fire once are only seen by the poll that follows the key press.
+    pub fn poll_input(&mut self) -> &InputStates {
+        self.input = self.frontend.get_input();
+        &self.input
+    }
+
+    /// Input from the last poll
+    pub fn input(&self) -> &InputStates {
+        &self.input
+    }
+
+    fn draw_pixel(&mut self, position: (usize, usize), colour: RGBA) {
+        self.frame[position.1 * SCREEN_WIDTH + position.0] = colour;
+        self.frontend.draw_pixel(position, colour)
+    }
+
+    /// Save what is on the screen as a binary PPM image
+    pub fn screenshot(&self, path: &std::path::Path) -> Result<(), String> {
+        let mut image = format!("P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT).into_bytes();
+        for &pixel in self.frame.iter() {
+            let (red, green, blue): (u8, u8, u8) = pixel.into();
+            image.extend_from_slice(&[red, green, blue]);
+        }
+        std::fs::write(path, image)
+            .map_err(|error| format!("Unable to save {}: {}", path.display(), error))
+    }
+
+    /// Show the name of the running game, where the frontend has a title bar
+    pub fn set_title(&mut self, title: &str) {
+        self.frontend.set_title(title)
     }
 
     /// A graphics cycle is done every 4 cpu cycles
@@ -74,13 +145,18 @@ impl Display {
         let mut interrupts = Interrupt::none();
 
         // Get user input
-        let input = self.frontend.get_input();
+        let keys = self.poll_input().to_u16();
+        memory.set_keys(keys);
+        let (x, y) = self.input.tilt();
+        memory.set_tilt(x, y);
+        if let Some(on) = memory.take_rumble() {
+            self.frontend.set_rumble(on)
+        }
 
         // Only bits 0-7 are used of this register
         let mut vcount = memory.load8(registers::VCOUNT) as usize;
-        let vcount_setting = (memory.load8(registers::DISPSTAT) >> 7) as usize;
-        let vblank = vcount > 160;
-        let hblank = self.hcount > 240;
+        let vblank = vcount >= 160;
+        let hblank = self.hcount >= SCREEN_WIDTH;
 
         if !vblank && !hblank {
             // Generate draw closure based on video mode
@@ -95,55 +171,56 @@ impl Display {
                 _ => unreachable!(),
             };
 
-            self.frontend.draw_pixel((self.hcount, vcount), pixel.into())
+            self.draw_pixel((self.hcount, vcount), pixel.into())
         }
 
-        // Increment the hcount
+        // Increment the hcount and VCOUNT
+        let dispstat = memory.io_raw(registers::DISPSTAT);
         self.hcount = if self.hcount < 307 {
-            // Set hblank flag
-            memory.store8(
-                registers::DISPSTAT,
-                memory.load8(registers::DISPSTAT) | 0b10u8,
-            );
-            // Check if hblank IRQ is set
-            if memory.load8(registers::DISPSTAT) & 0b10000u8 != 0 {
-                interrupts.hblank()
-            };
             self.hcount + 1
         } else {
-            // Unset hblank flag
-            memory.store8(
-                registers::DISPSTAT,
-                memory.load8(registers::DISPSTAT) & !0b10u8,
-            );
-
-            // Increment or reset the VCOUNT register
-            vcount = if vcount < 227 {
-                // Set vblank flag
-                memory.store8(
-                    registers::DISPSTAT,
-                    memory.load8(registers::DISPSTAT) | 0b1u8,
-                );
-                // Check if vblank IRQ is set
-                if memory.load8(registers::DISPSTAT) & 0b1000u8 != 0 {
-                    interrupts.vblank()
-                };
-                vcount + 1
-            } else {
-                // Unset vblank flag
-                memory.store8(
-                    registers::DISPSTAT,
-                    memory.load8(registers::DISPSTAT) & !0b1u8,
-                );
-                0
-            };
-
+            vcount = if vcount < 227 { vcount + 1 } else { 0 };
             0
         };
 
-        memory.store8(registers::VCOUNT, vcount as _);
+        // The status bits and VCOUNT are read only for the CPU
+        let mut status = dispstat & !0b111;
+        if self.hcount >= SCREEN_WIDTH {
+            status |= 0b10;
+        }
+        if (160..227).contains(&vcount) {
+            status |= 0b1;
+        }
+        if vcount == (dispstat >> 8) as usize {
+            status |= 0b100;
+        }
+        memory.set_io_raw(registers::DISPSTAT, status);
+        memory.set_io_raw(registers::VCOUNT, vcount as _);
+
+        // IRQs and DMAs fire when a flag gets set
+        let raised = status & !dispstat;
+        if raised & 0b1 != 0 {
+            memory.dma_trigger(memory::dma::Timing::VBlank);
+        }
+        if raised & 0b10 != 0 {
+            // no HBlank DMA during VBlank
+            if vcount < 160 {
+                memory.dma_trigger(memory::dma::Timing::HBlank);
+            }
+            memory.dma_video_capture(vcount as u16);
+        }
+        if raised & 0b1 != 0 && dispstat & 0b1000 != 0 {
+            interrupts.vblank()
+        }
+        if raised & 0b10 != 0 && dispstat & 0b1_0000 != 0 {
+            interrupts.hblank()
+        }
+        if raised & 0b100 != 0 && dispstat & 0b10_0000 != 0 {
+            interrupts.vcounter()
+        }
+        interrupts.request(memory);
 
-        (if input.exit { State::Exited } else { State::Running }, interrupts)
+        (if self.input.exit { State::Exited } else { State::Running }, interrupts)
     }
 }
 
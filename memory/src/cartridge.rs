@@ -0,0 +1,165 @@
+// GBA cartridges and the header at the start of their ROM.
+// Based on https://problemkaputt.de/gbatek.htm#gbacartridgeheader
+
+pub mod patch;
+
+use std::path::{Path, PathBuf};
+
+/// Size of the header, the game starts after it
+pub const HEADER_SIZE: usize = 0xC0;
+
+/// Compressed Nintendo logo the BIOS checks before booting a cartridge
+const NINTENDO_LOGO: [u8; 156] = [
+    0x24, 0xFF, 0xAE, 0x51, 0x69, 0x9A, 0xA2, 0x21, 0x3D, 0x84, 0x82, 0x0A, 0x84, 0xE4, 0x09, 0xAD,
+    0x11, 0x24, 0x8B, 0x98, 0xC0, 0x81, 0x7F, 0x21, 0xA3, 0x52, 0xBE, 0x19, 0x93, 0x09, 0xCE, 0x20,
+    0x10, 0x46, 0x4A, 0x4A, 0xF8, 0x27, 0x31, 0xEC, 0x58, 0xC7, 0xE8, 0x33, 0x82, 0xE3, 0xCE, 0xBF,
+    0x85, 0xF4, 0xDF, 0x94, 0xCE, 0x4B, 0x09, 0xC1, 0x94, 0x56, 0x8A, 0xC0, 0x13, 0x72, 0xA7, 0xFC,
+    0x9F, 0x84, 0x4D, 0x73, 0xA3, 0xCA, 0x9A, 0x61, 0x58, 0x97, 0xA3, 0x27, 0xFC, 0x03, 0x98, 0x76,
+    0x23, 0x1D, 0xC7, 0x61, 0x03, 0x04, 0xAE, 0x56, 0xBF, 0x38, 0x84, 0x00, 0x40, 0xA7, 0x0E, 0xFD,
+    0xFF, 0x52, 0xFE, 0x03, 0x6F, 0x95, 0x30, 0xF1, 0x97, 0xFB, 0xC0, 0x85, 0x60, 0xD6, 0x80, 0x25,
+    0xA9, 0x63, 0xBE, 0x03, 0x01, 0x4E, 0x38, 0xE2, 0xF9, 0xA2, 0x34, 0xFF, 0xBB, 0x3E, 0x03, 0x44,
+    0x78, 0x00, 0x90, 0xCB, 0x88, 0x11, 0x3A, 0x94, 0x65, 0xC0, 0x7C, 0x63, 0x87, 0xF0, 0x3C, 0xAF,
+    0xD6, 0x25, 0xE4, 0x8B, 0x38, 0x0A, 0xAC, 0x72, 0x21, 0xD4, 0xF8, 0x07,
+];
+
+/// Metadata from the cartridge header
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    /// Address the branch at the start of the ROM jumps to
+    pub entry_point: u32,
+    pub logo_valid: bool,
+    /// Up to 12 uppercase characters
+    pub title: String,
+    /// 4 characters, the last one is the region
+    pub game_code: String,
+    pub maker_code: String,
+    pub version: u8,
+    pub checksum: u8,
+}
+
+/// Reads a fixed size text field, padded with zeroes
+fn text(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| byte as char)
+        .collect()
+}
+
+/// Complement check of the bytes from the title to the version
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0xA0..0xBD]
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_sub(byte))
+        .wrapping_sub(0x19)
+}
+
+/// Value of the first of the library strings found in the ROM, the Nintendo SDK libraries
+/// leave their names there word aligned. Earlier entries win at the same offset.
+pub fn find_library<T: Copy>(rom: &[u8], ids: &[(&[u8], T)]) -> Option<T> {
+    (0..rom.len()).step_by(4).find_map(|offset| {
+        ids.iter()
+            .find(|(id, _)| rom[offset..].starts_with(id))
+            .map(|&(_, value)| value)
+    })
+}
+
+impl Header {
+    pub fn parse(rom: &[u8]) -> Result<Self, String> {
+        if rom.len() < HEADER_SIZE {
+            return Err("ROM is too small to hold a GBA cartridge header".to_string());
+        }
+
+        // a B instruction, offsets are in words from 8 bytes ahead
+        let branch = u32::from_le_bytes([rom[0], rom[1], rom[2], rom[3]]);
+        let offset = ((branch << 8) as i32 >> 6) as u32;
+        let entry_point = crate::base_addrs::CART0_ADDR as u32 + 8 + offset;
+
+        Ok(Self {
+            entry_point,
+            logo_valid: rom[0x04..0xA0] == NINTENDO_LOGO[..],
+            title: text(&rom[0xA0..0xAC]),
+            game_code: text(&rom[0xAC..0xB0]),
+            maker_code: text(&rom[0xB0..0xB2]),
+            version: rom[0xBC],
+            checksum: rom[0xBD],
+        })
+    }
+
+    /// Problems that would stop a real GBA from booting the cartridge
+    pub fn problems(&self, rom: &[u8]) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.logo_valid {
+            problems.push("the Nintendo logo is corrupted".to_string());
+        }
+        if rom[0xB2] != 0x96 {
+            problems.push(format!(
+                "the fixed value is {:#04x} instead of 0x96",
+                rom[0xB2]
+            ));
+        }
+        let checksum = header_checksum(rom);
+        if self.checksum != checksum {
+            problems.push(format!(
+                "the header checksum is {:#04x} instead of {:#04x}",
+                self.checksum, checksum
+            ));
+        }
+        problems
+    }
+}
+
+/// A ROM loaded from a file, with its parsed header
+#[derive(Clone)]
+pub struct Cartridge {
+    pub header: Header,
+    pub rom: Vec<u8>,
+    pub path: PathBuf,
+}
+
+impl Cartridge {
+    /// Parse the header of rom, malformed headers are only warned about as the game may still run
+    pub fn new(rom: Vec<u8>, path: PathBuf) -> Result<Self, String> {
+        let header = Header::parse(&rom)?;
+        for problem in header.problems(&rom) {
+            eprintln!("{}: {}", path.display(), problem);
+        }
+        Ok(Self { header, rom, path })
+    }
+
+    /// Load a ROM, applying the given patch or else a patch with the same name as the ROM.
+    /// Patches are applied in memory, the ROM file is left as it is.
+    pub fn load(path: &Path, patch: Option<&Path>) -> Result<Self, String> {
+        let read = |path: &Path| {
+            std::fs::read(path)
+                .map_err(|error| format!("Unable to read {}: {}", path.display(), error))
+        };
+
+        let mut rom = read(path)?;
+        let patch = patch
+            .map(Path::to_path_buf)
+            .or_else(|| patch::find_for(path));
+        if let Some(patch) = patch {
+            rom = patch::apply(&read(&patch)?, &rom)
+                .map_err(|error| format!("{}: {}", patch.display(), error))?;
+        }
+        Self::new(rom, path.to_path_buf())
+    }
+
+    /// Name to show for the game, the file name when the header has no title
+    pub fn name(&self) -> String {
+        if self.header.title.trim().is_empty() {
+            let stem = self.path.file_stem().unwrap_or_default();
+            stem.to_string_lossy().into_owned()
+        } else {
+            self.header.title.clone()
+        }
+    }
+
+    /// The .sav file kept next to the ROM
+    pub fn save_path(&self) -> PathBuf {
+        crate::save::Save::path_for(&self.path)
+    }
+}
+
+pub mod tests;
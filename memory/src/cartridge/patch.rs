@@ -0,0 +1,284 @@
+// ROM patches in the IPS, UPS and BPS formats, applied to a copy of the ROM when it is loaded.
+// Based on https://zerosoft.zophar.net/ips.php and byuu's UPS and BPS specifications
+
+use crate::sizes::CART0_SIZE;
+
+use std::path::{Path, PathBuf};
+
+/// Extensions of the patch files looked for next to a ROM
+pub const EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+/// A patch file with the same name as the ROM, if there is one
+pub fn find_for(rom_path: &Path) -> Option<PathBuf> {
+    EXTENSIONS
+        .iter()
+        .map(|extension| rom_path.with_extension(extension))
+        .find(|path| path.is_file())
+}
+
+/// Applies a patch to rom, the format is picked from its magic
+pub fn apply(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(patch, rom)
+    } else if patch.starts_with(b"UPS1") {
+        apply_ups(patch, rom)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(patch, rom)
+    } else {
+        Err("Unknown patch format".to_string())
+    }
+}
+
+/// CRC-32 as used by zip, UPS and BPS
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+/// Reads through the patch, failing instead of running past its end
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Self {
+        Self { data, position }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .position
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or_else(|| "Patch ends unexpectedly".to_string())?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Big endian number of len bytes, as used by IPS
+    fn big_endian(&mut self, len: usize) -> Result<usize, String> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+
+    /// Variable length number of UPS and BPS, 7 bits per byte with the last one flagged
+    fn number(&mut self) -> Result<usize, String> {
+        let invalid = || "Patch has an invalid number".to_string();
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = self.byte()?;
+            value = ((byte & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|bits| value.checked_add(bits))
+                .ok_or_else(invalid)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or_else(invalid)?;
+            value = value.checked_add(shift).ok_or_else(invalid)?;
+        }
+    }
+}
+
+/// Records of offset and bytes, or offset and a run of one byte
+fn apply_ips(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+    let mut target = rom.to_vec();
+    let mut reader = Reader::new(patch, 5);
+
+    loop {
+        if reader.data[reader.position..].starts_with(b"EOF") {
+            reader.position += 3;
+            break;
+        }
+
+        let offset = reader.big_endian(3)?;
+        let (len, value) = match reader.big_endian(2)? {
+            0 => (reader.big_endian(2)?, Some(reader.byte()?)),
+            len => (len, None),
+        };
+        if target.len() < offset + len {
+            target.resize(offset + len, 0);
+        }
+
+        match value {
+            Some(value) => {
+                for byte in target[offset..offset + len].iter_mut() {
+                    *byte = value;
+                }
+            }
+            None => target[offset..offset + len].copy_from_slice(reader.bytes(len)?),
+        }
+    }
+
+    // some patches end with the size to truncate the ROM to
+    if let Ok(size) = reader.big_endian(3) {
+        target.truncate(size);
+    }
+
+    Ok(target)
+}
+
+/// Source, target and patch checksums at the end of UPS and BPS patches
+fn footer(patch: &[u8]) -> Result<(u32, u32), String> {
+    if patch.len() < 16 {
+        return Err("Patch ends unexpectedly".to_string());
+    }
+    let body = patch.len() - 12;
+    let checksum =
+        |at: usize| u32::from_le_bytes([patch[at], patch[at + 1], patch[at + 2], patch[at + 3]]);
+
+    if crc32(&patch[..patch.len() - 4]) != checksum(body + 8) {
+        return Err("Patch is corrupted".to_string());
+    }
+    Ok((checksum(body), checksum(body + 4)))
+}
+
+fn check_source(rom: &[u8], size: usize, checksum: u32) -> Result<(), String> {
+    if rom.len() != size || crc32(rom) != checksum {
+        return Err("Patch is meant for another ROM".to_string());
+    }
+    Ok(())
+}
+
+/// The patched ROM has to fit in the cartridge space, checked before making room for it
+fn check_target_size(size: usize) -> Result<(), String> {
+    if size > CART0_SIZE {
+        return Err(format!(
+            "Patched ROM would be {} bytes, more than the {} a cartridge holds",
+            size, CART0_SIZE
+        ));
+    }
+    Ok(())
+}
+
+fn check_target(target: &[u8], checksum: u32) -> Result<(), String> {
+    if crc32(target) != checksum {
+        return Err("Patched ROM does not match the expected checksum".to_string());
+    }
+    Ok(())
+}
+
+/// Runs of bytes XORed into the ROM, each after a number of unchanged bytes
+fn apply_ups(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+    let (source_checksum, target_checksum) = footer(patch)?;
+    let end = patch.len() - 12;
+    let mut reader = Reader::new(&patch[..end], 4);
+
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    check_source(rom, source_size, source_checksum)?;
+    check_target_size(target_size)?;
+
+    let mut target = rom.to_vec();
+    target.resize(target_size, 0);
+
+    // bytes past the end of the target are dropped, so the offset can stop at the largest one
+    let mut offset = 0usize;
+    while reader.position < end {
+        offset = offset.saturating_add(reader.number()?);
+        // the run ends with a 0, which leaves its byte unchanged
+        loop {
+            let value = reader.byte()?;
+            if let Some(byte) = target.get_mut(offset) {
+                *byte ^= value;
+            }
+            offset = offset.saturating_add(1);
+            if value == 0 {
+                break;
+            }
+        }
+    }
+
+    check_target(&target, target_checksum)?;
+    Ok(target)
+}
+
+/// Builds the new ROM from copies of the ROM, the patch and what was already built
+fn apply_bps(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+    let (source_checksum, target_checksum) = footer(patch)?;
+    let end = patch.len() - 12;
+    let mut reader = Reader::new(&patch[..end], 4);
+
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+    check_source(rom, source_size, source_checksum)?;
+    check_target_size(target_size)?;
+
+    let out_of_range = || "Patch copies from outside the ROM".to_string();
+    // the copies move relative to where the last one of their kind stopped
+    let relative = |reader: &mut Reader, offset: usize| -> Result<usize, String> {
+        let value = reader.number()?;
+        let distance = value >> 1;
+        if value & 1 != 0 {
+            offset.checked_sub(distance).ok_or_else(out_of_range)
+        } else {
+            offset.checked_add(distance).ok_or_else(out_of_range)
+        }
+    };
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset = 0;
+    let mut target_offset = 0;
+    while reader.position < end {
+        let action = reader.number()?;
+        let len = (action >> 2) + 1;
+        // every action adds to the target, which cannot grow past its size
+        if len > target_size - target.len() {
+            return Err("Patched ROM does not have the expected size".to_string());
+        }
+        match action & 3 {
+            // source read, the bytes at the same offset in the ROM
+            0 => {
+                let at = target.len();
+                let bytes = rom.get(at..at + len).ok_or_else(out_of_range)?;
+                target.extend_from_slice(bytes);
+            }
+            // target read, the bytes follow in the patch
+            1 => target.extend_from_slice(reader.bytes(len)?),
+            // source copy
+            2 => {
+                source_offset = relative(&mut reader, source_offset)?;
+                let bytes = source_offset
+                    .checked_add(len)
+                    .and_then(|end| rom.get(source_offset..end))
+                    .ok_or_else(out_of_range)?;
+                target.extend_from_slice(bytes);
+                source_offset += len;
+            }
+            // target copy, a byte at a time as the copy can overlap itself
+            _ => {
+                target_offset = relative(&mut reader, target_offset)?;
+                for _ in 0..len {
+                    let byte = *target.get(target_offset).ok_or_else(out_of_range)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err("Patched ROM does not have the expected size".to_string());
+    }
+    check_target(&target, target_checksum)?;
+    Ok(target)
+}
+
+pub mod tests;
@@ -0,0 +1,172 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::cartridge::Cartridge;
+
+    fn number(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte | 0x80);
+                return bytes;
+            }
+            bytes.push(byte);
+            value -= 1;
+        }
+    }
+
+    /// Adds the checksums UPS and BPS patches end with
+    fn finish(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let checksum = crc32(&patch);
+        patch.extend_from_slice(&checksum.to_le_bytes());
+        patch
+    }
+
+    fn source() -> Vec<u8> {
+        (0..16).collect()
+    }
+
+    #[test]
+    fn test_patch_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn test_patch_ips() {
+        let mut patch = b"PATCH".to_vec();
+        // 2 bytes at 4
+        patch.extend_from_slice(&[0, 0, 4, 0, 2, 0xAA, 0xBB]);
+        // run of 3 bytes at 0x12, past the end of the ROM
+        patch.extend_from_slice(&[0, 0, 0x12, 0, 0, 0, 3, 0xCC]);
+        patch.extend_from_slice(b"EOF");
+
+        let target = apply(&patch, &source()).unwrap();
+        assert_eq!(target.len(), 0x15);
+        assert_eq!(&target[3..7], &[3, 0xAA, 0xBB, 6]);
+        assert_eq!(&target[0x10..], &[0, 0, 0xCC, 0xCC, 0xCC]);
+
+        // truncated
+        patch.extend_from_slice(&[0, 0, 8]);
+        assert_eq!(apply(&patch, &source()).unwrap().len(), 8);
+
+        assert!(apply(&b"PATCH\0\0\x04\0\x02\xAA"[..], &source()).is_err());
+    }
+
+    #[test]
+    fn test_patch_ups() {
+        let source = source();
+        let mut target = source.clone();
+        target[3] = 0x55;
+        target[4] = 0x66;
+        target.extend_from_slice(&[0x77, 0x88]);
+
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(target.len()));
+        patch.extend(number(3));
+        patch.extend_from_slice(&[3 ^ 0x55, 4 ^ 0x66, 0]);
+        // 16 and 17 are past the source, so XORed with 0
+        patch.extend(number(16 - 6));
+        patch.extend_from_slice(&[0x77, 0x88, 0]);
+        let patch = finish(patch, &source, &target);
+
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+
+        let mut other = source.clone();
+        other[0] = 1;
+        assert!(apply(&patch, &other).is_err());
+        let mut corrupted = patch.clone();
+        corrupted[6] ^= 1;
+        assert!(apply(&corrupted, &source).is_err());
+    }
+
+    #[test]
+    fn test_patch_bps() {
+        let source = source();
+        let mut target = source[..4].to_vec();
+        target.extend_from_slice(&[0xAA, 0xBB]);
+        target.extend_from_slice(&source[8..12]);
+        target.extend_from_slice(&[10, 11, 10, 11]);
+
+        let action = |command: usize, len: usize| number((len - 1) << 2 | command);
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(target.len()));
+        patch.extend(number(3));
+        patch.extend_from_slice(b"abc");
+        patch.extend(action(0, 4));
+        patch.extend(action(1, 2));
+        patch.extend_from_slice(&[0xAA, 0xBB]);
+        patch.extend(action(2, 4));
+        patch.extend(number(8 << 1));
+        // overlapping copy of the last 2 bytes written
+        patch.extend(action(3, 4));
+        patch.extend(number(8 << 1));
+        let patch = finish(patch, &source, &target);
+
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+        assert!(apply(&patch, &source[..15]).is_err());
+        assert!(apply(b"NOTAPATCH", &source).is_err());
+    }
+
+    #[test]
+    fn test_patch_malformed() {
+        let source = source();
+
+        // a number too long to fit
+        let mut patch = b"UPS1".to_vec();
+        patch.extend_from_slice(&[0; 10]);
+        patch.push(0x80);
+        let patch = finish(patch, &source, &source);
+        assert!(apply(&patch, &source).is_err());
+
+        // a target far bigger than a cartridge is not made room for
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(1 << 40));
+        let patch = finish(patch, &source, &source);
+        assert!(apply(&patch, &source).is_err());
+
+        // a copy running past the size of the target
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(source.len()));
+        patch.extend(number(0));
+        patch.extend(number((usize::MAX >> 9) << 2 | 3));
+        patch.extend(number(0));
+        let patch = finish(patch, &source, &source);
+        assert!(apply(&patch, &source).is_err());
+    }
+
+    #[test]
+    fn test_patch_on_load() {
+        let dir = std::env::temp_dir().join(format!("velera-patch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("game.gba");
+        let rom = vec![0; 0x200];
+        std::fs::write(&rom_path, &rom).unwrap();
+
+        // picked up next to the ROM
+        let mut ips = b"PATCH".to_vec();
+        ips.extend_from_slice(&[0, 0x01, 0x00, 0, 1, 0x11]);
+        ips.extend_from_slice(b"EOF");
+        std::fs::write(dir.join("game.ips"), &ips).unwrap();
+        let cartridge = Cartridge::load(&rom_path, None).unwrap();
+        assert_eq!(cartridge.rom[0x100], 0x11);
+
+        // given explicitly
+        let explicit = dir.join("translation.ips");
+        ips[10] = 0x22;
+        std::fs::write(&explicit, &ips).unwrap();
+        let cartridge = Cartridge::load(&rom_path, Some(&explicit)).unwrap();
+        assert_eq!(cartridge.rom[0x100], 0x22);
+
+        assert_eq!(std::fs::read(&rom_path).unwrap(), rom);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
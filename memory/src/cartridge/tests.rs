@@ -0,0 +1,59 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    /// A ROM with a valid header
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x200];
+        // b 0x080000C0
+        rom[0..4].copy_from_slice(&0xEA00_002Eu32.to_le_bytes());
+        rom[0x04..0xA0].copy_from_slice(&NINTENDO_LOGO);
+        rom[0xA0..0xA8].copy_from_slice(b"VELERA\0\0");
+        rom[0xAC..0xB0].copy_from_slice(b"BVLE");
+        rom[0xB0..0xB2].copy_from_slice(b"01");
+        rom[0xB2] = 0x96;
+        rom[0xBC] = 1;
+        rom[0xBD] = header_checksum(&rom);
+        rom
+    }
+
+    #[test]
+    fn test_cartridge_header() {
+        let rom = rom();
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.entry_point, 0x0800_00C0);
+        assert!(header.logo_valid);
+        assert_eq!(header.title, "VELERA");
+        assert_eq!(header.game_code, "BVLE");
+        assert_eq!(header.maker_code, "01");
+        assert_eq!(header.version, 1);
+        assert!(header.problems(&rom).is_empty());
+
+        assert!(Header::parse(&rom[..0xBF]).is_err());
+    }
+
+    #[test]
+    fn test_cartridge_malformed_header() {
+        let mut rom = rom();
+        rom[0x10] ^= 0xFF;
+        rom[0xA0] = b'X';
+        let header = Header::parse(&rom).unwrap();
+        assert!(!header.logo_valid);
+        assert_eq!(header.problems(&rom).len(), 2);
+
+        // still loads
+        let cartridge = Cartridge::new(rom, PathBuf::from("game.gba")).unwrap();
+        assert_eq!(cartridge.name(), "XELERA");
+        assert_eq!(cartridge.save_path(), PathBuf::from("game.sav"));
+    }
+
+    #[test]
+    fn test_cartridge_name_without_title() {
+        let mut rom = rom();
+        for byte in rom[0xA0..0xAC].iter_mut() {
+            *byte = 0;
+        }
+        let cartridge = Cartridge::new(rom, PathBuf::from("roms/homebrew.gba")).unwrap();
+        assert_eq!(cartridge.name(), "homebrew");
+    }
+}
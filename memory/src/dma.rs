@@ -0,0 +1,271 @@
+// The four DMA channels. Transfers run all at once when they start, the CPU is stalled
+// for the cycles returned by run_dma.
+
+use crate::io::interrupts;
+use crate::MMU;
+
+/// Addresses of the DMA registers
+pub mod registers {
+    pub const DMA0SAD: u32 = 0x400_00B0;
+    pub const DMA1SAD: u32 = 0x400_00BC;
+    pub const DMA2SAD: u32 = 0x400_00C8;
+    pub const DMA3SAD: u32 = 0x400_00D4;
+
+    /// Source address of channel n
+    pub const fn source(n: usize) -> u32 {
+        DMA0SAD + n as u32 * 12
+    }
+
+    /// Destination address of channel n
+    pub const fn destination(n: usize) -> u32 {
+        source(n) + 4
+    }
+
+    /// Word count of channel n
+    pub const fn count(n: usize) -> u32 {
+        source(n) + 8
+    }
+
+    /// Control of channel n
+    pub const fn control(n: usize) -> u32 {
+        source(n) + 10
+    }
+}
+
+/// Bits of DMAxCNT_H
+pub mod control {
+    pub const DESTINATION_CONTROL: u16 = 0b11 << 5;
+    pub const SOURCE_CONTROL: u16 = 0b11 << 7;
+    pub const REPEAT: u16 = 1 << 9;
+    pub const WORD: u16 = 1 << 10;
+    pub const START_TIMING: u16 = 0b11 << 12;
+    pub const IRQ: u16 = 1 << 14;
+    pub const ENABLE: u16 = 1 << 15;
+}
+
+/// When an enabled channel starts transferring
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    Immediate,
+    VBlank,
+    HBlank,
+    /// Sound FIFO for DMA1 and DMA2, video capture for DMA3
+    Special,
+}
+
+impl Timing {
+    fn from_control(control: u16) -> Self {
+        match (control & control::START_TIMING) >> 12 {
+            0 => Timing::Immediate,
+            1 => Timing::VBlank,
+            2 => Timing::HBlank,
+            _ => Timing::Special,
+        }
+    }
+}
+
+/// Internal state of a channel, latched from the registers when it gets enabled
+#[derive(Clone, Copy, Default)]
+pub struct Channel {
+    source: u32,
+    destination: u32,
+    count: u32,
+    /// Waiting for the bus
+    pending: bool,
+}
+
+/// Address bits each channel can reach, DMA0 is limited to internal memory
+const SOURCE_MASKS: [u32; 4] = [0x07FF_FFFF, 0x0FFF_FFFF, 0x0FFF_FFFF, 0x0FFF_FFFF];
+const DESTINATION_MASKS: [u32; 4] = [0x07FF_FFFF, 0x07FF_FFFF, 0x07FF_FFFF, 0x0FFF_FFFF];
+
+/// Called on writes to DMAxCNT_H, latches the addresses when the channel gets enabled
+pub(crate) fn control_written(mmu: &mut MMU, addr: u32, old: u16, _: u16, _: u16) {
+    let n = ((addr - registers::DMA0SAD) / 12) as usize;
+    let control = mmu.io_raw(addr);
+
+    if control & control::ENABLE == 0 {
+        mmu.dma[n].pending = false;
+        return;
+    }
+    if old & control::ENABLE != 0 {
+        return;
+    }
+
+    mmu.dma[n] = Channel {
+        source: mmu.io_raw32(registers::source(n)) & SOURCE_MASKS[n],
+        destination: mmu.io_raw32(registers::destination(n)) & DESTINATION_MASKS[n],
+        count: mmu.dma_count(n),
+        pending: Timing::from_control(control) == Timing::Immediate,
+    };
+}
+
+/// Address step for a source or destination control setting
+fn step(setting: u16, size: u32) -> u32 {
+    match setting {
+        1 => size.wrapping_neg(),
+        2 => 0,
+        _ => size,
+    }
+}
+
+impl MMU {
+    /// Starts the enabled channels waiting for a VBlank or HBlank
+    pub fn dma_trigger(&mut self, timing: Timing) {
+        for n in 0..4 {
+            let control = self.io_raw(registers::control(n));
+            if control & control::ENABLE != 0 && Timing::from_control(control) == timing {
+                self.dma[n].pending = true;
+            }
+        }
+    }
+
+    /// A sound FIFO wants more samples, starts the DMA1/2 channel feeding it
+    pub fn dma_sound_fifo(&mut self, fifo: u32) {
+        for n in 1..3 {
+            let control = self.io_raw(registers::control(n));
+            if control & control::ENABLE != 0
+                && Timing::from_control(control) == Timing::Special
+                && self.dma[n].destination == fifo
+            {
+                self.dma[n].pending = true;
+            }
+        }
+    }
+
+    /// Video capture runs DMA3 on lines 2 to 161 and stops it on line 162
+    pub fn dma_video_capture(&mut self, vcount: u16) {
+        let control = self.io_raw(registers::control(3));
+        if control & control::ENABLE == 0 || Timing::from_control(control) != Timing::Special {
+            return;
+        }
+
+        match vcount {
+            2..=161 => self.dma[3].pending = true,
+            162 => self.set_io_raw(registers::control(3), control & !control::ENABLE),
+            _ => (),
+        }
+    }
+
+    /// True when a channel is waiting to transfer
+    pub fn dma_pending(&self) -> bool {
+        self.dma.iter().any(|channel| channel.pending)
+    }
+
+    /// Runs the waiting channels, DMA0 first, and returns the cycles the CPU is stalled for
+    pub fn run_dma(&mut self) -> u32 {
+        let mut cycles = 0;
+        for n in 0..4 {
+            if self.dma[n].pending {
+                cycles += self.transfer(n);
+            }
+        }
+        cycles
+    }
+
+    fn transfer(&mut self, n: usize) -> u32 {
+        let control = self.io_raw(registers::control(n));
+        let timing = Timing::from_control(control);
+        let mut channel = self.dma[n];
+
+        // sound DMA always moves 4 words into the fixed FIFO address
+        let fifo = timing == Timing::Special && (n == 1 || n == 2);
+        let word = fifo || control & control::WORD != 0;
+        let size = if word { 4 } else { 2 };
+        let count = if fifo { 4 } else { channel.count };
+        let destination_control = (control & control::DESTINATION_CONTROL) >> 5;
+        let source_step = step((control & control::SOURCE_CONTROL) >> 7, size);
+        let destination_step = if fifo {
+            0
+        } else {
+            step(destination_control, size)
+        };
+
+        if self.is_eeprom(channel.destination) {
+            self.save.eeprom_dma_started(count);
+        }
+
+        // two internal cycles to start
+        let mut cycles = 2;
+        for i in 0..count {
+            let sequential = i != 0;
+            if word {
+                let value = self.load32(channel.source);
+                self.store32(channel.destination, value);
+            } else {
+                let value = self.load16(channel.source);
+                self.store16(channel.destination, value);
+            }
+            cycles += access_cycles(self, channel.source, word, sequential)
+                + access_cycles(self, channel.destination, word, sequential);
+
+            channel.source = channel.source.wrapping_add(source_step) & SOURCE_MASKS[n];
+            channel.destination =
+                channel.destination.wrapping_add(destination_step) & DESTINATION_MASKS[n];
+        }
+
+        channel.pending = false;
+        if control & control::REPEAT != 0 && timing != Timing::Immediate {
+            channel.count = self.dma_count(n);
+            if destination_control == 3 {
+                channel.destination =
+                    self.io_raw32(registers::destination(n)) & DESTINATION_MASKS[n];
+            }
+        } else {
+            self.set_io_raw(registers::control(n), control & !control::ENABLE);
+        }
+        self.dma[n] = channel;
+
+        if control & control::IRQ != 0 {
+            self.request_interrupt(interrupts::dma(n));
+        }
+
+        cycles
+    }
+
+    /// Number of units to transfer, 0 means the maximum
+    fn dma_count(&self, n: usize) -> u32 {
+        match self.io_raw(registers::count(n)) as u32 {
+            0 if n == 3 => 0x1_0000,
+            0 => 0x4000,
+            count if n == 3 => count,
+            count => count & 0x3FFF,
+        }
+    }
+
+    fn io_raw32(&self, addr: u32) -> u32 {
+        self.io_raw(addr) as u32 | (self.io_raw(addr + 2) as u32) << 16
+    }
+}
+
+/// Cycles of one access by the bus, using the game pak waitstates from WAITCNT
+fn access_cycles(mmu: &MMU, addr: u32, word: bool, sequential: bool) -> u32 {
+    let waitcnt = mmu.io_raw(0x400_0204);
+    // first and second access waitstates of the game pak regions
+    let game_pak = |first: u16, second: u16, second_wait: u32| {
+        let n = [4, 3, 2, 8][((waitcnt >> first) & 0b11) as usize] + 1;
+        let s = if (waitcnt >> second) & 1 != 0 {
+            2
+        } else {
+            second_wait + 1
+        };
+        let access = if sequential { s } else { n };
+        if word {
+            access + s
+        } else {
+            access
+        }
+    };
+
+    match addr >> 24 {
+        0x02 if word => 6,
+        0x02 => 3,
+        0x05 | 0x06 if word => 2,
+        0x08 | 0x09 => game_pak(2, 4, 2),
+        0x0A | 0x0B => game_pak(5, 7, 4),
+        0x0C | 0x0D => game_pak(8, 10, 8),
+        0x0E | 0x0F => [4, 3, 2, 8][(waitcnt & 0b11) as usize] + 1,
+        _ => 1,
+    }
+}
+
+pub mod tests;
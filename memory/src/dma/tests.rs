@@ -0,0 +1,105 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    const EWRAM: u32 = 0x200_0000;
+    const IWRAM: u32 = 0x300_0000;
+
+    fn setup(mmu: &mut MMU, n: usize, source: u32, destination: u32, count: u16) {
+        mmu.store32(registers::source(n), source);
+        mmu.store32(registers::destination(n), destination);
+        mmu.store16(registers::count(n), count);
+    }
+
+    #[test]
+    fn test_dma_immediate_halfwords() {
+        let mut mmu = MMU::new();
+        for i in 0..8 {
+            mmu.store8(EWRAM + i, i as u8 + 1);
+        }
+
+        setup(&mut mmu, 3, EWRAM, IWRAM, 4);
+        mmu.store16(registers::control(3), control::ENABLE | control::IRQ);
+        assert!(mmu.dma_pending());
+
+        let cycles = mmu.run_dma();
+        assert!(cycles > 4);
+        assert!(!mmu.dma_pending());
+        for i in 0..8 {
+            assert_eq!(mmu.load8(IWRAM + i), i as u8 + 1);
+        }
+
+        // the enable bit is cleared and the IRQ requested
+        assert_eq!(mmu.io_raw(registers::control(3)) & control::ENABLE, 0);
+        assert_eq!(mmu.io_raw(0x400_0202), interrupts::DMA3);
+    }
+
+    #[test]
+    fn test_dma_decrementing_words() {
+        let mut mmu = MMU::new();
+        mmu.store32(EWRAM, 0x1122_3344);
+        mmu.store32(EWRAM + 4, 0x5566_7788);
+
+        // source decrements, destination is fixed
+        setup(&mut mmu, 0, EWRAM + 4, IWRAM, 2);
+        mmu.store16(
+            registers::control(0),
+            control::ENABLE | control::WORD | 1 << 7 | 2 << 5,
+        );
+        mmu.run_dma();
+
+        // the last word written was the first one in memory
+        assert_eq!(mmu.load8(IWRAM), 0x44);
+        assert_eq!(mmu.load8(IWRAM + 3), 0x11);
+        assert_eq!(mmu.load8(IWRAM + 4), 0);
+    }
+
+    #[test]
+    fn test_dma_repeat_reloads() {
+        let mut mmu = MMU::new();
+        mmu.store16(EWRAM, 0xABCD);
+
+        // fixed source, increment/reload destination, on every HBlank
+        setup(&mut mmu, 1, EWRAM, IWRAM, 1);
+        mmu.store16(
+            registers::control(1),
+            control::ENABLE | control::REPEAT | 2 << 12 | 2 << 7 | 3 << 5,
+        );
+        assert!(!mmu.dma_pending());
+
+        mmu.dma_trigger(Timing::VBlank);
+        assert!(!mmu.dma_pending());
+
+        for _ in 0..2 {
+            mmu.dma_trigger(Timing::HBlank);
+            mmu.run_dma();
+        }
+        assert_eq!(mmu.load8(IWRAM), 0xCD);
+        assert_eq!(mmu.load8(IWRAM + 2), 0);
+        assert_ne!(mmu.io_raw(registers::control(1)) & control::ENABLE, 0);
+    }
+
+    #[test]
+    fn test_dma_sound_fifo() {
+        let mut mmu = MMU::new();
+        let fifo_a = 0x400_00A0;
+
+        setup(&mut mmu, 1, EWRAM, fifo_a, 0);
+        mmu.store16(
+            registers::control(1),
+            control::ENABLE | control::REPEAT | 3 << 12,
+        );
+
+        mmu.dma_sound_fifo(0x400_00A4);
+        assert!(!mmu.dma_pending());
+        mmu.dma_sound_fifo(fifo_a);
+        assert!(mmu.dma_pending());
+        mmu.run_dma();
+
+        // four words were read, the FIFO holds the last one of the next transfer
+        mmu.dma_sound_fifo(fifo_a);
+        mmu.store32(EWRAM + 28, 0xDEAD_BEEF);
+        mmu.run_dma();
+        assert_eq!(mmu.io_raw(fifo_a), 0xBEEF);
+    }
+}
@@ -0,0 +1,423 @@
+// Game Boy and Game Boy Color memory map, used when running GB cartridges on the LR35902.
+// Based on https://gbdev.io/pandocs/
+
+pub mod mbc;
+
+use mbc::MBC;
+
+pub mod registers {
+    pub const P1: u16 = 0xFF00;
+    pub const SB: u16 = 0xFF01;
+    pub const SC: u16 = 0xFF02;
+    pub const DIV: u16 = 0xFF04;
+    pub const TIMA: u16 = 0xFF05;
+    pub const TMA: u16 = 0xFF06;
+    pub const TAC: u16 = 0xFF07;
+    pub const IF: u16 = 0xFF0F;
+    pub const NR10: u16 = 0xFF10;
+    pub const NR52: u16 = 0xFF26;
+    pub const WAVE_RAM: u16 = 0xFF30;
+    pub const LCDC: u16 = 0xFF40;
+    pub const STAT: u16 = 0xFF41;
+    pub const SCY: u16 = 0xFF42;
+    pub const SCX: u16 = 0xFF43;
+    pub const LY: u16 = 0xFF44;
+    pub const LYC: u16 = 0xFF45;
+    pub const DMA: u16 = 0xFF46;
+    pub const BGP: u16 = 0xFF47;
+    pub const OBP0: u16 = 0xFF48;
+    pub const OBP1: u16 = 0xFF49;
+    pub const WY: u16 = 0xFF4A;
+    pub const WX: u16 = 0xFF4B;
+    pub const KEY1: u16 = 0xFF4D;
+    pub const VBK: u16 = 0xFF4F;
+    pub const HDMA1: u16 = 0xFF51;
+    pub const HDMA2: u16 = 0xFF52;
+    pub const HDMA3: u16 = 0xFF53;
+    pub const HDMA4: u16 = 0xFF54;
+    pub const HDMA5: u16 = 0xFF55;
+    pub const BCPS: u16 = 0xFF68;
+    pub const BCPD: u16 = 0xFF69;
+    pub const OCPS: u16 = 0xFF6A;
+    pub const OCPD: u16 = 0xFF6B;
+    pub const SVBK: u16 = 0xFF70;
+    pub const IE: u16 = 0xFFFF;
+}
+
+/// Bits of IF and IE
+pub mod interrupts {
+    pub const VBLANK: u8 = 0x01;
+    pub const LCD_STAT: u8 = 0x02;
+    pub const TIMER: u8 = 0x04;
+    pub const SERIAL: u8 = 0x08;
+    pub const JOYPAD: u8 = 0x10;
+}
+
+/// Clock of the LR35902 in normal speed mode
+pub const CLOCK_SPEED: u32 = 4_194_304;
+
+#[derive(Clone)]
+pub struct DMG {
+    pub mbc: MBC,
+    /// Running a Game Boy Color cartridge in colour mode
+    pub cgb: bool,
+    /// Sound register writes, in order, since they were last drained by the APU
+    pub sound_writes: Vec<(u16, u8)>,
+
+    // two 8KiB banks on the CGB
+    vram: Box<[u8]>,
+    // eight 4KiB banks on the CGB
+    wram: Box<[u8]>,
+    oam: Box<[u8]>,
+    io: Box<[u8]>,
+    hram: Box<[u8]>,
+    ie: u8,
+    bg_palettes: [u8; 64],
+    obj_palettes: [u8; 64],
+
+    // pressed keys: A, B, Select, Start, Right, Left, Up, Down from bit 0
+    keys: u8,
+    divider: u16,
+    timer_counter: u32,
+    double_speed: bool,
+    hdma_active: bool,
+}
+
+impl Default for DMG {
+    fn default() -> Self {
+        Self::with_mbc(Default::default())
+    }
+}
+
+impl DMG {
+    /// Create the memory map for a cartridge, colour mode is picked from the header
+    pub fn new(rom: Vec<u8>) -> Result<Self, String> {
+        let mbc = MBC::new(rom)?;
+        let mut dmg = Self::with_mbc(mbc);
+        dmg.cgb = dmg.mbc.rom[0x143] & 0x80 != 0;
+        Ok(dmg)
+    }
+
+    fn with_mbc(mbc: MBC) -> Self {
+        let mut dmg = Self {
+            mbc,
+            cgb: false,
+            sound_writes: Vec::new(),
+            vram: vec![0; 0x4000].into_boxed_slice(),
+            wram: vec![0; 0x8000].into_boxed_slice(),
+            oam: vec![0; 0xA0].into_boxed_slice(),
+            io: vec![0; 0x80].into_boxed_slice(),
+            hram: vec![0; 0x7F].into_boxed_slice(),
+            ie: 0,
+            bg_palettes: [0xFF; 64],
+            obj_palettes: [0xFF; 64],
+            keys: 0,
+            divider: 0xABCC,
+            timer_counter: 0,
+            double_speed: false,
+            hdma_active: false,
+        };
+
+        // state left by the boot ROM
+        for &(register, value) in [
+            (registers::P1, 0xCF),
+            (registers::TAC, 0xF8),
+            (registers::IF, 0xE1),
+            (registers::NR52, 0xF1),
+            (registers::LCDC, 0x91),
+            (registers::STAT, 0x85),
+            (registers::BGP, 0xFC),
+            (registers::OBP0, 0xFF),
+            (registers::OBP1, 0xFF),
+            (registers::HDMA5, 0xFF),
+        ]
+        .iter()
+        {
+            dmg.set_io(register, value);
+        }
+
+        dmg
+    }
+
+    /// Reads a byte as seen by the CPU
+    pub fn load8(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => self.mbc.read_rom(addr),
+            0x8000..=0x9FFF => self.vram[self.vram_bank() * 0x2000 + (addr as usize - 0x8000)],
+            0xA000..=0xBFFF => self.mbc.read_ram(addr),
+            0xC000..=0xCFFF => self.wram[addr as usize - 0xC000],
+            0xD000..=0xDFFF => self.wram[self.wram_bank() * 0x1000 + (addr as usize - 0xD000)],
+            // echo RAM
+            0xE000..=0xFDFF => self.load8(addr - 0x2000),
+            0xFE00..=0xFE9F => self.oam[addr as usize - 0xFE00],
+            0xFEA0..=0xFEFF => 0xFF,
+            0xFF00..=0xFF7F => self.load_io(addr),
+            0xFF80..=0xFFFE => self.hram[addr as usize - 0xFF80],
+            registers::IE => self.ie,
+        }
+    }
+
+    /// Writes a byte as the CPU would
+    pub fn store8(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x7FFF => self.mbc.write_rom(addr, value),
+            0x8000..=0x9FFF => {
+                let offset = self.vram_bank() * 0x2000 + (addr as usize - 0x8000);
+                self.vram[offset] = value
+            }
+            0xA000..=0xBFFF => self.mbc.write_ram(addr, value),
+            0xC000..=0xCFFF => self.wram[addr as usize - 0xC000] = value,
+            0xD000..=0xDFFF => {
+                let offset = self.wram_bank() * 0x1000 + (addr as usize - 0xD000);
+                self.wram[offset] = value
+            }
+            0xE000..=0xFDFF => self.store8(addr - 0x2000, value),
+            0xFE00..=0xFE9F => self.oam[addr as usize - 0xFE00] = value,
+            0xFEA0..=0xFEFF => (),
+            0xFF00..=0xFF7F => self.store_io(addr, value),
+            0xFF80..=0xFFFE => self.hram[addr as usize - 0xFF80] = value,
+            registers::IE => self.ie = value,
+        }
+    }
+
+    fn load_io(&self, addr: u16) -> u8 {
+        let raw = self.io(addr);
+        match addr {
+            registers::P1 => {
+                let mut pressed = 0;
+                if raw & 0x10 == 0 {
+                    pressed |= self.keys >> 4;
+                }
+                if raw & 0x20 == 0 {
+                    pressed |= self.keys & 0xF;
+                }
+                0xC0 | raw & 0x30 | !pressed & 0xF
+            }
+            registers::DIV => (self.divider >> 8) as u8,
+            registers::TAC => raw | 0xF8,
+            registers::IF => raw | 0xE0,
+            registers::STAT => raw | 0x80,
+            registers::KEY1 if self.cgb => 0x7E | (self.double_speed as u8) << 7 | raw & 1,
+            registers::VBK if self.cgb => 0xFE | raw,
+            registers::BCPD if self.cgb => {
+                self.bg_palettes[self.io(registers::BCPS) as usize & 0x3F]
+            }
+            registers::OCPD if self.cgb => {
+                self.obj_palettes[self.io(registers::OCPS) as usize & 0x3F]
+            }
+            registers::SVBK if self.cgb => 0xF8 | raw,
+            registers::HDMA5 if self.cgb => raw,
+            0xFF4C..=0xFF7F if !self.cgb => 0xFF,
+            _ => raw,
+        }
+    }
+
+    fn store_io(&mut self, addr: u16, value: u8) {
+        match addr {
+            registers::P1 => self.set_io(addr, value & 0x30),
+            registers::DIV => self.divider = 0,
+            // LY is read only
+            registers::LY => (),
+            registers::STAT => {
+                let mode = self.io(addr) & 0b111;
+                self.set_io(addr, value & 0x78 | mode)
+            }
+            registers::DMA => {
+                self.set_io(addr, value);
+                let source = (value as u16) << 8;
+                for i in 0..0xA0 {
+                    self.oam[i as usize] = self.load8(source + i);
+                }
+            }
+            0xFF10..=0xFF3F => {
+                // the APU is powered down, only NR52 and wave RAM respond
+                if self.io(registers::NR52) & 0x80 == 0
+                    && addr != registers::NR52
+                    && addr < registers::WAVE_RAM
+                {
+                    return;
+                }
+                if addr == registers::NR52 {
+                    let status = self.io(addr) & 0x0F;
+                    self.set_io(addr, value & 0x80 | status);
+                } else {
+                    self.set_io(addr, value);
+                }
+                self.sound_writes.push((addr, value));
+            }
+            _ if !self.cgb => self.set_io(addr, value),
+
+            registers::KEY1 => self.set_io(addr, value & 1),
+            registers::VBK => self.set_io(addr, value & 1),
+            registers::SVBK => self.set_io(addr, value & 0b111),
+            registers::BCPD => {
+                let index = self.io(registers::BCPS);
+                self.bg_palettes[index as usize & 0x3F] = value;
+                if index & 0x80 != 0 {
+                    self.set_io(registers::BCPS, 0x80 | (index + 1) & 0x3F);
+                }
+            }
+            registers::OCPD => {
+                let index = self.io(registers::OCPS);
+                self.obj_palettes[index as usize & 0x3F] = value;
+                if index & 0x80 != 0 {
+                    self.set_io(registers::OCPS, 0x80 | (index + 1) & 0x3F);
+                }
+            }
+            registers::HDMA5 => {
+                if self.hdma_active && value & 0x80 == 0 {
+                    // cancels the running HBlank transfer
+                    self.hdma_active = false;
+                    self.set_io(addr, self.io(addr) | 0x80);
+                } else if value & 0x80 == 0 {
+                    // general purpose transfer, all at once
+                    self.set_io(addr, value);
+                    for _ in 0..=value & 0x7F {
+                        self.hdma_block();
+                    }
+                } else {
+                    self.set_io(addr, value & 0x7F);
+                    self.hdma_active = true;
+                }
+            }
+            _ => self.set_io(addr, value),
+        }
+    }
+
+    /// Copies the next 16 bytes of a VRAM DMA, returns true when the transfer is done
+    fn hdma_block(&mut self) -> bool {
+        let source = (self.io(registers::HDMA1) as u16) << 8 | self.io(registers::HDMA2) as u16;
+        let source = source & 0xFFF0;
+        let dest = (self.io(registers::HDMA3) as u16) << 8 | self.io(registers::HDMA4) as u16;
+        let dest = 0x8000 | dest & 0x1FF0;
+
+        for i in 0..0x10 {
+            let value = self.load8(source.wrapping_add(i));
+            self.store8(0x8000 | (dest + i) & 0x9FFF, value);
+        }
+
+        let source = source.wrapping_add(0x10);
+        let dest = dest.wrapping_add(0x10);
+        self.set_io(registers::HDMA1, (source >> 8) as u8);
+        self.set_io(registers::HDMA2, source as u8);
+        self.set_io(registers::HDMA3, (dest >> 8) as u8);
+        self.set_io(registers::HDMA4, dest as u8);
+
+        let remaining = self.io(registers::HDMA5) & 0x7F;
+        if remaining == 0 {
+            self.set_io(registers::HDMA5, 0xFF);
+            true
+        } else {
+            self.set_io(registers::HDMA5, remaining - 1);
+            false
+        }
+    }
+
+    /// Called by the PPU when a line enters HBlank, runs a block of a pending HBlank DMA
+    pub fn hblank(&mut self) {
+        if self.hdma_active && self.hdma_block() {
+            self.hdma_active = false;
+        }
+    }
+
+    /// Advance the divider and timer by CPU clocks
+    pub fn tick(&mut self, cycles: u32) {
+        self.divider = self.divider.wrapping_add(cycles as u16);
+
+        let control = self.io(registers::TAC);
+        if control & 0b100 == 0 {
+            return;
+        }
+
+        let period = match control & 0b11 {
+            0 => 1024,
+            1 => 16,
+            2 => 64,
+            _ => 256,
+        };
+
+        self.timer_counter += cycles;
+        while self.timer_counter >= period {
+            self.timer_counter -= period;
+            let (tima, overflow) = self.io(registers::TIMA).overflowing_add(1);
+            if overflow {
+                self.set_io(registers::TIMA, self.io(registers::TMA));
+                self.request_interrupt(interrupts::TIMER);
+            } else {
+                self.set_io(registers::TIMA, tima);
+            }
+        }
+    }
+
+    /// Switches CPU speed if it was armed through KEY1, called on STOP
+    pub fn speed_switch(&mut self) -> bool {
+        if !self.cgb || self.io(registers::KEY1) & 1 == 0 {
+            return false;
+        }
+
+        self.double_speed = !self.double_speed;
+        self.set_io(registers::KEY1, 0);
+        self.divider = 0;
+        true
+    }
+
+    /// The CGB double speed mode runs the CPU and timers at twice the clock
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    pub fn request_interrupt(&mut self, flags: u8) {
+        self.set_io(registers::IF, self.io(registers::IF) | flags);
+    }
+
+    /// Update the pressed keys, using the GBA KEYINPUT bit order but active high
+    pub fn set_keys(&mut self, keys: u8) {
+        if keys & !self.keys != 0 {
+            self.request_interrupt(interrupts::JOYPAD);
+        }
+        self.keys = keys;
+    }
+
+    /// Reads an IO register without any of the side effects of the CPU bus
+    pub fn io(&self, addr: u16) -> u8 {
+        self.io[addr as usize & 0x7F]
+    }
+
+    /// Writes an IO register without any of the side effects of the CPU bus
+    pub fn set_io(&mut self, addr: u16, value: u8) {
+        self.io[addr as usize & 0x7F] = value;
+    }
+
+    /// One 8KiB bank of video memory
+    pub fn vram(&self, bank: usize) -> &[u8] {
+        &self.vram[bank * 0x2000..(bank + 1) * 0x2000]
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    /// CGB background palette memory, 8 palettes of 4 BGR555 colours
+    pub fn bg_palettes(&self) -> &[u8; 64] {
+        &self.bg_palettes
+    }
+
+    /// CGB object palette memory, 8 palettes of 4 BGR555 colours
+    pub fn obj_palettes(&self) -> &[u8; 64] {
+        &self.obj_palettes
+    }
+
+    fn vram_bank(&self) -> usize {
+        (self.cgb && self.io(registers::VBK) & 1 != 0) as usize
+    }
+
+    fn wram_bank(&self) -> usize {
+        if self.cgb {
+            (self.io(registers::SVBK) as usize & 0b111).max(1)
+        } else {
+            1
+        }
+    }
+}
+
+pub mod tests;
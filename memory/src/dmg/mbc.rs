@@ -0,0 +1,271 @@
+use std::time::Instant;
+
+/// Memory bank controllers we know how to drive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    None,
+    MBC1,
+    MBC2,
+    MBC3,
+    MBC5,
+}
+
+/// Cartridge ROM and RAM as switched by the memory bank controller.
+#[derive(Clone)]
+pub struct MBC {
+    pub kind: Kind,
+    pub rom: Box<[u8]>,
+    pub ram: Box<[u8]>,
+
+    ram_enabled: bool,
+    rom_bank: usize,
+    ram_bank: usize,
+    // MBC1 banking mode, when set the upper bank bits also switch bank 0 and RAM
+    advanced_banking: bool,
+    rtc: Clock,
+}
+
+impl Default for MBC {
+    fn default() -> Self {
+        Self {
+            kind: Kind::None,
+            rom: vec![0xFF; 0x8000].into_boxed_slice(),
+            ram: Box::new([]),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            advanced_banking: false,
+            rtc: Default::default(),
+        }
+    }
+}
+
+impl MBC {
+    /// Set up the controller described by the cartridge header
+    pub fn new(mut rom: Vec<u8>) -> Result<Self, String> {
+        if rom.len() < 0x150 {
+            return Err("ROM is too small to hold a Game Boy cartridge header".to_string());
+        }
+
+        let kind = match rom[0x147] {
+            0x00 | 0x08 | 0x09 => Kind::None,
+            0x01..=0x03 => Kind::MBC1,
+            0x05 | 0x06 => Kind::MBC2,
+            0x0F..=0x13 => Kind::MBC3,
+            0x19..=0x1E => Kind::MBC5,
+            other => return Err(format!("Unsupported cartridge type {:#04x}", other)),
+        };
+
+        let ram_size = match (kind, rom[0x149]) {
+            // 512 half bytes built into the controller
+            (Kind::MBC2, _) => 0x200,
+            (_, 0x01) => 0x800,
+            (_, 0x02) => 0x2000,
+            (_, 0x03) => 0x8000,
+            (_, 0x04) => 0x2_0000,
+            (_, 0x05) => 0x1_0000,
+            _ => 0,
+        };
+
+        // pad to whole banks so bank masking never lands outside the ROM
+        // (usize::div_ceil needs Rust 1.73)
+        #[allow(clippy::manual_div_ceil)]
+        let banks = ((rom.len() + 0x3FFF) / 0x4000).max(2).next_power_of_two();
+        rom.resize(banks * 0x4000, 0xFF);
+
+        Ok(Self {
+            kind,
+            rom: rom.into_boxed_slice(),
+            ram: vec![0; ram_size].into_boxed_slice(),
+            ..Default::default()
+        })
+    }
+
+    /// Reads from the cartridge ROM, 0x0000-0x7FFF
+    pub fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 {
+            match self.kind {
+                Kind::MBC1 if self.advanced_banking => self.ram_bank << 5,
+                _ => 0,
+            }
+        } else {
+            match self.kind {
+                Kind::MBC1 => self.ram_bank << 5 | self.rom_bank,
+                _ => self.rom_bank,
+            }
+        };
+
+        let offset = (bank * 0x4000 + (addr as usize & 0x3FFF)) & (self.rom.len() - 1);
+        self.rom[offset]
+    }
+
+    /// Writes to the ROM area drive the controller registers
+    pub fn write_rom(&mut self, addr: u16, value: u8) {
+        match (self.kind, addr) {
+            (Kind::None, _) => (),
+
+            (Kind::MBC2, 0x0000..=0x3FFF) => {
+                // address bit 8 picks between the two registers
+                if addr & 0x100 == 0 {
+                    self.ram_enabled = value & 0xF == 0xA;
+                } else {
+                    self.rom_bank = (value as usize & 0xF).max(1);
+                }
+            }
+            (Kind::MBC2, _) => (),
+
+            (_, 0x0000..=0x1FFF) => self.ram_enabled = value & 0xF == 0xA,
+
+            (Kind::MBC1, 0x2000..=0x3FFF) => self.rom_bank = (value as usize & 0x1F).max(1),
+            (Kind::MBC1, 0x4000..=0x5FFF) => self.ram_bank = value as usize & 0b11,
+            (Kind::MBC1, _) => self.advanced_banking = value & 1 != 0,
+
+            (Kind::MBC3, 0x2000..=0x3FFF) => self.rom_bank = (value as usize & 0x7F).max(1),
+            // 0x08-0x0C map the clock registers instead of RAM
+            (Kind::MBC3, 0x4000..=0x5FFF) => self.ram_bank = value as usize,
+            (Kind::MBC3, _) => self.rtc.write_latch(value),
+
+            (Kind::MBC5, 0x2000..=0x2FFF) => self.rom_bank = self.rom_bank & 0x100 | value as usize,
+            (Kind::MBC5, 0x3000..=0x3FFF) => {
+                self.rom_bank = self.rom_bank & 0xFF | (value as usize & 1) << 8
+            }
+            (Kind::MBC5, 0x4000..=0x5FFF) => self.ram_bank = value as usize & 0xF,
+            (Kind::MBC5, _) => (),
+        }
+    }
+
+    /// Reads from the cartridge RAM, 0xA000-0xBFFF
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        match self.kind {
+            // only the lower nibble exists
+            Kind::MBC2 => self.ram[addr as usize & 0x1FF] | 0xF0,
+            Kind::MBC3 if self.ram_bank >= 0x08 => self.rtc.read(self.ram_bank),
+            _ => match self.ram_offset(addr) {
+                Some(offset) => self.ram[offset],
+                None => 0xFF,
+            },
+        }
+    }
+
+    /// Writes to the cartridge RAM, 0xA000-0xBFFF
+    pub fn write_ram(&mut self, addr: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+
+        match self.kind {
+            Kind::MBC2 => self.ram[addr as usize & 0x1FF] = value & 0xF,
+            Kind::MBC3 if self.ram_bank >= 0x08 => self.rtc.write(self.ram_bank, value),
+            _ => {
+                if let Some(offset) = self.ram_offset(addr) {
+                    self.ram[offset] = value;
+                }
+            }
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+
+        let bank = match self.kind {
+            Kind::MBC1 if !self.advanced_banking => 0,
+            _ => self.ram_bank,
+        };
+        Some((bank * 0x2000 + (addr as usize & 0x1FFF)) % self.ram.len())
+    }
+}
+
+/// MBC3 real time clock, counting host time while the emulator runs
+#[derive(Clone)]
+struct Clock {
+    // seconds counted until `since`
+    seconds: u64,
+    since: Instant,
+    halted: bool,
+    // day counter overflow
+    carry: bool,
+    latched: [u8; 5],
+    latch_armed: bool,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self {
+            seconds: 0,
+            since: Instant::now(),
+            halted: false,
+            carry: false,
+            latched: [0; 5],
+            latch_armed: false,
+        }
+    }
+}
+
+impl Clock {
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn now(&self) -> u64 {
+        if self.halted {
+            self.seconds
+        } else {
+            self.seconds + self.since.elapsed().as_secs()
+        }
+    }
+
+    /// Seconds, minutes, hours, day low, day high/flags
+    fn registers(&mut self) -> [u8; 5] {
+        let now = self.now();
+        let days = now / Self::DAY;
+        if days > 0x1FF {
+            self.carry = true;
+        }
+
+        [
+            (now % 60) as u8,
+            (now / 60 % 60) as u8,
+            (now / 3600 % 24) as u8,
+            days as u8,
+            (days >> 8) as u8 & 1 | (self.halted as u8) << 6 | (self.carry as u8) << 7,
+        ]
+    }
+
+    /// Writing 0 then 1 copies the running clock into the readable registers
+    fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 1 {
+            self.latched = self.registers();
+        }
+        self.latch_armed = value == 0;
+    }
+
+    fn read(&self, register: usize) -> u8 {
+        match register {
+            0x08..=0x0C => self.latched[register - 0x08],
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, register: usize, value: u8) {
+        if !(0x08..=0x0C).contains(&register) {
+            return;
+        }
+
+        let mut registers = self.registers();
+        registers[register - 0x08] = value;
+        self.latched[register - 0x08] = value;
+
+        let days = (registers[4] as u64 & 1) << 8 | registers[3] as u64;
+        self.seconds = days * Self::DAY
+            + registers[2] as u64 % 24 * 3600
+            + registers[1] as u64 % 60 * 60
+            + registers[0] as u64 % 60;
+        self.since = Instant::now();
+        self.halted = registers[4] & 0x40 != 0;
+        self.carry = registers[4] & 0x80 != 0;
+    }
+}
@@ -0,0 +1,133 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    /// Builds a ROM with the given cartridge type where every bank starts with its own number
+    fn rom(cartridge_type: u8, banks: usize, ram_size: u8) -> Vec<u8> {
+        let mut rom = vec![0; banks * 0x4000];
+        for bank in 0..banks {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x147] = cartridge_type;
+        rom[0x149] = ram_size;
+        rom
+    }
+
+    #[test]
+    fn test_dmg_mbc1_banking() {
+        let mut dmg = DMG::new(rom(0x03, 64, 0x03)).unwrap();
+        assert_eq!(dmg.load8(0x4000), 1);
+
+        dmg.store8(0x2000, 0x05);
+        assert_eq!(dmg.load8(0x4000), 5);
+
+        // bank 0 can not be selected in the upper area
+        dmg.store8(0x2000, 0x00);
+        assert_eq!(dmg.load8(0x4000), 1);
+
+        // upper bits
+        dmg.store8(0x4000, 0x01);
+        dmg.store8(0x2000, 0x02);
+        assert_eq!(dmg.load8(0x4000), 0x22);
+        assert_eq!(dmg.load8(0x0000), 0);
+        dmg.store8(0x6000, 0x01);
+        assert_eq!(dmg.load8(0x0000), 0x20);
+    }
+
+    #[test]
+    fn test_dmg_cartridge_ram() {
+        let mut dmg = DMG::new(rom(0x03, 4, 0x03)).unwrap();
+
+        // disabled RAM ignores writes
+        dmg.store8(0xA000, 0x12);
+        assert_eq!(dmg.load8(0xA000), 0xFF);
+
+        dmg.store8(0x0000, 0x0A);
+        dmg.store8(0xA000, 0x12);
+        assert_eq!(dmg.load8(0xA000), 0x12);
+
+        dmg.store8(0x6000, 0x01);
+        dmg.store8(0x4000, 0x01);
+        assert_eq!(dmg.load8(0xA000), 0x00);
+    }
+
+    #[test]
+    fn test_dmg_mbc2_ram_nibbles() {
+        let mut dmg = DMG::new(rom(0x06, 16, 0)).unwrap();
+        dmg.store8(0x0000, 0x0A);
+        dmg.store8(0xA001, 0xAB);
+        assert_eq!(dmg.load8(0xA001), 0xFB);
+        // mirrored every 512 bytes
+        assert_eq!(dmg.load8(0xA201), 0xFB);
+
+        dmg.store8(0x0100, 0x03);
+        assert_eq!(dmg.load8(0x4000), 3);
+    }
+
+    #[test]
+    fn test_dmg_mbc5_high_banks() {
+        let mut dmg = DMG::new(rom(0x19, 512, 0)).unwrap();
+        dmg.store8(0x2000, 0x00);
+        assert_eq!(dmg.load8(0x4000), 0);
+
+        dmg.store8(0x2000, 0x03);
+        dmg.store8(0x3000, 0x01);
+        // bank 0x103, the marker only holds the low byte
+        assert_eq!(dmg.load8(0x4000), 0x03);
+        assert_eq!(dmg.mbc.rom[0x103 * 0x4000], 0x03);
+    }
+
+    #[test]
+    fn test_dmg_joypad() {
+        let mut dmg = DMG::default();
+        dmg.set_keys(0b0001_0001); // A and right
+
+        dmg.store8(registers::P1, 0x20);
+        assert_eq!(dmg.load8(registers::P1) & 0xF, 0b1110);
+        dmg.store8(registers::P1, 0x10);
+        assert_eq!(dmg.load8(registers::P1) & 0xF, 0b1110);
+        dmg.store8(registers::P1, 0x30);
+        assert_eq!(dmg.load8(registers::P1) & 0xF, 0b1111);
+
+        assert_ne!(dmg.load8(registers::IF) & interrupts::JOYPAD, 0);
+    }
+
+    #[test]
+    fn test_dmg_timer_interrupt() {
+        let mut dmg = DMG::default();
+        dmg.store8(registers::IF, 0);
+        dmg.store8(registers::TMA, 0xFE);
+        dmg.store8(registers::TIMA, 0xFF);
+        dmg.store8(registers::TAC, 0b101);
+
+        dmg.tick(16);
+        assert_eq!(dmg.load8(registers::TIMA), 0xFE);
+        assert_ne!(dmg.load8(registers::IF) & interrupts::TIMER, 0);
+    }
+
+    #[test]
+    fn test_cgb_banks_and_palettes() {
+        let mut rom = rom(0x00, 2, 0);
+        rom[0x143] = 0x80;
+        let mut dmg = DMG::new(rom).unwrap();
+        assert!(dmg.cgb);
+
+        dmg.store8(registers::SVBK, 3);
+        dmg.store8(0xD000, 0x33);
+        dmg.store8(registers::SVBK, 0);
+        assert_eq!(dmg.load8(0xD000), 0);
+        assert_eq!(dmg.wram[3 * 0x1000], 0x33);
+
+        dmg.store8(registers::VBK, 1);
+        dmg.store8(0x8000, 0x44);
+        assert_eq!(dmg.vram(1)[0], 0x44);
+        assert_eq!(dmg.vram(0)[0], 0);
+
+        // auto incrementing palette index
+        dmg.store8(registers::BCPS, 0x80);
+        dmg.store8(registers::BCPD, 0x1F);
+        dmg.store8(registers::BCPD, 0x00);
+        assert_eq!(&dmg.bg_palettes()[..2], &[0x1F, 0x00]);
+        assert_eq!(dmg.load8(registers::BCPS), 0x82);
+    }
+}
@@ -0,0 +1,202 @@
+// General purpose I/O port of the cartridge, four pins wired to extra hardware such as a
+// real-time clock, a rumble motor or sensors. Its registers sit in the ROM at 0x080000C4-0x080000C9 and are only seen in
+// place of the ROM while the game makes them readable.
+// Based on https://problemkaputt.de/gbatek.htm#gbacartioportgpio
+
+mod gyro;
+pub mod rtc;
+mod rumble;
+mod solar;
+pub use gyro::Gyro;
+pub use rtc::{Clock, Rtc};
+pub use rumble::Rumble;
+pub use solar::SolarSensor;
+
+use crate::cartridge::{self, Header};
+use crate::pages::{self, Page, PAGE_SHIFT};
+use crate::MMU;
+
+/// Addresses of the GPIO registers
+pub mod registers {
+    /// Level of the pins
+    pub const DATA: u32 = 0x800_00C4;
+    /// Bit set for the pins the GBA drives, clear for the ones the cartridge drives
+    pub const DIRECTION: u32 = 0x800_00C6;
+    /// Bit 0 makes the registers readable
+    pub const CONTROL: u32 = 0x800_00C8;
+}
+
+const PINS: u8 = 0b1111;
+
+/// Hardware wired to the GPIO pins
+#[derive(Clone)]
+pub enum Device {
+    Rtc(Rtc),
+    Rumble(Rumble),
+    Solar(SolarSensor),
+    Gyro(Gyro),
+}
+
+impl Device {
+    /// Pins the device drives
+    fn read(&self) -> u8 {
+        match self {
+            Device::Rtc(rtc) => rtc.read(),
+            Device::Rumble(_) => 0,
+            Device::Solar(solar) => solar.read(),
+            Device::Gyro(gyro) => gyro.read(),
+        }
+    }
+
+    /// The GBA drove the pins
+    fn write(&mut self, pins: u8) {
+        match self {
+            Device::Rtc(rtc) => rtc.write(pins),
+            Device::Rumble(rumble) => rumble.write(pins),
+            Device::Solar(solar) => solar.write(pins),
+            Device::Gyro(gyro) => gyro.write(pins),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Gpio {
+    pub devices: Vec<Device>,
+    /// Levels written to the pins, only the ones the GBA drives matter
+    data: u8,
+    direction: u8,
+    readable: bool,
+}
+
+/// The GPIO registers, which are in the ROM region
+pub fn is_register(addr: u32) -> bool {
+    (registers::DATA..registers::CONTROL + 2).contains(&addr)
+}
+
+impl Gpio {
+    pub fn new(devices: Vec<Device>) -> Self {
+        Self {
+            devices,
+            ..Default::default()
+        }
+    }
+
+    /// The registers are seen instead of the ROM
+    pub fn readable(&self) -> bool {
+        self.readable
+    }
+
+    /// Level of the pins, as driven by the GBA or the devices
+    pub fn pins(&self) -> u8 {
+        let driven = self
+            .devices
+            .iter()
+            .fold(0, |pins, device| pins | device.read());
+        self.data & self.direction | driven & !self.direction & PINS
+    }
+
+    /// Reads a register, the address is halfword aligned
+    pub fn read(&self, addr: u32) -> u16 {
+        match addr {
+            registers::DATA => self.pins() as u16,
+            registers::DIRECTION => self.direction as u16,
+            registers::CONTROL => self.readable as u16,
+            _ => 0,
+        }
+    }
+
+    /// Writes a register, the address is halfword aligned. Cartridges without devices have
+    /// nothing behind the registers.
+    pub fn write(&mut self, addr: u32, value: u16) {
+        if self.devices.is_empty() {
+            return;
+        }
+
+        match addr {
+            registers::DATA => self.data = value as u8 & PINS,
+            registers::DIRECTION => self.direction = value as u8 & PINS,
+            registers::CONTROL => self.readable = value & 1 != 0,
+            _ => return,
+        }
+        let pins = self.data & self.direction;
+        for device in self.devices.iter_mut() {
+            device.write(pins);
+        }
+    }
+}
+
+/// Devices of the games that have them, by game code without the region letter. Other
+/// games with a real-time clock are found from the library string.
+fn game_devices(code: &str, clock: Clock) -> Vec<Device> {
+    let rtc = || Device::Rtc(Rtc::new(clock));
+    match code.get(..3).unwrap_or_default() {
+        // Boktai 1, 2 and 3, the sun is measured by the solar sensor
+        "U3I" | "U32" | "U33" => vec![rtc(), Device::Solar(Default::default())],
+        "BKA" => vec![rtc()], // Sennen Kazoku
+        "BR4" => vec![rtc()], // Rockman EXE 4.5: Real Operation
+        // WarioWare: Twisted!, turning the GBA is measured by the gyro
+        "RZW" => vec![
+            Device::Rumble(Default::default()),
+            Device::Gyro(Default::default()),
+        ],
+        "V49" => vec![Device::Rumble(Default::default())], // Drill Dozer
+        _ => Vec::new(),
+    }
+}
+
+/// String the Nintendo SDK RTC library leaves in the ROM
+const RTC_LIBRARY_ID: &[u8] = b"SIIRTC_V";
+
+/// Devices on the GPIO port of a game, from its game code or the library strings in the ROM
+pub fn detect(rom: &[u8], clock: Clock) -> Vec<Device> {
+    let code = Header::parse(rom)
+        .map(|header| header.game_code)
+        .unwrap_or_default();
+    let devices = game_devices(&code, clock);
+    if !devices.is_empty() {
+        return devices;
+    }
+
+    if cartridge::find_library(rom, &[(RTC_LIBRARY_ID, ())]).is_some() {
+        vec![Device::Rtc(Rtc::new(clock))]
+    } else {
+        Vec::new()
+    }
+}
+
+impl MMU {
+    /// Writes reaching the GPIO registers, the address is halfword aligned
+    pub(crate) fn gpio_write(&mut self, addr: u32, value: u16) {
+        self.gpio.write(addr, value);
+
+        // while readable the registers hide the ROM, so their page goes through the region match
+        let page = (registers::DATA >> PAGE_SHIFT) as usize;
+        self.pages[page] = if self.gpio.readable() {
+            Page::Handler
+        } else {
+            pages::map(page << PAGE_SHIFT, self.rom_size)
+        };
+    }
+
+    /// Light falling on the solar sensor, from 0 for darkness to 255 for the brightest sun
+    pub fn set_light(&mut self, level: u8) {
+        for device in self.gpio.devices.iter_mut() {
+            if let Device::Solar(solar) = device {
+                solar.light = level;
+            }
+        }
+    }
+
+    /// The rumble motor turned on or off since the last call, for the frontend to follow
+    pub fn take_rumble(&mut self) -> Option<bool> {
+        self.gpio
+            .devices
+            .iter_mut()
+            .find_map(|device| match device {
+                Device::Rumble(rumble) => rumble.take_change(),
+                _ => None,
+            })
+    }
+}
+
+pub mod tests;
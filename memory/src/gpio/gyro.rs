@@ -0,0 +1,49 @@
+// Pins of the gyro
+/// Raised to take a sample
+const SAMPLE: u8 = 1 << 0;
+/// A bit of the sample is sent on each falling edge
+const CLOCK: u8 = 1 << 1;
+/// Driven by the gyro
+const DATA: u8 = 1 << 2;
+
+/// Sample of the gyro at rest
+const CENTRE: i32 = 0x6C0;
+
+/// Gyro of WarioWare: Twisted!, measuring how fast the GBA turns. A sample is taken when pin 0
+/// is raised then sent over pin 2, MSB first, as pin 1 is clocked.
+#[derive(Clone, Debug, Default)]
+pub struct Gyro {
+    /// Speed the GBA turns at, from -32768 to 32767, positive clockwise
+    pub rotation: i16,
+    /// Bits of the sample left to send, the next one on top
+    sample: u16,
+    output: bool,
+    /// Pins as last driven by the GBA
+    pins: u8,
+}
+
+impl Gyro {
+    /// Pins the gyro drives
+    pub fn read(&self) -> u8 {
+        if self.output {
+            DATA
+        } else {
+            0
+        }
+    }
+
+    /// The GBA drove the pins
+    pub fn write(&mut self, pins: u8) {
+        let falling = self.pins & CLOCK != 0 && pins & CLOCK == 0;
+        self.pins = pins;
+
+        if pins & SAMPLE != 0 {
+            // about 1024 either side of the centre
+            self.sample = (CENTRE + (self.rotation as i32 >> 5)) as u16;
+        }
+        if falling {
+            self.output = self.sample & 0x8000 != 0;
+            self.sample <<= 1;
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Pins of the GPIO port the RTC is wired to
+const SCK: u8 = 1 << 0;
+const SIO: u8 = 1 << 1;
+const CS: u8 = 1 << 2;
+
+// Commands, as their bits are received LSB first
+const RESET: u8 = 0;
+const DATE_TIME: u8 = 2;
+const CONTROL: u8 = 4;
+const TIME: u8 = 6;
+
+/// Bits of the control register
+pub mod control {
+    /// Hours count from 0 to 23 instead of 0 to 11 with the PM flag
+    pub const HOURS_24: u8 = 1 << 6;
+    /// Set when the battery ran out, games then ask for the time to be set
+    pub const POWER_FAILED: u8 = 1 << 7;
+    /// Bits the game can write
+    pub const WRITABLE: u8 = 0b0110_1010;
+}
+
+/// Seconds from the UNIX epoch to 2000-01-01, the earliest time the RTC counts from
+const EPOCH_2000: i64 = 946_684_800;
+const DAY: u64 = 24 * 60 * 60;
+/// The RTC counts years from 2000 to 2099
+const CENTURY: u64 = 36_525 * DAY;
+
+/// Where the RTC gets the time from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Clock {
+    /// Local time of the host
+    Host,
+    /// Always the same time, in seconds since 2000-01-01 00:00:00, for tests and runs that
+    /// must play out the same way
+    Fixed(u64),
+}
+
+impl Clock {
+    /// Seconds since 2000-01-01 00:00:00
+    fn now(self) -> u64 {
+        match self {
+            Clock::Host => {
+                let unix = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_secs() as i64);
+                (unix + utc_offset(unix) - EPOCH_2000).max(0) as u64
+            }
+            Clock::Fixed(seconds) => seconds,
+        }
+    }
+}
+
+/// Seconds local time is ahead of UTC
+#[cfg(unix)]
+fn utc_offset(time: i64) -> i64 {
+    use std::os::raw::{c_char, c_int, c_long};
+
+    /// struct tm of glibc and musl
+    #[repr(C)]
+    struct Tm {
+        fields: [c_int; 9],
+        gmtoff: c_long,
+        zone: *const c_char,
+    }
+
+    extern "C" {
+        fn localtime_r(time: *const c_long, tm: *mut Tm) -> *mut Tm;
+    }
+
+    let mut tm = Tm {
+        fields: [0; 9],
+        gmtoff: 0,
+        zone: std::ptr::null(),
+    };
+    let time = time as c_long;
+    if unsafe { localtime_r(&time, &mut tm) }.is_null() {
+        0
+    } else {
+        tm.gmtoff as i64
+    }
+}
+
+#[cfg(not(unix))]
+fn utc_offset(_: i64) -> i64 {
+    0
+}
+
+fn bcd(value: u64) -> u8 {
+    ((value / 10) << 4 | (value % 10)) as u8
+}
+
+fn from_bcd(value: u8) -> u64 {
+    (value >> 4) as u64 * 10 + (value & 0xF) as u64
+}
+
+// u64::is_multiple_of needs Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+fn is_leap(year: u64) -> bool {
+    // 2000 is a leap year, so every fourth year is one up to 2099
+    year % 4 == 0
+}
+
+fn month_days(year: u64, month: u64) -> u64 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Year, month, day, day of the week, hour, minute and second of a time in seconds since 2000,
+/// in BCD as the RTC sends them
+fn date_time(time: u64, control: u8) -> [u8; 7] {
+    let mut days = time / DAY;
+    // 2000-01-01 was a Saturday, days of the week count from Sunday
+    let weekday = (days + 6) % 7;
+
+    let mut year = 0;
+    while days >= 365 + is_leap(year) as u64 {
+        days -= 365 + is_leap(year) as u64;
+        year += 1;
+    }
+    let mut month = 1;
+    while days >= month_days(year, month) {
+        days -= month_days(year, month);
+        month += 1;
+    }
+
+    let hour = time / 3600 % 24;
+    let pm = if hour >= 12 { 0x80 } else { 0 };
+    let hour = if control & control::HOURS_24 != 0 {
+        hour
+    } else {
+        hour % 12
+    };
+
+    [
+        bcd(year),
+        bcd(month),
+        bcd(days + 1),
+        bcd(weekday),
+        bcd(hour) | pm,
+        bcd(time / 60 % 60),
+        bcd(time % 60),
+    ]
+}
+
+/// Seconds since 2000 of the date and time the RTC sends
+fn seconds(date_time: &[u8; 7], control: u8) -> u64 {
+    let year = from_bcd(date_time[0]).min(99);
+    let month = from_bcd(date_time[1]).clamp(1, 12);
+    let day = from_bcd(date_time[2]).clamp(1, month_days(year, month));
+
+    let mut hour = from_bcd(date_time[4] & 0x3F) % 24;
+    if control & control::HOURS_24 == 0 && date_time[4] & 0x80 != 0 {
+        hour = hour % 12 + 12;
+    }
+
+    let days = (0..year)
+        .map(|year| 365 + is_leap(year) as u64)
+        .sum::<u64>()
+        + (1..month).map(|month| month_days(year, month)).sum::<u64>()
+        + day
+        - 1;
+    days * DAY + hour * 3600 + from_bcd(date_time[5]) % 60 * 60 + from_bcd(date_time[6]) % 60
+}
+
+/// Part of a transfer the RTC is in
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Transfer {
+    /// Receiving the command byte
+    Command,
+    /// Sending the data of a command
+    Read(u8),
+    /// Receiving the data of a command
+    Write(u8),
+    /// The command byte was not one, the rest of the transfer is ignored
+    Ignored,
+}
+
+/// Seiko S-3511 real-time clock, talked to one bit at a time through the GPIO pins.
+/// With CS high, each rising edge of SCK moves a bit over SIO, LSB first. A transfer starts with
+/// a command byte, 0110 then the command and a bit set for reading, followed by its data.
+#[derive(Clone)]
+pub struct Rtc {
+    clock: Clock,
+    /// Seconds the time was set away from the clock
+    offset: i64,
+    control: u8,
+    /// Pins as last driven by the GBA
+    pins: u8,
+    transfer: Transfer,
+    /// Bits received so far, LSB first
+    received: u8,
+    /// Bits received or sent of the current byte
+    bit: u8,
+    /// Data of the command, the date and time or the control register
+    data: Vec<u8>,
+    /// Bytes of data sent or received
+    position: usize,
+    /// Level the RTC drives SIO to while reading
+    output: bool,
+}
+
+impl Rtc {
+    pub fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            offset: 0,
+            control: control::HOURS_24,
+            pins: 0,
+            transfer: Transfer::Command,
+            received: 0,
+            bit: 0,
+            data: Vec::new(),
+            position: 0,
+            output: false,
+        }
+    }
+
+    /// Seconds since 2000-01-01 00:00:00 the RTC is at
+    pub fn now(&self) -> u64 {
+        (self.clock.now() as i64 + self.offset).rem_euclid(CENTURY as i64) as u64
+    }
+
+    /// Set the time, in seconds since 2000-01-01 00:00:00
+    pub fn set(&mut self, time: u64) {
+        self.offset = time as i64 - self.clock.now() as i64;
+    }
+
+    pub fn control(&self) -> u8 {
+        self.control
+    }
+
+    /// Pins the RTC drives
+    pub fn read(&self) -> u8 {
+        if self.output {
+            SIO
+        } else {
+            0
+        }
+    }
+
+    /// The GBA drove the pins
+    pub fn write(&mut self, pins: u8) {
+        let rising = self.pins & SCK == 0 && pins & SCK != 0;
+        let selected = self.pins & CS != 0;
+        self.pins = pins;
+
+        if pins & CS == 0 {
+            self.output = false;
+            return;
+        }
+        if !selected {
+            // a transfer starts
+            self.transfer = Transfer::Command;
+            self.received = 0;
+            self.bit = 0;
+            self.position = 0;
+        }
+        if !rising {
+            return;
+        }
+
+        if let Transfer::Read(_) = self.transfer {
+            // the next bit is put on SIO
+            let byte = self.data.get(self.position).copied().unwrap_or(0);
+            self.output = byte >> self.bit & 1 != 0;
+        } else {
+            self.received |= ((pins & SIO != 0) as u8) << self.bit;
+        }
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.byte_done();
+        }
+    }
+
+    /// A whole byte was sent or received
+    fn byte_done(&mut self) {
+        let byte = self.received;
+        self.received = 0;
+        match self.transfer {
+            Transfer::Command if byte & 0xF != 0b0110 => self.transfer = Transfer::Ignored,
+            Transfer::Command => self.command(byte),
+            Transfer::Read(_) => self.position += 1,
+            Transfer::Write(command) if self.position < self.data.len() => {
+                self.data[self.position] = byte;
+                self.position += 1;
+                if self.position == self.data.len() {
+                    self.written(command);
+                }
+            }
+            Transfer::Write(_) | Transfer::Ignored => (),
+        }
+    }
+
+    fn command(&mut self, byte: u8) {
+        let command = byte >> 4 & 0b111;
+        self.transfer = if byte & 0x80 != 0 {
+            Transfer::Read(command)
+        } else {
+            Transfer::Write(command)
+        };
+        self.data = match command {
+            RESET => {
+                self.control = 0;
+                self.set(0);
+                Vec::new()
+            }
+            DATE_TIME => date_time(self.now(), self.control).to_vec(),
+            TIME => date_time(self.now(), self.control)[4..].to_vec(),
+            CONTROL => vec![self.control],
+            _ => Vec::new(),
+        };
+    }
+
+    /// Every byte of a write command is in
+    fn written(&mut self, command: u8) {
+        match command {
+            DATE_TIME => {
+                let mut date_time = [0; 7];
+                date_time.copy_from_slice(&self.data);
+                self.set(seconds(&date_time, self.control));
+            }
+            TIME => {
+                let mut date_time = date_time(self.now(), self.control);
+                date_time[4..].copy_from_slice(&self.data);
+                self.set(seconds(&date_time, self.control));
+            }
+            CONTROL => {
+                self.control = self.control & !control::WRITABLE | self.data[0] & control::WRITABLE
+            }
+            _ => (),
+        }
+    }
+}
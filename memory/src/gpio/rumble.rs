@@ -0,0 +1,32 @@
+/// Pin of the motor, driven by the GBA
+const MOTOR: u8 = 1 << 3;
+
+/// Rumble motor of Drill Dozer and WarioWare: Twisted!, turned on through pin 3
+#[derive(Clone, Debug, Default)]
+pub struct Rumble {
+    on: bool,
+    /// The motor turned on or off since the frontend was last told
+    changed: bool,
+}
+
+impl Rumble {
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    /// The GBA drove the pins
+    pub fn write(&mut self, pins: u8) {
+        let on = pins & MOTOR != 0;
+        self.changed |= on != self.on;
+        self.on = on;
+    }
+
+    /// The state of the motor when it changed since the last call
+    pub fn take_change(&mut self) -> Option<bool> {
+        if std::mem::take(&mut self.changed) {
+            Some(self.on)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,53 @@
+// Pins of the sensor
+const CLOCK: u8 = 1 << 0;
+const RESET: u8 = 1 << 1;
+/// Low while the sensor is selected
+const SELECT: u8 = 1 << 2;
+/// Driven by the sensor once the counter reached the light level
+const FLAG: u8 = 1 << 3;
+
+/// Counter values the flag is raised at in darkness and in the brightest sun
+const DARK: u32 = 0xE9;
+const BRIGHT: u32 = 0x32;
+
+/// Solar sensor of the Boktai games. The game resets a counter then clocks it up until the
+/// sensor raises its flag, which comes sooner the more light there is.
+#[derive(Clone, Debug, Default)]
+pub struct SolarSensor {
+    /// Light falling on the sensor, from 0 for darkness to 255 for the brightest sun
+    pub light: u8,
+    counter: u8,
+    /// Pins as last driven by the GBA
+    pins: u8,
+}
+
+impl SolarSensor {
+    /// Counter value the flag is raised at
+    fn threshold(&self) -> u8 {
+        (DARK - (DARK - BRIGHT) * self.light as u32 / 255) as u8
+    }
+
+    /// Pins the sensor drives
+    pub fn read(&self) -> u8 {
+        if self.counter >= self.threshold() {
+            FLAG
+        } else {
+            0
+        }
+    }
+
+    /// The GBA drove the pins
+    pub fn write(&mut self, pins: u8) {
+        let rising = self.pins & CLOCK == 0 && pins & CLOCK != 0;
+        self.pins = pins;
+        if pins & SELECT != 0 {
+            return;
+        }
+
+        if pins & RESET != 0 {
+            self.counter = 0;
+        } else if rising {
+            self.counter = self.counter.saturating_add(1);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    const SCK: u16 = 1;
+    const SIO: u16 = 2;
+    const CS: u16 = 4;
+
+    /// 2024-02-29 13:45:07, a Thursday
+    const LEAP_DAY: u64 = 8825 * 24 * 3600 + 13 * 3600 + 45 * 60 + 7;
+
+    fn rom() -> Vec<u8> {
+        (0..0x200).map(|byte| byte as u8).collect()
+    }
+
+    fn rtc_mmu(time: u64) -> MMU {
+        let mut mmu = MMU::new();
+        mmu.load_rom(&rom());
+        mmu.gpio = Gpio::new(vec![Device::Rtc(Rtc::new(Clock::Fixed(time)))]);
+        mmu.store16(registers::CONTROL, 1);
+        mmu
+    }
+
+    /// Sends the command byte then writes or reads the bytes of data as a game does
+    fn transfer(mmu: &mut MMU, command: u8, data: &mut [u8]) {
+        mmu.store16(registers::DIRECTION, 0b111);
+        mmu.store16(registers::DATA, SCK);
+        mmu.store16(registers::DATA, SCK | CS);
+
+        let send = |mmu: &mut MMU, byte: u8| {
+            for bit in 0..8 {
+                let sio = (byte >> bit & 1) as u16 * SIO;
+                mmu.store16(registers::DATA, CS | sio);
+                mmu.store16(registers::DATA, CS | SCK | sio);
+            }
+        };
+        send(mmu, command);
+
+        if command & 0x80 == 0 {
+            for &byte in data.iter() {
+                send(mmu, byte);
+            }
+        } else {
+            mmu.store16(registers::DIRECTION, 0b101);
+            for byte in data.iter_mut() {
+                *byte = 0;
+                for bit in 0..8 {
+                    mmu.store16(registers::DATA, CS);
+                    mmu.store16(registers::DATA, CS | SCK);
+                    let sio = mmu.load16(registers::DATA) & SIO != 0;
+                    *byte |= (sio as u8) << bit;
+                }
+            }
+        }
+        mmu.store16(registers::DATA, SCK);
+    }
+
+    #[test]
+    fn test_gpio_registers() {
+        let mut mmu = rtc_mmu(0);
+        assert_eq!(mmu.load16(registers::CONTROL), 1);
+        mmu.store16(registers::DIRECTION, 0b0111);
+        mmu.store16(registers::DATA, 0b1101);
+        assert_eq!(mmu.load16(registers::DIRECTION), 0b0111);
+        // pin 3 is not driven by the GBA
+        assert_eq!(mmu.load16(registers::DATA), 0b0101);
+        assert_eq!(mmu.load32(registers::DATA), 0b0111 << 16 | 0b0101);
+        // the rest of the page is still the ROM
+        assert_eq!(mmu.load32(0x0800_00C0), 0xC3C2_C1C0);
+        assert_eq!(mmu.load8(0x0800_00CA), 0xCA);
+
+        // the ROM is seen again once the registers are not readable
+        mmu.store16(registers::CONTROL, 0);
+        assert_eq!(mmu.load16(registers::DATA), 0xC5C4);
+        assert_eq!(mmu.load8(registers::CONTROL), 0xC8);
+    }
+
+    #[test]
+    fn test_gpio_without_devices() {
+        let mut mmu = MMU::new();
+        mmu.load_rom(&rom());
+        mmu.store16(registers::CONTROL, 1);
+        assert_eq!(mmu.load16(registers::CONTROL), 0xC9C8);
+        assert!(!mmu.gpio.readable());
+    }
+
+    #[test]
+    fn test_gpio_rtc_date_time() {
+        let mut mmu = rtc_mmu(LEAP_DAY);
+
+        let mut control = [0];
+        transfer(&mut mmu, 0xC6, &mut control);
+        assert_eq!(control[0], rtc::control::HOURS_24);
+
+        let mut date_time = [0; 7];
+        transfer(&mut mmu, 0xA6, &mut date_time);
+        assert_eq!(date_time, [0x24, 0x02, 0x29, 4, 0x80 | 0x13, 0x45, 0x07]);
+
+        let mut time = [0; 3];
+        transfer(&mut mmu, 0xE6, &mut time);
+        assert_eq!(time, [0x80 | 0x13, 0x45, 0x07]);
+    }
+
+    #[test]
+    fn test_gpio_rtc_set() {
+        let mut mmu = rtc_mmu(LEAP_DAY);
+
+        transfer(&mut mmu, 0x26, &mut [0x31, 0x12, 0x31, 3, 0x23, 0x59, 0x58]);
+        let mut date_time = [0; 7];
+        transfer(&mut mmu, 0xA6, &mut date_time);
+        assert_eq!(date_time, [0x31, 0x12, 0x31, 3, 0x80 | 0x23, 0x59, 0x58]);
+
+        // 12 hour mode, then setting the time alone keeps the date
+        transfer(&mut mmu, 0x46, &mut [0]);
+        transfer(&mut mmu, 0x66, &mut [0x80 | 0x01, 0x02, 0x03]);
+        transfer(&mut mmu, 0xA6, &mut date_time);
+        assert_eq!(date_time, [0x31, 0x12, 0x31, 3, 0x80 | 0x01, 0x02, 0x03]);
+
+        // reset goes back to 2000-01-01, a Saturday
+        transfer(&mut mmu, 0x06, &mut []);
+        transfer(&mut mmu, 0xA6, &mut date_time);
+        assert_eq!(date_time, [0x00, 0x01, 0x01, 6, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_gpio_detect() {
+        let mut rom = rom();
+        assert!(detect(&rom, Clock::Host).is_empty());
+
+        rom[0x100..0x10B].copy_from_slice(b"SIIRTC_V001");
+        let devices = detect(&rom, Clock::Host);
+        assert!(matches!(devices[..], [Device::Rtc(_)]));
+
+        // Boktai: The Sun Is in Your Hand, in any region
+        rom[0xAC..0xB0].copy_from_slice(b"U3IP");
+        let devices = detect(&rom, Clock::Host);
+        assert!(matches!(devices[..], [Device::Rtc(_), Device::Solar(_)]));
+
+        rom[0xAC..0xB0].copy_from_slice(b"RZWE");
+        let devices = detect(&rom, Clock::Host);
+        assert!(matches!(devices[..], [Device::Rumble(_), Device::Gyro(_)]));
+    }
+
+    #[test]
+    fn test_gpio_rumble() {
+        let mut mmu = MMU::new();
+        mmu.gpio = Gpio::new(vec![Device::Rumble(Rumble::default())]);
+        assert_eq!(mmu.take_rumble(), None);
+
+        mmu.store16(registers::DIRECTION, 0b1000);
+        mmu.store16(registers::DATA, 0b1000);
+        assert_eq!(mmu.take_rumble(), Some(true));
+        assert_eq!(mmu.take_rumble(), None);
+        mmu.store16(registers::DATA, 0b1000);
+        assert_eq!(mmu.take_rumble(), None);
+        mmu.store16(registers::DATA, 0);
+        assert_eq!(mmu.take_rumble(), Some(false));
+    }
+
+    /// Clocks the solar sensor until it raises its flag, as Boktai does
+    fn solar_count(mmu: &mut MMU) -> u32 {
+        mmu.store16(registers::DIRECTION, 0b0111);
+        mmu.store16(registers::DATA, 0b0010);
+        mmu.store16(registers::DATA, 0);
+        let mut count = 0;
+        while mmu.load16(registers::DATA) & 0b1000 == 0 && count < 0x100 {
+            mmu.store16(registers::DATA, 0b0001);
+            mmu.store16(registers::DATA, 0);
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn test_gpio_solar_sensor() {
+        let mut mmu = rtc_mmu(0);
+        mmu.gpio.devices = vec![Device::Solar(SolarSensor::default())];
+        let dark = solar_count(&mut mmu);
+        mmu.set_light(255);
+        let bright = solar_count(&mut mmu);
+        assert_eq!((dark, bright), (0xE9, 0x32));
+
+        // the counter is left alone while the sensor is not selected
+        mmu.store16(registers::DATA, 0b0110);
+        mmu.store16(registers::DATA, 0b0100);
+        assert_ne!(mmu.load16(registers::DATA) & 0b1000, 0);
+    }
+
+    #[test]
+    fn test_gpio_gyro() {
+        let mut mmu = rtc_mmu(0);
+        mmu.gpio.devices = vec![Device::Gyro(Gyro::default())];
+        mmu.set_tilt(0x1000, 0);
+
+        mmu.store16(registers::DIRECTION, 0b0011);
+        mmu.store16(registers::DATA, 0b0001);
+        let mut sample = 0;
+        for _ in 0..16 {
+            mmu.store16(registers::DATA, 0b0010);
+            mmu.store16(registers::DATA, 0);
+            sample = sample << 1 | (mmu.load16(registers::DATA) >> 2 & 1);
+        }
+        assert_eq!(sample, 0x6C0 + 0x80);
+    }
+}
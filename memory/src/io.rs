@@ -0,0 +1,353 @@
+// IO register layer, every halfword of the 0x04000000 region gets a descriptor telling
+// which bits can be read and written and what happens when they are.
+
+use crate::{base_addrs, dma, keypad, power, serial, sizes, timers, MMU};
+
+/// Addresses of the registers handled in this crate
+pub mod registers {
+    pub const DISPCNT: u32 = 0x400_0000;
+    pub const IE: u32 = 0x400_0200;
+    pub const IF: u32 = 0x400_0202;
+    pub const IME: u32 = 0x400_0208;
+}
+
+/// Bits of IE and IF
+pub mod interrupts {
+    pub const VBLANK: u16 = 1 << 0;
+    pub const HBLANK: u16 = 1 << 1;
+    pub const VCOUNTER: u16 = 1 << 2;
+    pub const TIMER0: u16 = 1 << 3;
+    pub const TIMER1: u16 = 1 << 4;
+    pub const TIMER2: u16 = 1 << 5;
+    pub const TIMER3: u16 = 1 << 6;
+    pub const SERIAL: u16 = 1 << 7;
+    pub const DMA0: u16 = 1 << 8;
+    pub const DMA1: u16 = 1 << 9;
+    pub const DMA2: u16 = 1 << 10;
+    pub const DMA3: u16 = 1 << 11;
+    pub const KEYPAD: u16 = 1 << 12;
+    pub const GAMEPAK: u16 = 1 << 13;
+
+    /// Flag of timer n
+    pub const fn timer(n: usize) -> u16 {
+        TIMER0 << n
+    }
+
+    /// Flag of DMA channel n
+    pub const fn dma(n: usize) -> u16 {
+        DMA0 << n
+    }
+}
+
+/// Called instead of reading the stored value, gets the address of the halfword
+pub type ReadHook = fn(&MMU, u32) -> u16;
+
+/// Called after a write has been stored, with the address of the halfword, the value it held
+/// before, the bits that were written (already write masked) and the byte lanes written to
+pub type WriteHook = fn(&mut MMU, u32, u16, u16, u16);
+
+/// Behaviour of a single 16 bit IO register
+#[derive(Clone, Copy)]
+pub struct IoRegister {
+    /// Bits that can be read back, the rest read as 0
+    pub read_mask: u16,
+    /// Bits the CPU can change, the rest keep their value
+    pub write_mask: u16,
+    pub on_read: Option<ReadHook>,
+    pub on_write: Option<WriteHook>,
+}
+
+impl Default for IoRegister {
+    /// Plain read/write memory
+    fn default() -> Self {
+        Self::new(0xFFFF, 0xFFFF)
+    }
+}
+
+impl IoRegister {
+    pub const fn new(read_mask: u16, write_mask: u16) -> Self {
+        Self {
+            read_mask,
+            write_mask,
+            on_read: None,
+            on_write: None,
+        }
+    }
+
+    /// Only the CPU can not write it
+    pub const fn read_only(mask: u16) -> Self {
+        Self::new(mask, 0)
+    }
+
+    /// Reads back as 0
+    pub const fn write_only(mask: u16) -> Self {
+        Self::new(0, mask)
+    }
+
+    /// Unused addresses
+    pub const fn unused() -> Self {
+        Self::new(0, 0)
+    }
+}
+
+/// Index of the register holding addr in the register table
+#[inline]
+pub(crate) fn index(addr: u32) -> usize {
+    (addr as usize - base_addrs::IO_REGISTERS_ADDR) >> 1
+}
+
+/// Register table of the GBA, masks from GBATEK
+pub(crate) fn default_registers() -> Box<[IoRegister]> {
+    let mut table = vec![IoRegister::unused(); sizes::IO_REGISTERS_SIZE / 2];
+    let mut set = |addr: u32, register: IoRegister| table[index(addr)] = register;
+
+    // LCD
+    set(0x400_0000, IoRegister::new(0xFFF7, 0xFFF7)); // DISPCNT, bit 3 is set by the BIOS only
+    set(0x400_0002, IoRegister::default()); // green swap
+    set(0x400_0004, IoRegister::new(0xFF3F, 0xFF38)); // DISPSTAT, status bits are read only
+    set(0x400_0006, IoRegister::read_only(0x00FF)); // VCOUNT
+    set(0x400_0008, IoRegister::new(0xDFFF, 0xDFFF)); // BG0CNT
+    set(0x400_000A, IoRegister::new(0xDFFF, 0xDFFF)); // BG1CNT
+    set(0x400_000C, IoRegister::default()); // BG2CNT
+    set(0x400_000E, IoRegister::default()); // BG3CNT
+    for addr in (0x400_0010..0x400_0020).step_by(2) {
+        set(addr, IoRegister::write_only(0x01FF)); // BGxHOFS/BGxVOFS
+    }
+    for &base in [0x400_0020, 0x400_0030].iter() {
+        for addr in (base..base + 8).step_by(2) {
+            set(addr, IoRegister::write_only(0xFFFF)); // BGxPA-PD
+        }
+        set(base + 0x8, IoRegister::write_only(0xFFFF)); // BGxX
+        set(base + 0xA, IoRegister::write_only(0x0FFF));
+        set(base + 0xC, IoRegister::write_only(0xFFFF)); // BGxY
+        set(base + 0xE, IoRegister::write_only(0x0FFF));
+    }
+    for addr in (0x400_0040..0x400_0048).step_by(2) {
+        set(addr, IoRegister::write_only(0xFFFF)); // WINxH/WINxV
+    }
+    set(0x400_0048, IoRegister::new(0x3F3F, 0x3F3F)); // WININ
+    set(0x400_004A, IoRegister::new(0x3F3F, 0x3F3F)); // WINOUT
+    set(0x400_004C, IoRegister::write_only(0xFFFF)); // MOSAIC
+    set(0x400_0050, IoRegister::new(0x3FFF, 0x3FFF)); // BLDCNT
+    set(0x400_0052, IoRegister::new(0x1F1F, 0x1F1F)); // BLDALPHA
+    set(0x400_0054, IoRegister::write_only(0x001F)); // BLDY
+
+    // Sound, lengths and triggers are write only
+    set(0x400_0060, IoRegister::new(0x007F, 0x007F)); // SOUND1CNT_L
+    set(0x400_0062, IoRegister::new(0xFFC0, 0xFFFF)); // SOUND1CNT_H
+    set(0x400_0064, IoRegister::new(0x4000, 0xC7FF)); // SOUND1CNT_X
+    set(0x400_0068, IoRegister::new(0xFFC0, 0xFFFF)); // SOUND2CNT_L
+    set(0x400_006C, IoRegister::new(0x4000, 0xC7FF)); // SOUND2CNT_H
+    set(0x400_0070, IoRegister::new(0x00E0, 0x00E0)); // SOUND3CNT_L
+    set(0x400_0072, IoRegister::new(0xE000, 0xE0FF)); // SOUND3CNT_H
+    set(0x400_0074, IoRegister::new(0x4000, 0xC7FF)); // SOUND3CNT_X
+    set(0x400_0078, IoRegister::new(0xFF00, 0xFF3F)); // SOUND4CNT_L
+    set(0x400_007C, IoRegister::new(0x40FF, 0xC0FF)); // SOUND4CNT_H
+    set(0x400_0080, IoRegister::new(0xFF77, 0xFF77)); // SOUNDCNT_L
+    let sound = |read_mask, write_mask| IoRegister {
+        on_write: Some(sound_written),
+        ..IoRegister::new(read_mask, write_mask)
+    };
+    set(0x400_0082, sound(0x770F, 0xFF0F)); // SOUNDCNT_H, FIFO resets are write only
+    set(0x400_0084, IoRegister::new(0x008F, 0x0080)); // SOUNDCNT_X, channel flags are read only
+    set(0x400_0088, IoRegister::new(0xC3FE, 0xC3FE)); // SOUNDBIAS
+    for addr in (0x400_0090..0x400_00A0).step_by(2) {
+        set(addr, IoRegister::default()); // wave RAM
+    }
+    for addr in (0x400_00A0..0x400_00A8).step_by(2) {
+        set(addr, sound(0, 0xFFFF)); // FIFO A/B
+    }
+
+    // DMA
+    for channel in 0..4 {
+        let base = 0x400_00B0 + channel * 12;
+        // source, destination and count are write only
+        for addr in (base..base + 10).step_by(2) {
+            set(addr, IoRegister::write_only(0xFFFF));
+        }
+        // only DMA3 has the game pak DRQ bit
+        let control = if channel == 3 { 0xFFE0 } else { 0xF7E0 };
+        set(
+            base + 10,
+            IoRegister {
+                on_write: Some(dma::control_written),
+                ..IoRegister::new(control, control)
+            },
+        ); // DMAxCNT_H
+    }
+
+    // Timers
+    for n in 0..4 {
+        set(
+            timers::registers::counter(n),
+            IoRegister {
+                on_read: Some(timers::counter_read),
+                ..Default::default()
+            },
+        );
+        set(
+            timers::registers::control(n),
+            IoRegister {
+                on_write: Some(timers::control_written),
+                ..IoRegister::new(0x00C7, 0x00C7)
+            },
+        );
+    }
+
+    // Serial and keypad
+    for addr in (0x400_0120..0x400_0128).step_by(2) {
+        set(addr, IoRegister::default()); // SIODATA32/SIOMULTI0-3
+    }
+    set(
+        serial::registers::SIOCNT,
+        IoRegister {
+            on_write: Some(serial::control_written),
+            ..IoRegister::new(0x7FFF, 0x7FFF)
+        },
+    );
+    set(
+        serial::registers::SIODATA8,
+        IoRegister {
+            on_write: Some(serial::data_written),
+            ..Default::default()
+        },
+    );
+    set(keypad::registers::KEYINPUT, IoRegister::read_only(0x03FF));
+    set(
+        keypad::registers::KEYCNT,
+        IoRegister {
+            on_write: Some(keypad::control_written),
+            ..IoRegister::new(0xC3FF, 0xC3FF)
+        },
+    );
+    set(
+        serial::registers::RCNT,
+        IoRegister {
+            on_write: Some(serial::mode_written),
+            ..IoRegister::new(0xC1FF, 0xC1FF)
+        },
+    );
+    set(
+        serial::registers::JOYCNT,
+        IoRegister {
+            on_write: Some(serial::joy_control_written),
+            ..IoRegister::new(0x0047, 0x0047)
+        },
+    );
+    for addr in (serial::registers::JOY_RECV..serial::registers::JOY_TRANS).step_by(2) {
+        set(addr, IoRegister::read_only(0xFFFF)); // written by the host
+    }
+    for addr in (serial::registers::JOY_TRANS..serial::registers::JOYSTAT).step_by(2) {
+        set(
+            addr,
+            IoRegister {
+                on_write: Some(serial::joy_trans_written),
+                ..Default::default()
+            },
+        );
+    }
+    set(serial::registers::JOYSTAT, IoRegister::new(0x003A, 0x0030));
+
+    // Interrupts, waitstates and power
+    set(registers::IE, IoRegister::new(0x3FFF, 0x3FFF));
+    set(
+        registers::IF,
+        IoRegister {
+            on_write: Some(acknowledge_interrupts),
+            ..IoRegister::new(0x3FFF, 0x3FFF)
+        },
+    );
+    set(0x400_0204, IoRegister::new(0xDFFF, 0x5FFF)); // WAITCNT, game pak type is read only
+    set(registers::IME, IoRegister::new(0x0001, 0x0001));
+    set(
+        0x400_0300,
+        IoRegister {
+            on_write: Some(power::halt_written),
+            ..IoRegister::new(0x00FF, 0xFF01)
+        },
+    ); // POSTFLG/HALTCNT
+
+    table.into_boxed_slice()
+}
+
+/// The sound side drains these writes, the FIFO reset bits only act once
+fn sound_written(mmu: &mut MMU, addr: u32, _: u16, _: u16, _: u16) {
+    let value = mmu.io_raw(addr);
+    mmu.sound_writes.push((addr, value));
+    if addr == 0x400_0082 {
+        mmu.set_io_raw(addr, value & !0x8800);
+    }
+}
+
+/// Writing 1 to a bit of IF clears it
+fn acknowledge_interrupts(mmu: &mut MMU, addr: u32, old: u16, written: u16, _: u16) {
+    mmu.set_io_raw(addr, old & !written);
+}
+
+impl MMU {
+    /// Sets flags in IF, the CPU takes them once they are enabled in IE and IME
+    pub fn request_interrupt(&mut self, flags: u16) {
+        let pending = self.io_raw(registers::IF);
+        self.set_io_raw(registers::IF, pending | flags & 0x3FFF);
+    }
+
+    /// Requested interrupts that are enabled in IE, regardless of IME
+    pub fn pending_interrupts(&self) -> u16 {
+        self.io_raw(registers::IE) & self.io_raw(registers::IF) & 0x3FFF
+    }
+
+    /// True when the IRQ line to the CPU is asserted
+    pub fn irq_line(&self) -> bool {
+        self.io_raw(registers::IME) & 1 != 0 && self.pending_interrupts() != 0
+    }
+
+    /// Replace the behaviour of the IO register containing addr, so subsystems can hook into it
+    pub fn set_io_register(&mut self, addr: u32, register: IoRegister) {
+        self.io_registers[index(addr)] = register;
+    }
+
+    /// The behaviour of the IO register containing addr
+    pub fn io_register(&self, addr: u32) -> IoRegister {
+        self.io_registers[index(addr)]
+    }
+
+    /// Reads the stored value of an IO register, without masks or hooks.
+    /// Meant for the hardware the register belongs to.
+    pub fn io_raw(&self, addr: u32) -> u16 {
+        let offset = (addr as usize - base_addrs::IO_REGISTERS_ADDR) & !1;
+        u16::from_le_bytes([self.registers[offset], self.registers[offset + 1]])
+    }
+
+    /// Stores into an IO register, without masks or hooks.
+    /// Meant for the hardware the register belongs to, such as the display updating VCOUNT.
+    pub fn set_io_raw(&mut self, addr: u32, value: u16) {
+        let offset = (addr as usize - base_addrs::IO_REGISTERS_ADDR) & !1;
+        self.registers[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Reads an IO register as seen by the CPU
+    pub fn io_read16(&self, addr: u32) -> u16 {
+        let register = self.io_register(addr);
+        let value = match register.on_read {
+            Some(hook) => hook(self, addr & !1),
+            None => self.io_raw(addr),
+        };
+        value & register.read_mask
+    }
+
+    /// Writes an IO register as the CPU would, mask selects the bytes being written
+    pub fn io_write16(&mut self, addr: u32, value: u16, mask: u16) {
+        let addr = addr & !1;
+        let register = self.io_register(addr);
+        let write_mask = register.write_mask & mask;
+
+        let old = self.io_raw(addr);
+        self.set_io_raw(addr, old & !write_mask | value & write_mask);
+
+        if let Some(hook) = register.on_write {
+            hook(self, addr, old, value & write_mask, mask);
+        }
+    }
+}
+
+pub mod tests;
@@ -0,0 +1,101 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_io_read_only_bits() {
+        let mut mmu = MMU::new();
+
+        // VCOUNT belongs to the display
+        mmu.set_io_raw(0x400_0006, 100);
+        mmu.store16(0x400_0006, 5);
+        assert_eq!(mmu.load8(0x400_0006), 100);
+
+        // the DISPSTAT status bits survive CPU writes, the settings change
+        mmu.set_io_raw(0x400_0004, 0b011);
+        mmu.store8(0x400_0004, 0b1111_1000);
+        assert_eq!(mmu.io_raw(0x400_0004), 0b0011_1011);
+        assert_eq!(mmu.load8(0x400_0004), 0b0011_1011);
+    }
+
+    #[test]
+    fn test_io_write_only_and_unused() {
+        let mut mmu = MMU::new();
+
+        // scroll registers are write only and 9 bits wide
+        mmu.store16(0x400_0010, 0xFFFF);
+        assert_eq!(mmu.io_raw(0x400_0010), 0x01FF);
+        assert_eq!(mmu.load8(0x400_0010), 0);
+
+        // unused registers ignore writes
+        mmu.store16(0x400_0056, 0xFFFF);
+        assert_eq!(mmu.io_raw(0x400_0056), 0);
+    }
+
+    #[test]
+    fn test_io_byte_lanes() {
+        let mut mmu = MMU::new();
+
+        mmu.store16(0x400_0008, 0x1234);
+        mmu.store8(0x400_0009, 0x0A);
+        assert_eq!(mmu.io_raw(0x400_0008), 0x0A34);
+        assert_eq!(mmu.load8(0x400_0008), 0x34);
+        assert_eq!(mmu.load8(0x400_0009), 0x0A);
+    }
+
+    #[test]
+    fn test_io_hooks() {
+        fn on_write(mmu: &mut MMU, _: u32, old: u16, written: u16, _: u16) {
+            // count the writes in wave RAM
+            let count = mmu.io_raw(0x400_0090);
+            mmu.set_io_raw(0x400_0090, count + 1);
+            mmu.set_io_raw(0x400_0092, old ^ written);
+        }
+        fn on_read(_: &MMU, _: u32) -> u16 {
+            0xBEEF
+        }
+
+        let mut mmu = MMU::new();
+        let register = IoRegister {
+            on_write: Some(on_write),
+            on_read: Some(on_read),
+            ..Default::default()
+        };
+        mmu.set_io_register(0x400_0100, register);
+        mmu.set_io_register(0x400_0102, register);
+
+        // a word write reaches every register once
+        mmu.store32(0x400_0100, 0x00FF_00F0);
+        assert_eq!(mmu.io_raw(0x400_0090), 2);
+        assert_eq!(mmu.io_raw(0x400_0092), 0x00FF);
+
+        mmu.store8(0x400_0101, 0x0F);
+        assert_eq!(mmu.io_raw(0x400_0090), 3);
+        assert_eq!(mmu.io_raw(0x400_0100), 0x0FF0);
+
+        assert_eq!(mmu.load8(0x400_0100), 0xEF);
+        assert_eq!(mmu.load8(0x400_0101), 0xBE);
+    }
+
+    #[test]
+    fn test_io_interrupt_acknowledge() {
+        let mut mmu = MMU::new();
+
+        mmu.request_interrupt(interrupts::VBLANK | interrupts::TIMER0);
+        assert!(!mmu.irq_line());
+
+        mmu.store16(registers::IE, interrupts::TIMER0);
+        assert_eq!(mmu.pending_interrupts(), interrupts::TIMER0);
+        assert!(!mmu.irq_line());
+        mmu.store32(registers::IME, 1);
+        assert!(mmu.irq_line());
+
+        // writing 1 clears, writing 0 keeps
+        mmu.store16(registers::IF, interrupts::TIMER0);
+        assert_eq!(mmu.load8(registers::IF), interrupts::VBLANK as u8);
+        assert!(!mmu.irq_line());
+        mmu.store8(registers::IF, 0);
+        assert_eq!(mmu.io_raw(registers::IF), interrupts::VBLANK);
+    }
+}
+
@@ -0,0 +1,61 @@
+// The keypad: KEYINPUT shows the pressed keys, KEYCNT picks keys that raise the keypad
+// interrupt, which is also what wakes the GBA up from STOP.
+
+use crate::io::interrupts;
+use crate::MMU;
+
+/// Addresses of the keypad registers
+pub mod registers {
+    pub const KEYINPUT: u32 = 0x400_0130;
+    pub const KEYCNT: u32 = 0x400_0132;
+}
+
+/// Bits of KEYCNT
+pub mod control {
+    pub const KEYS: u16 = 0x03FF;
+    pub const IRQ: u16 = 1 << 14;
+    /// All the selected keys have to be pressed instead of any of them
+    pub const AND: u16 = 1 << 15;
+}
+
+/// Called on writes to KEYCNT, the new condition may already be met
+pub(crate) fn control_written(mmu: &mut MMU, _: u32, _: u16, _: u16, _: u16) {
+    mmu.check_keypad_interrupt();
+}
+
+impl MMU {
+    /// Updates KEYINPUT from the pressed keys, set bits being pressed in the KEYINPUT order
+    pub fn set_keys(&mut self, pressed: u16) {
+        // KEYINPUT is active low
+        let keys = !pressed & control::KEYS;
+        if keys != self.io_raw(registers::KEYINPUT) {
+            self.set_io_raw(registers::KEYINPUT, keys);
+            self.check_keypad_interrupt();
+        }
+    }
+
+    /// Pressed keys, set bits being pressed
+    pub fn keys(&self) -> u16 {
+        !self.io_raw(registers::KEYINPUT) & control::KEYS
+    }
+
+    fn check_keypad_interrupt(&mut self) {
+        let control = self.io_raw(registers::KEYCNT);
+        if control & control::IRQ == 0 {
+            return;
+        }
+
+        let selected = control & control::KEYS;
+        let pressed = self.keys() & selected;
+        let condition = if control & control::AND != 0 {
+            selected != 0 && pressed == selected
+        } else {
+            pressed != 0
+        };
+        if condition {
+            self.request_interrupt(interrupts::KEYPAD);
+        }
+    }
+}
+
+pub mod tests;
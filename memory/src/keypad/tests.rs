@@ -0,0 +1,93 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::power::Power;
+
+    const A: u16 = 1;
+    const B: u16 = 1 << 1;
+    const START: u16 = 1 << 3;
+
+    #[test]
+    fn test_keyinput_active_low() {
+        let mut mmu = MMU::new();
+        assert_eq!(mmu.load16(registers::KEYINPUT), 0x03FF);
+
+        mmu.set_keys(A | START);
+        assert_eq!(mmu.load16(registers::KEYINPUT), 0x03F6);
+        assert_eq!(mmu.keys(), A | START);
+
+        // the CPU cannot change it
+        mmu.store16(registers::KEYINPUT, 0);
+        assert_eq!(mmu.load16(registers::KEYINPUT), 0x03F6);
+    }
+
+    #[test]
+    fn test_keycnt_or() {
+        let mut mmu = MMU::new();
+        mmu.store16(registers::KEYCNT, control::IRQ | A | B);
+
+        mmu.set_keys(START);
+        assert_eq!(mmu.io_raw(0x400_0202) & interrupts::KEYPAD, 0);
+        mmu.set_keys(START | B);
+        assert_ne!(mmu.io_raw(0x400_0202) & interrupts::KEYPAD, 0);
+    }
+
+    #[test]
+    fn test_keycnt_and() {
+        let mut mmu = MMU::new();
+        mmu.store16(registers::KEYCNT, control::IRQ | control::AND | A | B);
+
+        mmu.set_keys(A);
+        assert_eq!(mmu.io_raw(0x400_0202) & interrupts::KEYPAD, 0);
+        mmu.set_keys(A | B);
+        assert_ne!(mmu.io_raw(0x400_0202) & interrupts::KEYPAD, 0);
+    }
+
+    #[test]
+    fn test_keycnt_write_checks_condition() {
+        let mut mmu = MMU::new();
+        mmu.set_keys(A);
+
+        // without the IRQ bit nothing is requested
+        mmu.store16(registers::KEYCNT, A);
+        assert_eq!(mmu.io_raw(0x400_0202) & interrupts::KEYPAD, 0);
+        mmu.store16(registers::KEYCNT, control::IRQ | A);
+        assert_ne!(mmu.io_raw(0x400_0202) & interrupts::KEYPAD, 0);
+    }
+
+    #[test]
+    fn test_stop_woken_by_keys() {
+        let mut mmu = MMU::new();
+        mmu.store16(0x400_0200, interrupts::KEYPAD | interrupts::VBLANK);
+        mmu.store16(registers::KEYCNT, control::IRQ | START);
+
+        // STOP is bit 7 of HALTCNT, POSTFLG is left alone
+        mmu.store8(0x400_0301, 0x80);
+        assert_eq!(mmu.power(), Power::Stopped);
+        assert!(mmu.sleeping());
+
+        // other interrupts do not wake it up
+        mmu.request_interrupt(interrupts::VBLANK);
+        assert!(mmu.sleeping());
+
+        mmu.set_keys(START);
+        assert!(!mmu.sleeping());
+        assert_eq!(mmu.power(), Power::Running);
+    }
+
+    #[test]
+    fn test_halt_woken_by_interrupts() {
+        let mut mmu = MMU::new();
+        mmu.store16(0x400_0200, interrupts::VBLANK);
+
+        mmu.store8(0x400_0300, 1);
+        assert_eq!(mmu.power(), Power::Running);
+
+        mmu.store8(0x400_0301, 0);
+        assert_eq!(mmu.power(), Power::Halted);
+        assert!(mmu.sleeping());
+
+        mmu.request_interrupt(interrupts::VBLANK);
+        assert!(!mmu.sleeping());
+    }
+}
@@ -2,6 +2,22 @@
 // and https://www.akkit.org/info/gbatek.htm.
 // thank you!
 
+pub mod cartridge;
+pub mod dma;
+pub mod dmg;
+pub mod gpio;
+pub mod io;
+pub mod keypad;
+mod pages;
+pub mod power;
+pub mod save;
+pub mod serial;
+pub mod tilt;
+pub mod timers;
+
+use io::IoRegister;
+use std::convert::TryInto;
+
 pub mod sizes {
     pub const BIOS_SIZE: usize = 0x000_4000;
     pub const WRAM_SIZE: usize = 0x004_0000;
@@ -40,122 +56,358 @@ pub mod base_addrs {
 
 #[derive(Default, Clone)]
 pub struct MMU {
+    /// Writes to the DirectSound FIFOs and SOUNDCNT_H, in order, since they were last drained
+    pub sound_writes: Vec<(u32, u16)>,
+    /// Battery backed memory of the cartridge
+    pub save: save::Save,
+    /// GPIO port of the cartridge and the devices behind it
+    pub gpio: gpio::Gpio,
+    /// Tilt sensor of the cartridge, in the save region
+    pub tilt: Option<tilt::TiltSensor>,
+
     wram: Box<[u8]>,
     iwram: Box<[u8]>,
     bios: Box<[u8]>,
     rom: Box<[u8]>,
+    /// Size of the loaded ROM, reads past it see the address pattern
+    rom_size: usize,
+    /// Last opcode prefetched by the CPU, seen when reading unmapped memory
+    open_bus: u32,
+    power: power::Power,
     registers: Box<[u8]>,
+    io_registers: Box<[IoRegister]>,
+    dma: [dma::Channel; 4],
+    timers: [timers::Timer; 4],
+    serial: serial::Serial,
+    fifo_ticks: [u32; 2],
     palette: Box<[u8]>,
     vram: Box<[u8]>,
     oam: Box<[u8]>,
+    /// Maps 16KiB pages to the memory backing them
+    pages: Box<[pages::Page]>,
+
+    #[cfg(feature = "write-tracking")]
+    write_generations: Box<[u32]>,
 }
 
+/// Pages are 1KiB when tracking writes
+#[cfg(feature = "write-tracking")]
+pub const WRITE_TRACKING_PAGE_SHIFT: u32 = 10;
+
 impl MMU {
     /// Create a new instance of the MMU
     pub fn new() -> Self {
-        Self {
+        let mut mmu = Self {
+            sound_writes: Vec::new(),
+            save: Default::default(),
+            gpio: Default::default(),
+            tilt: None,
+
             bios: vec![0; sizes::BIOS_SIZE].into_boxed_slice(),
             wram: vec![0; sizes::WRAM_SIZE].into_boxed_slice(),
             iwram: vec![0; sizes::IWRAM_SIZE].into_boxed_slice(),
             rom: vec![0; sizes::CART0_SIZE].into_boxed_slice(),
+            rom_size: 0,
+            open_bus: 0,
+            power: power::Power::Running,
             registers: vec![0; sizes::IO_REGISTERS_SIZE].into_boxed_slice(),
+            io_registers: io::default_registers(),
+            dma: Default::default(),
+            timers: Default::default(),
+            serial: Default::default(),
+            fifo_ticks: [0; 2],
             palette: vec![0; sizes::PALETTE_RAM_SIZE].into_boxed_slice(),
             vram: vec![0; sizes::VRAM_SIZE].into_boxed_slice(),
             oam: vec![0; sizes::OAM_SIZE].into_boxed_slice(),
-        }
+            pages: pages::build(0),
+
+            #[cfg(feature = "write-tracking")]
+            write_generations: vec![0; 0x1000_0000 >> WRITE_TRACKING_PAGE_SHIFT].into_boxed_slice(),
+        };
+        // no keys pressed, no link cable
+        mmu.set_keys(0);
+        mmu.update_serial_status();
+        mmu
+    }
+
+    /// Number of writes seen by the page containing addr.
+    /// Compare two readings to find out if the page changed in between.
+    #[cfg(feature = "write-tracking")]
+    pub fn page_generation(&self, addr: u32) -> u32 {
+        self.write_generations[(addr as usize & 0x0FFF_FFFF) >> WRITE_TRACKING_PAGE_SHIFT]
+    }
+
+    /// First address of RAM, palette RAM, VRAM or OAM holding a different byte in other.
+    /// IO registers are skipped, they move on with time on their own.
+    pub fn first_difference(&self, other: &MMU) -> Option<u32> {
+        let regions = [
+            (base_addrs::WORKING_RAM_ADDR, &self.wram, &other.wram),
+            (base_addrs::WORKING_IRAM_ADDR, &self.iwram, &other.iwram),
+            (base_addrs::PALETTE_RAM_ADDR, &self.palette, &other.palette),
+            (base_addrs::VRAM_ADDR, &self.vram, &other.vram),
+            (base_addrs::OAM_ADDR, &self.oam, &other.oam),
+        ];
+        regions.iter().find_map(|(base, ours, theirs)| {
+            ours.iter()
+                .zip(theirs.iter())
+                .position(|(a, b)| a != b)
+                .map(|offset| (base + offset) as u32)
+        })
+    }
+
+    #[cfg(feature = "write-tracking")]
+    #[inline]
+    fn mark_written(&mut self, addr: u32) {
+        let page = (addr as usize & 0x0FFF_FFFF) >> WRITE_TRACKING_PAGE_SHIFT;
+        self.write_generations[page] = self.write_generations[page].wrapping_add(1);
+    }
+
+    /// Map a cartridge ROM into the game pak region
+    pub fn load_rom(&mut self, rom: &[u8]) {
+        let len = rom.len().min(self.rom.len());
+        self.rom[..len].copy_from_slice(&rom[..len]);
+        self.rom_size = len;
+        self.pages = pages::build(len);
+    }
+
+    /// The CPU prefetched opcode, unmapped addresses read it back.
+    /// THUMB opcodes are seen twice on the 32-bit bus.
+    pub fn set_prefetched(&mut self, opcode: u32, thumb: bool) {
+        self.open_bus = if thumb {
+            opcode & 0xFFFF | opcode << 16
+        } else {
+            opcode
+        };
     }
 
     /// Reads a byte from memory
     pub fn load8(&self, addr: u32) -> u8 {
-        match addr as usize {
-            base_addrs::BIOS_ADDR..=0x0000_3FFF => self.bios[addr as usize - base_addrs::BIOS_ADDR],
-            base_addrs::WORKING_RAM_ADDR..=0x0203_FFFF => {
-                self.wram[addr as usize - base_addrs::WORKING_RAM_ADDR]
-            }
-            base_addrs::WORKING_IRAM_ADDR..=0x0300_7FFF => {
-                self.iwram[addr as usize - base_addrs::WORKING_IRAM_ADDR]
-            }
-            base_addrs::IO_REGISTERS_ADDR..=0x0400_03FE => {
-                self.registers[addr as usize - base_addrs::IO_REGISTERS_ADDR]
-            }
-            base_addrs::PALETTE_RAM_ADDR..=0x0500_03FF => {
-                self.palette[addr as usize - base_addrs::PALETTE_RAM_ADDR]
-            }
-            base_addrs::VRAM_ADDR..=0x0601_7FFF => self.vram[addr as usize - base_addrs::VRAM_ADDR],
-            base_addrs::OAM_ADDR..=0x0700_03FF => self.oam[addr as usize - base_addrs::OAM_ADDR],
-            base_addrs::CART0_ADDR..=0x0DFF_FFFF => {
-                self.rom[addr as usize - base_addrs::CART0_ADDR]
-            }
-            base_addrs::CART_SRAM_ADDR..=0x0E00_FFFF => unimplemented!(), // SRAM unimplemented
-            base_addrs::CART_SRAM_MIRROR_ADDR..=0x0F00_FFFF => unimplemented!(), // SRAM unimplemented
-            _ => 0,
+        if let Some((slice, offset)) = self.page(addr) {
+            return self.slice(slice)[offset];
+        }
+
+        match addr >> 24 {
+            0x04 if Self::is_io(addr) => (self.io_read16(addr) >> ((addr & 1) * 8)) as u8,
+            // the EEPROM sits on bit 0 of each halfword
+            0x0D if self.is_eeprom(addr) && addr & 1 == 0 => self.save.eeprom_read() as u8,
+            0x0D if self.is_eeprom(addr) => 0,
+            // the three waitstate regions all show the same ROM
+            0x08..=0x0D => self.rom_read8(addr),
+            0x0E | 0x0F => self.save_read8(addr),
+            _ => self.open_bus8(addr),
+        }
+    }
+
+    /// Reads a byte of the ROM, past its end the bus returns the halfword address
+    fn rom_read8(&self, addr: u32) -> u8 {
+        if self.gpio.readable() && gpio::is_register(addr) {
+            return (self.gpio.read(addr & !1) >> ((addr & 1) * 8)) as u8;
+        }
+        let offset = addr as usize & (sizes::CART0_SIZE - 1);
+        if offset < self.rom_size {
+            self.rom[offset]
+        } else {
+            ((addr >> 1) >> ((addr & 1) * 8)) as u8
         }
     }
 
-    /// Reads a half-word from memory
+    /// Byte of the last prefetched opcode in the lane of addr
+    fn open_bus8(&self, addr: u32) -> u8 {
+        (self.open_bus >> ((addr & 3) * 8)) as u8
+    }
+
+    /// Reads a half-word from memory, the address is aligned down
     pub fn load16(&self, addr: u32) -> u16 {
-        let mut x = self.load8(addr) as u16;
-        x <<= 8;
-        x += self.load8(addr + 1) as u16;
+        let aligned = addr & !1;
+        if let Some((slice, offset)) = self.page(aligned) {
+            return read16(self.slice(slice), offset);
+        }
 
-        x
+        match addr >> 24 {
+            0x04 if Self::is_io(addr) => self.io_read16(aligned),
+            0x0D if self.is_eeprom(addr) => self.save.eeprom_read(),
+            0x08..=0x0D => self.rom_read16(aligned),
+            // the save chip has an 8-bit bus, the byte is repeated
+            0x0E | 0x0F => self.save_read8(addr) as u16 * 0x0101,
+            _ => (self.open_bus >> ((addr & 2) * 8)) as u16,
+        }
     }
 
-    /// Reads a word from memory
+    /// Reads a word from memory, the address is aligned down
     pub fn load32(&self, addr: u32) -> u32 {
-        let mut x = self.load16(addr) as u32;
-        x <<= 16;
-        x += self.load16(addr + 2) as u32;
+        let aligned = addr & !3;
+        if let Some((slice, offset)) = self.page(aligned) {
+            return read32(self.slice(slice), offset);
+        }
+
+        match addr >> 24 {
+            0x04 if Self::is_io(addr) => {
+                self.io_read16(aligned) as u32 | (self.io_read16(aligned + 2) as u32) << 16
+            }
+            0x0D if self.is_eeprom(addr) => self.save.eeprom_read() as u32,
+            0x08..=0x0D => {
+                self.rom_read16(aligned) as u32 | (self.rom_read16(aligned + 2) as u32) << 16
+            }
+            0x0E | 0x0F => self.save_read8(addr) as u32 * 0x0101_0101,
+            _ => self.open_bus,
+        }
+    }
 
-        x
+    /// Reads a halfword of the ROM, past its end the bus returns the halfword address
+    fn rom_read16(&self, addr: u32) -> u16 {
+        if self.gpio.readable() && gpio::is_register(addr) {
+            return self.gpio.read(addr);
+        }
+        let offset = addr as usize & (sizes::CART0_SIZE - 1);
+        if offset < self.rom_size {
+            read16(&self.rom, offset)
+        } else {
+            (addr >> 1) as u16
+        }
     }
 
-    /// Write a byte into memory
+    /// Write a byte into memory. Video memory is on a 16-bit bus: bytes written to palette
+    /// RAM and BG VRAM land in both halves of the halfword, OAM and OBJ VRAM ignore them.
     pub fn store8(&mut self, addr: u32, val: u8) {
-        match addr as usize {
-            base_addrs::BIOS_ADDR..=0x0000_3FFF => {
-                self.bios[addr as usize - base_addrs::BIOS_ADDR] = val
-            }
-            base_addrs::WORKING_RAM_ADDR..=0x0203_FFFF => {
-                self.wram[addr as usize - base_addrs::WORKING_RAM_ADDR] = val
+        #[cfg(feature = "write-tracking")]
+        self.mark_written(addr);
+
+        if let Some((slice, offset)) = self.page(addr) {
+            if slice.byte_writable() {
+                self.slice_mut(slice)[offset] = val;
+                return;
             }
-            base_addrs::WORKING_IRAM_ADDR..=0x0300_7FFF => {
-                self.iwram[addr as usize - base_addrs::WORKING_IRAM_ADDR] = val
+        }
+
+        match addr >> 24 {
+            0x04 if Self::is_io(addr) => {
+                let shift = (addr & 1) * 8;
+                self.io_write16(addr, (val as u16) << shift, 0xFF << shift)
             }
-            base_addrs::IO_REGISTERS_ADDR..=0x0400_03FE => {
-                self.registers[addr as usize - base_addrs::IO_REGISTERS_ADDR] = val
+            0x05 => self.store16(addr, val as u16 * 0x0101),
+            0x06 if vram_offset(addr) < self.bg_vram_size() => {
+                self.store16(addr, val as u16 * 0x0101)
             }
-            base_addrs::PALETTE_RAM_ADDR..=0x0500_03FF => {
-                self.palette[addr as usize - base_addrs::PALETTE_RAM_ADDR] = val
+            0x08 if gpio::is_register(addr) && addr & 1 == 0 => self.gpio_write(addr, val as u16),
+            0x0D if self.is_eeprom(addr) && addr & 1 == 0 => self.save.eeprom_write(val as u16),
+            0x0E | 0x0F => self.save_write8(addr, val),
+            // the ROM, OBJ VRAM, OAM and unmapped addresses ignore writes
+            _ => (),
+        }
+    }
+
+    /// Write a half-word into memory, the address is aligned down
+    pub fn store16(&mut self, addr: u32, val: u16) {
+        let aligned = addr & !1;
+        #[cfg(feature = "write-tracking")]
+        self.mark_written(aligned);
+
+        if let Some((slice, offset)) = self.page(aligned) {
+            if slice.writable() {
+                write16(self.slice_mut(slice), offset, val);
+                return;
             }
-            base_addrs::VRAM_ADDR..=0x0601_7FFF => {
-                self.vram[addr as usize - base_addrs::VRAM_ADDR] = val
+        }
+
+        match addr >> 24 {
+            // registers see the whole half-word at once
+            0x04 if Self::is_io(addr) => self.io_write16(aligned, val, 0xFFFF),
+            0x08 if gpio::is_register(aligned) => self.gpio_write(aligned, val),
+            0x0D if self.is_eeprom(addr) => self.save.eeprom_write(val),
+            // only the byte lane of the address reaches the save chip
+            0x0E | 0x0F => self.save_write8(addr, (val >> ((addr & 1) * 8)) as u8),
+            _ => (),
+        }
+    }
+
+    /// Write a word into memory, the address is aligned down
+    pub fn store32(&mut self, addr: u32, val: u32) {
+        let aligned = addr & !3;
+        #[cfg(feature = "write-tracking")]
+        self.mark_written(aligned);
+
+        if let Some((slice, offset)) = self.page(aligned) {
+            if slice.writable() {
+                write32(self.slice_mut(slice), offset, val);
+                return;
             }
-            base_addrs::OAM_ADDR..=0x0700_03FF => {
-                self.oam[addr as usize - base_addrs::OAM_ADDR] = val
+        }
+
+        match addr >> 24 {
+            0x04 if Self::is_io(addr) => {
+                self.io_write16(aligned, val as u16, 0xFFFF);
+                if Self::is_io(aligned + 2) {
+                    self.io_write16(aligned + 2, (val >> 16) as u16, 0xFFFF);
+                }
             }
-            base_addrs::CART0_ADDR..=0x0DFF_FFFF => {
-                self.rom[addr as usize - base_addrs::CART0_ADDR] = val
+            0x08 if gpio::is_register(aligned) => {
+                self.gpio_write(aligned, val as u16);
+                if gpio::is_register(aligned + 2) {
+                    self.gpio_write(aligned + 2, (val >> 16) as u16);
+                }
             }
-            base_addrs::CART_SRAM_ADDR..=0x0E00_FFFF => unimplemented!(), // SRAM unimplemented
-            base_addrs::CART_SRAM_MIRROR_ADDR..=0x0F00_FFFF => unimplemented!(), // SRAM unimplemented
-            _ => (), // Consider making this an error
+            0x0D if self.is_eeprom(addr) => self.save.eeprom_write(val as u16),
+            0x0E | 0x0F => self.save_write8(addr, (val >> ((addr & 3) * 8)) as u8),
+            _ => (),
         }
     }
 
-    /// Write an aligned half-word into memory
-    #[allow(overflowing_literals)]
-    pub fn store16(&mut self, addr: u32, val: u16) {
-        self.store8(addr, val as u8);
-        self.store8(addr + 1, (val >> 8) as u8);
+    /// BG VRAM is 64KiB in the tile modes and 80KiB in the bitmap modes, OBJ tiles take the rest
+    fn bg_vram_size(&self) -> usize {
+        if self.io_raw(io::registers::DISPCNT) & 0b111 >= 3 {
+            0x1_4000
+        } else {
+            0x1_0000
+        }
     }
 
-    /// Write an aligned word into memory
-    #[allow(overflowing_literals)]
-    pub fn store32(&mut self, addr: u32, val: u32) {
-        self.store8(addr, val as u8);
-        self.store8(addr + 1, (val >> 8) as u8);
-        self.store8(addr + 2, (val >> 16) as u8);
-        self.store8(addr + 3, (val >> 24) as u8);
+    #[inline]
+    fn is_io(addr: u32) -> bool {
+        (base_addrs::IO_REGISTERS_ADDR..=0x0400_03FE).contains(&(addr as usize))
+    }
+
+    /// The EEPROM takes the upper 16MiB of the last waitstate region, or only its last
+    /// 256 bytes when the ROM is bigger than 16MiB
+    #[inline]
+    fn is_eeprom(&self, addr: u32) -> bool {
+        let start = if self.rom_size > 0x100_0000 {
+            0x0DFF_FF00
+        } else {
+            base_addrs::CART2_EX_ADDR
+        };
+        (start..=0x0DFF_FFFF).contains(&(addr as usize)) && self.save.is_eeprom()
     }
 }
+
+#[inline]
+fn read16(memory: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([memory[offset], memory[offset + 1]])
+}
+
+#[inline]
+fn read32(memory: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(memory[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+fn write16(memory: &mut [u8], offset: usize, value: u16) {
+    memory[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+#[inline]
+fn write32(memory: &mut [u8], offset: usize, value: u32) {
+    memory[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// VRAM is 96KiB repeated every 128KiB, the last 32KiB show the OBJ tiles at 0x10000 again
+#[inline]
+fn vram_offset(addr: u32) -> usize {
+    let offset = addr as usize & 0x1_FFFF;
+    if offset >= sizes::VRAM_SIZE {
+        offset - 0x8000
+    } else {
+        offset
+    }
+}
+
+pub mod tests;
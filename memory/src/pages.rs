@@ -0,0 +1,125 @@
+// Page table over the 0x00000000-0x0FFFFFFF address space. Pages of plain memory are read and
+// written straight from their backing slice, the others go through the region match of the
+// MMU: registers, save chips, open bus and the end of the ROM.
+
+use crate::{sizes, vram_offset, MMU};
+
+/// Pages are 16KiB, the size of the BIOS
+pub const PAGE_SHIFT: u32 = 14;
+const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+const PAGE_COUNT: usize = 0x1000_0000 >> PAGE_SHIFT;
+
+/// Backing slices of the MMU
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Slice {
+    Bios,
+    Wram,
+    Iwram,
+    Palette,
+    Vram,
+    Oam,
+    Rom,
+}
+
+impl Slice {
+    /// Byte stores reach the slice as they are, video memory has its own rules for them
+    pub(crate) fn byte_writable(self) -> bool {
+        matches!(self, Slice::Bios | Slice::Wram | Slice::Iwram)
+    }
+
+    pub(crate) fn writable(self) -> bool {
+        self != Slice::Rom
+    }
+}
+
+/// What a page is mapped to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Page {
+    /// Bytes of slice from base, offsets in the page are masked for regions smaller than a page
+    Memory {
+        slice: Slice,
+        base: usize,
+        mask: usize,
+    },
+    /// Handled by the region match
+    Handler,
+}
+
+/// Page table for a ROM of rom_size bytes
+pub(crate) fn build(rom_size: usize) -> Box<[Page]> {
+    (0..PAGE_COUNT)
+        .map(|page| map(page << PAGE_SHIFT, rom_size))
+        .collect()
+}
+
+pub(crate) fn map(addr: usize, rom_size: usize) -> Page {
+    let memory = |slice, base, size: usize| Page::Memory {
+        slice,
+        base,
+        mask: size.min(PAGE_SIZE) - 1,
+    };
+
+    match addr >> 24 {
+        0x00 if addr < sizes::BIOS_SIZE => memory(Slice::Bios, addr, sizes::BIOS_SIZE),
+        0x02 => memory(Slice::Wram, addr & (sizes::WRAM_SIZE - 1), sizes::WRAM_SIZE),
+        0x03 => memory(
+            Slice::Iwram,
+            addr & (sizes::IWRAM_SIZE - 1),
+            sizes::IWRAM_SIZE,
+        ),
+        0x05 => memory(Slice::Palette, 0, sizes::PALETTE_RAM_SIZE),
+        0x06 => memory(Slice::Vram, vram_offset(addr as u32), sizes::VRAM_SIZE),
+        0x07 => memory(Slice::Oam, 0, sizes::OAM_SIZE),
+        // the EEPROM can take over 0x0D, the ROM seen there goes through the handler
+        0x08..=0x0C => {
+            let base = addr & (sizes::CART0_SIZE - 1);
+            if base + PAGE_SIZE <= rom_size {
+                memory(Slice::Rom, base, PAGE_SIZE)
+            } else {
+                Page::Handler
+            }
+        }
+        _ => Page::Handler,
+    }
+}
+
+impl MMU {
+    /// Slice and offset in it for addr, when its page is plain memory
+    #[inline]
+    pub(crate) fn page(&self, addr: u32) -> Option<(Slice, usize)> {
+        match self.pages.get((addr >> PAGE_SHIFT) as usize) {
+            Some(&Page::Memory { slice, base, mask }) => {
+                Some((slice, base + (addr as usize & mask)))
+            }
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn slice(&self, slice: Slice) -> &[u8] {
+        match slice {
+            Slice::Bios => &self.bios,
+            Slice::Wram => &self.wram,
+            Slice::Iwram => &self.iwram,
+            Slice::Palette => &self.palette,
+            Slice::Vram => &self.vram,
+            Slice::Oam => &self.oam,
+            Slice::Rom => &self.rom,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn slice_mut(&mut self, slice: Slice) -> &mut [u8] {
+        match slice {
+            Slice::Bios => &mut self.bios,
+            Slice::Wram => &mut self.wram,
+            Slice::Iwram => &mut self.iwram,
+            Slice::Palette => &mut self.palette,
+            Slice::Vram => &mut self.vram,
+            Slice::Oam => &mut self.oam,
+            Slice::Rom => &mut self.rom,
+        }
+    }
+}
+
+pub mod tests;
@@ -0,0 +1,45 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_pages_mapping() {
+        let mmu = MMU::new();
+        assert_eq!(mmu.page(0x0000_3FFF), Some((Slice::Bios, 0x3FFF)));
+        assert_eq!(mmu.page(0x0000_4000), None);
+        assert_eq!(mmu.page(0x02FC_0010), Some((Slice::Wram, 0x10)));
+        assert_eq!(mmu.page(0x03FF_FFFC), Some((Slice::Iwram, 0x7FFC)));
+        assert_eq!(mmu.page(0x0400_0000), None);
+        assert_eq!(mmu.page(0x0500_0402), Some((Slice::Palette, 2)));
+        assert_eq!(mmu.page(0x0601_C004), Some((Slice::Vram, 0x1_4004)));
+        assert_eq!(mmu.page(0x07FF_FFFE), Some((Slice::Oam, 0x3FE)));
+        // no ROM loaded
+        assert_eq!(mmu.page(0x0800_0000), None);
+        assert_eq!(mmu.page(0x0E00_0000), None);
+        assert_eq!(mmu.page(0x1000_0000), None);
+        assert_eq!(mmu.page(0xFFFF_FFFF), None);
+    }
+
+    #[test]
+    fn test_pages_rom() {
+        let mut mmu = MMU::new();
+        let rom: Vec<u8> = (0..0x6000).map(|i| (i >> 4) as u8).collect();
+        mmu.load_rom(&rom);
+        assert_eq!(mmu.page(0x0A00_0010), Some((Slice::Rom, 0x10)));
+        // the last page is only half full
+        assert_eq!(mmu.page(0x0800_4000), None);
+
+        assert_eq!(mmu.load32(0x0800_3FFC), 0xFFFF_FFFF);
+        assert_eq!(mmu.load32(0x0800_4010), 0x0101_0101);
+        assert_eq!(mmu.load16(0x0800_6000), 0x3000);
+        assert_eq!(mmu.load8(0x0C00_0020), 0x02);
+        // 0x0D is the upper 16MiB of the ROM, past its end
+        assert_eq!(mmu.load8(0x0D00_0020), 0x10);
+
+        // the ROM ignores writes on both paths
+        mmu.store32(0x0800_0000, 0x1234_5678);
+        mmu.store16(0x0800_4000, 0x1234);
+        assert_eq!(mmu.load32(0x0800_0000), 0);
+        assert_eq!(mmu.load16(0x0800_4000), 0);
+    }
+}
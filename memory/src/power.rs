@@ -0,0 +1,57 @@
+// Low power modes entered by writing HALTCNT, usually through the Halt and Stop BIOS calls.
+
+use crate::io::interrupts;
+use crate::MMU;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Power {
+    Running,
+    /// The CPU waits for an interrupt, the rest of the system keeps going
+    Halted,
+    /// Everything is stopped until a keypad, serial or game pak interrupt
+    Stopped,
+}
+
+// #[default] on enum variants needs Rust 1.62
+#[allow(clippy::derivable_impls)]
+impl Default for Power {
+    fn default() -> Self {
+        Self::Running
+    }
+}
+
+/// Called on writes to POSTFLG/HALTCNT, bit 7 of HALTCNT picks stop over halt
+pub(crate) fn halt_written(mmu: &mut MMU, _: u32, _: u16, written: u16, lanes: u16) {
+    if lanes & 0xFF00 != 0 {
+        mmu.power = if written & 0x8000 != 0 {
+            Power::Stopped
+        } else {
+            Power::Halted
+        };
+    }
+}
+
+impl MMU {
+    pub fn power(&self) -> Power {
+        self.power
+    }
+
+    /// Wakes up once an interrupt that ends the current mode is requested,
+    /// returns true while still halted or stopped
+    pub fn sleeping(&mut self) -> bool {
+        let wake = match self.power {
+            Power::Running => return false,
+            // IME does not matter, only IE and IF
+            Power::Halted => self.pending_interrupts() != 0,
+            Power::Stopped => {
+                self.pending_interrupts()
+                    & (interrupts::KEYPAD | interrupts::SERIAL | interrupts::GAMEPAK)
+                    != 0
+            }
+        };
+        if wake {
+            self.power = Power::Running;
+        }
+        !wake
+    }
+}
@@ -0,0 +1,180 @@
+// Battery backed save memory on the cartridge, kept in a .sav file next to the ROM.
+
+use std::path::{Path, PathBuf};
+
+mod detect;
+mod eeprom;
+mod flash;
+mod sram;
+pub use detect::{detect, SaveType};
+pub use eeprom::Eeprom;
+pub use flash::{Flash, FlashId};
+pub use sram::Sram;
+
+/// Save chip found on the cartridge
+#[derive(Clone)]
+pub enum Chip {
+    None,
+    Sram(Sram),
+    Flash(Flash),
+    Eeprom(Eeprom),
+}
+
+impl Chip {
+    fn data(&self) -> &[u8] {
+        match self {
+            Chip::None => &[],
+            Chip::Sram(sram) => sram.data(),
+            Chip::Flash(flash) => flash.data(),
+            Chip::Eeprom(eeprom) => eeprom.data(),
+        }
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        match self {
+            Chip::None => &mut [],
+            Chip::Sram(sram) => sram.data_mut(),
+            Chip::Flash(flash) => flash.data_mut(),
+            Chip::Eeprom(eeprom) => eeprom.data_mut(),
+        }
+    }
+}
+
+/// Cycles without a write after which the save is written to its file, about a second
+pub const FLUSH_DELAY: u32 = 16_777_216;
+
+#[derive(Clone)]
+pub struct Save {
+    pub chip: Chip,
+    /// Where the save gets flushed, nothing is written without one
+    path: Option<PathBuf>,
+    /// Written since the last flush
+    dirty: bool,
+    /// Cycles since the last write
+    quiet: u32,
+}
+
+impl Default for Save {
+    fn default() -> Self {
+        Self::new(Chip::None)
+    }
+}
+
+impl Save {
+    /// A save that only lives in memory
+    pub fn new(chip: Chip) -> Self {
+        Self {
+            chip,
+            path: None,
+            dirty: false,
+            quiet: 0,
+        }
+    }
+
+    /// Save file of a ROM, the same name with a .sav extension
+    pub fn path_for(rom_path: &Path) -> PathBuf {
+        rom_path.with_extension("sav")
+    }
+
+    /// A save backed by a file, loading it when it already exists
+    pub fn open(path: &Path, chip: Chip) -> Result<Self, String> {
+        let mut save = Self::new(chip);
+        save.path = Some(path.to_path_buf());
+
+        match std::fs::read(path) {
+            Ok(contents) => {
+                // files from other emulators may be padded or cut short
+                let data = save.chip.data_mut();
+                let len = contents.len().min(data.len());
+                data[..len].copy_from_slice(&contents[..len]);
+                if let Chip::Eeprom(ref mut eeprom) = save.chip {
+                    eeprom.file_loaded(contents.len());
+                }
+            }
+            Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => (),
+            Err(error) => return Err(format!("Unable to read {}: {}", path.display(), error)),
+        }
+
+        Ok(save)
+    }
+
+    /// Writes the save to its file if it changed
+    pub fn flush(&mut self) -> Result<(), String> {
+        let path = match self.path {
+            Some(ref path) if self.dirty => path,
+            _ => return Ok(()),
+        };
+
+        std::fs::write(path, self.chip.data())
+            .map_err(|error| format!("Unable to write {}: {}", path.display(), error))?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Writes the save to its file once the game has not written to it for FLUSH_DELAY
+    /// cycles, so progress is kept even when the emulator does not exit cleanly
+    pub fn step(&mut self, cycles: u32) -> Result<(), String> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        self.quiet = self.quiet.saturating_add(cycles);
+        if self.quiet < FLUSH_DELAY {
+            return Ok(());
+        }
+        // a failed write is tried again after another delay
+        self.quiet = 0;
+        self.flush()
+    }
+
+    /// Reads from the save region, 0x0E000000-0x0FFFFFFF
+    pub fn read8(&self, addr: u32) -> u8 {
+        match self.chip {
+            Chip::None | Chip::Eeprom(_) => 0xFF,
+            Chip::Sram(ref sram) => sram.read(addr),
+            Chip::Flash(ref flash) => flash.read(addr),
+        }
+    }
+
+    /// Writes to the save region, 0x0E000000-0x0FFFFFFF
+    pub fn write8(&mut self, addr: u32, value: u8) {
+        match self.chip {
+            Chip::None | Chip::Eeprom(_) => return,
+            Chip::Sram(ref mut sram) => sram.write(addr, value),
+            Chip::Flash(ref mut flash) => flash.write(addr, value),
+        }
+        self.dirty = true;
+        self.quiet = 0;
+    }
+
+    /// The EEPROM is not in the save region but at the top of the ROM, 0x0D000000-0x0DFFFFFF
+    pub fn is_eeprom(&self) -> bool {
+        matches!(self.chip, Chip::Eeprom(_))
+    }
+
+    /// Reads the next bit from the EEPROM
+    pub fn eeprom_read(&self) -> u16 {
+        match self.chip {
+            Chip::Eeprom(ref eeprom) => eeprom.read(),
+            _ => 0,
+        }
+    }
+
+    /// Sends the next bit to the EEPROM
+    pub fn eeprom_write(&mut self, value: u16) {
+        if let Chip::Eeprom(ref mut eeprom) = self.chip {
+            eeprom.write(value);
+            self.dirty = true;
+            self.quiet = 0;
+        }
+    }
+
+    /// DMA is about to send count bits to the EEPROM
+    pub fn eeprom_dma_started(&mut self, count: u32) {
+        if let Chip::Eeprom(ref mut eeprom) = self.chip {
+            eeprom.dma_started(count);
+        }
+    }
+}
+
+pub mod tests;
@@ -0,0 +1,103 @@
+use super::{Chip, Eeprom, Flash, Sram};
+use crate::cartridge::{self, Header};
+use crate::sizes;
+
+/// Kinds of save chips a cartridge can have
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SaveType {
+    None,
+    Sram,
+    Flash64K,
+    Flash128K,
+    /// EEPROM sized by the first DMA request
+    Eeprom,
+    Eeprom512,
+    Eeprom8K,
+}
+
+impl SaveType {
+    /// Names accepted on the command line
+    pub const NAMES: &'static [&'static str] = &[
+        "none",
+        "sram",
+        "flash64",
+        "flash128",
+        "eeprom",
+        "eeprom512",
+        "eeprom8k",
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let save_type = match name.to_lowercase().as_str() {
+            "none" => SaveType::None,
+            "sram" => SaveType::Sram,
+            "flash64" | "flash512" => SaveType::Flash64K,
+            "flash128" | "flash1m" => SaveType::Flash128K,
+            "eeprom" => SaveType::Eeprom,
+            "eeprom512" => SaveType::Eeprom512,
+            "eeprom8k" => SaveType::Eeprom8K,
+            _ => return None,
+        };
+        Some(save_type)
+    }
+
+    /// An empty chip of this type
+    pub fn chip(self) -> Chip {
+        match self {
+            SaveType::None => Chip::None,
+            SaveType::Sram => Chip::Sram(Sram::default()),
+            SaveType::Flash64K => Chip::Flash(Flash::new_64k()),
+            SaveType::Flash128K => Chip::Flash(Flash::new_128k()),
+            SaveType::Eeprom => Chip::Eeprom(Eeprom::new(None)),
+            SaveType::Eeprom512 => Chip::Eeprom(Eeprom::new(Some(sizes::CART_EEPROM512_SIZE))),
+            SaveType::Eeprom8K => Chip::Eeprom(Eeprom::new(Some(sizes::CART_EEPROM_SIZE))),
+        }
+    }
+}
+
+/// Games whose library string is missing or wrong, by game code
+const OVERRIDES: &[(&str, SaveType)] = &[
+    ("AFXE", SaveType::Flash64K),  // Final Fantasy Tactics Advance
+    ("AGFE", SaveType::Flash64K),  // Golden Sun: The Lost Age
+    ("AREE", SaveType::Sram),      // Mega Man Battle Network
+    ("AX4E", SaveType::Flash128K), // Super Mario Advance 4
+    ("AXPE", SaveType::Flash128K), // Pokemon Sapphire
+    ("AXVE", SaveType::Flash128K), // Pokemon Ruby
+    ("AZCE", SaveType::Sram),      // Mega Man Zero
+    ("B24E", SaveType::Flash128K), // Pokemon Mystery Dungeon: Red Rescue Team
+    ("BFTJ", SaveType::Flash128K), // F-Zero: Climax
+    ("BPEE", SaveType::Flash128K), // Pokemon Emerald
+    ("BPGE", SaveType::Flash128K), // Pokemon LeafGreen
+    ("BPRE", SaveType::Flash128K), // Pokemon FireRed
+    ("BSME", SaveType::Eeprom),    // Metal Slug Advance
+    ("KHPJ", SaveType::Eeprom512), // Koro Koro Puzzle: Happy Panechu!
+    ("KYGE", SaveType::Eeprom),    // Yoshi's Universal Gravitation
+    ("RZWE", SaveType::Sram),      // WarioWare: Twisted!
+    ("U32E", SaveType::Eeprom),    // Boktai 2: Solar Boy Django
+    ("U3IE", SaveType::Eeprom),    // Boktai: The Sun Is in Your Hand
+    ("V49E", SaveType::Sram),      // Drill Dozer
+];
+
+/// Strings the Nintendo SDK save libraries leave in the ROM, longest first so
+/// FLASH512_V is not taken for FLASH_V
+const LIBRARY_IDS: &[(&[u8], SaveType)] = &[
+    (b"FLASH512_V", SaveType::Flash64K),
+    (b"FLASH1M_V", SaveType::Flash128K),
+    (b"EEPROM_V", SaveType::Eeprom),
+    (b"SRAM_F_V", SaveType::Sram),
+    (b"FLASH_V", SaveType::Flash64K),
+    (b"SRAM_V", SaveType::Sram),
+];
+
+/// The save type of a game, from the override table or the library strings in the ROM.
+/// Games without either get SRAM, which is only written out if the game uses it.
+pub fn detect(rom: &[u8]) -> SaveType {
+    if let Ok(header) = Header::parse(rom) {
+        let code = header.game_code.as_str();
+        if let Some(&(_, save_type)) = OVERRIDES.iter().find(|(game, _)| *game == code) {
+            return save_type;
+        }
+    }
+
+    cartridge::find_library(rom, LIBRARY_IDS).unwrap_or(SaveType::Sram)
+}
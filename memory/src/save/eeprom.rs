@@ -0,0 +1,137 @@
+use crate::sizes;
+use std::cell::Cell;
+
+/// Serial EEPROM, talked to one bit at a time through bit 0 of halfword accesses,
+/// usually with DMA3. Requests are sent MSB first:
+/// - read: 11, address, 0 then 68 bits are read back, 4 junk bits and 64 data bits
+/// - write: 10, address, 64 data bits, 0 then reading returns 1 once the write is done
+#[derive(Clone)]
+pub struct Eeprom {
+    data: Box<[u8]>,
+    /// 512 bytes with 6 bit addresses or 8KiB with 14 bit addresses, unknown until the game
+    /// sends its first request by DMA
+    size: Option<usize>,
+    /// Bits of the request being sent
+    request: Vec<u8>,
+    /// Bits left to read back, the next one is at the end
+    response: Vec<u8>,
+    /// Reads take bits out of the response, so they happen behind a shared reference
+    read_position: Cell<usize>,
+}
+
+impl Default for Eeprom {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Eeprom {
+    pub fn new(size: Option<usize>) -> Self {
+        Self {
+            data: vec![0xFF; sizes::CART_EEPROM_SIZE].into_boxed_slice(),
+            size,
+            request: Vec::new(),
+            response: Vec::new(),
+            read_position: Cell::new(0),
+        }
+    }
+
+    pub fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    /// Width of the addresses sent by the game
+    fn address_bits(&self) -> usize {
+        match self.size {
+            Some(sizes::CART_EEPROM512_SIZE) => 6,
+            _ => 14,
+        }
+    }
+
+    /// A DMA of count halfwords is about to write a request, its length tells the chip size
+    pub fn dma_started(&mut self, count: u32) {
+        if self.size.is_some() {
+            return;
+        }
+
+        self.size = match count {
+            // read requests of 2 + 6 + 1 bits and write requests of 2 + 6 + 64 + 1 bits
+            9 | 73 => Some(sizes::CART_EEPROM512_SIZE),
+            17 | 81 => Some(sizes::CART_EEPROM_SIZE),
+            _ => None,
+        };
+    }
+
+    /// A save file was loaded, its length tells the chip size
+    pub(crate) fn file_loaded(&mut self, len: usize) {
+        if self.size.is_none()
+            && (len == sizes::CART_EEPROM512_SIZE || len == sizes::CART_EEPROM_SIZE)
+        {
+            self.size = Some(len);
+        }
+    }
+
+    /// Reads the next bit of the response, 1 when there is none as the chip is ready
+    pub fn read(&self) -> u16 {
+        let position = self.read_position.get();
+        match self.response.len().checked_sub(position + 1) {
+            Some(index) => {
+                self.read_position.set(position + 1);
+                self.response[index] as u16
+            }
+            None => 1,
+        }
+    }
+
+    /// Sends the next bit of a request
+    pub fn write(&mut self, value: u16) {
+        // a new request ends the previous read
+        if !self.response.is_empty() {
+            self.response.clear();
+            self.read_position.set(0);
+        }
+        self.request.push(value as u8 & 1);
+
+        let address_bits = self.address_bits();
+        match self.request[..] {
+            [1, 1, ..] if self.request.len() == 2 + address_bits + 1 => {
+                let offset = self.block_offset(address_bits);
+                // popped from the end, data MSB first followed by 4 junk bits
+                self.response = (0..64)
+                    .rev()
+                    .map(|bit| self.data[offset + bit / 8] >> (7 - bit % 8) & 1)
+                    .chain([0; 4].iter().copied())
+                    .collect();
+                self.request.clear();
+            }
+            [1, 0, ..] if self.request.len() == 2 + address_bits + 64 + 1 => {
+                let offset = self.block_offset(address_bits);
+                for byte in 0..8 {
+                    let bits = &self.request[2 + address_bits + byte * 8..][..8];
+                    self.data[offset + byte] = bits.iter().fold(0, |value, &bit| value << 1 | bit);
+                }
+                self.request.clear();
+            }
+            // requests start with a 1
+            [0] => self.request.clear(),
+            _ => (),
+        }
+    }
+
+    /// Offset of the 8 byte block addressed by the request
+    fn block_offset(&self, address_bits: usize) -> usize {
+        let address = self.request[2..2 + address_bits]
+            .iter()
+            .fold(0, |address, &bit| address << 1 | bit as usize);
+        // 8KiB chips only use the lower 10 of the 14 bits
+        address * 8 % self.data().len()
+    }
+
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data[..self.size.unwrap_or(sizes::CART_EEPROM_SIZE)]
+    }
+
+    pub(crate) fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
@@ -0,0 +1,175 @@
+use crate::sizes;
+
+/// Flash chips found on cartridges, as (manufacturer, device) IDs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlashId {
+    /// SST 39VF512, 64KiB
+    Sst,
+    /// Macronix MX29L512, 64KiB
+    Macronix64,
+    /// Panasonic MN63F805MNP, 64KiB
+    Panasonic,
+    /// Atmel AT29LV512, 64KiB written in 128 byte pages
+    Atmel,
+    /// Sanyo LE26FV10N1TS, 128KiB
+    Sanyo,
+    /// Macronix MX29L010, 128KiB
+    Macronix128,
+}
+
+impl FlashId {
+    /// Manufacturer and device codes read in ID mode
+    pub fn codes(self) -> (u8, u8) {
+        match self {
+            FlashId::Sst => (0xBF, 0xD4),
+            FlashId::Macronix64 => (0xC2, 0x1C),
+            FlashId::Panasonic => (0x32, 0x1B),
+            FlashId::Atmel => (0x1F, 0x3D),
+            FlashId::Sanyo => (0x62, 0x13),
+            FlashId::Macronix128 => (0xC2, 0x09),
+        }
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            FlashId::Sanyo | FlashId::Macronix128 => sizes::CART_FLASH1M_SIZE,
+            _ => sizes::CART_FLASH512_SIZE,
+        }
+    }
+}
+
+/// Where the chip is in the command protocol
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Ready,
+    /// 0xAA was written to 0x5555
+    Unlock1,
+    /// 0x55 was written to 0x2AAA, the command comes next
+    Unlock2,
+    /// The next write is programmed, counting the bytes left in the page
+    Program(usize),
+    /// The next write to 0x0000 picks the 64KiB bank
+    Bank,
+}
+
+const SECTOR_SIZE: usize = 0x1000;
+const ATMEL_PAGE_SIZE: usize = 128;
+
+/// Flash memory driven by writing commands to 0x5555 and 0x2AAA
+#[derive(Clone)]
+pub struct Flash {
+    id: FlashId,
+    data: Box<[u8]>,
+    state: State,
+    id_mode: bool,
+    /// An erase command (0x80) was given, an erase follows the next unlock
+    erase_armed: bool,
+    bank: usize,
+}
+
+impl Flash {
+    pub fn new(id: FlashId) -> Self {
+        Self {
+            id,
+            data: vec![0xFF; id.size()].into_boxed_slice(),
+            state: State::Ready,
+            id_mode: false,
+            erase_armed: false,
+            bank: 0,
+        }
+    }
+
+    /// The usual 64KiB chip
+    pub fn new_64k() -> Self {
+        Self::new(FlashId::Panasonic)
+    }
+
+    /// The usual 128KiB chip
+    pub fn new_128k() -> Self {
+        Self::new(FlashId::Sanyo)
+    }
+
+    pub fn id(&self) -> FlashId {
+        self.id
+    }
+
+    pub fn read(&self, addr: u32) -> u8 {
+        let offset = addr as usize & 0xFFFF;
+        if self.id_mode && offset < 2 {
+            let (manufacturer, device) = self.id.codes();
+            return if offset == 0 { manufacturer } else { device };
+        }
+        self.data[self.bank * 0x1_0000 + offset]
+    }
+
+    pub fn write(&mut self, addr: u32, value: u8) {
+        let offset = addr as usize & 0xFFFF;
+
+        self.state = match (self.state, offset, value) {
+            (State::Program(left), _, _) => {
+                self.data[self.bank * 0x1_0000 + offset] = value;
+                if left > 1 {
+                    State::Program(left - 1)
+                } else {
+                    State::Ready
+                }
+            }
+            (State::Bank, 0x0000, _) => {
+                // only 128KiB chips have a second bank
+                self.bank = value as usize & 1 & (self.data.len() >> 17);
+                State::Ready
+            }
+
+            (State::Ready, 0x5555, 0xAA) => State::Unlock1,
+            (State::Unlock1, 0x2AAA, 0x55) => State::Unlock2,
+            (State::Unlock2, 0x5555, 0xA0) if self.id == FlashId::Atmel => {
+                State::Program(ATMEL_PAGE_SIZE)
+            }
+            (State::Unlock2, 0x5555, 0xA0) => State::Program(1),
+            (State::Unlock2, 0x5555, 0xB0) => State::Bank,
+            (State::Unlock2, _, _) => {
+                self.command(offset, value);
+                State::Ready
+            }
+
+            // the terminate command also works without unlocking first
+            (_, _, 0xF0) => {
+                self.id_mode = false;
+                self.erase_armed = false;
+                State::Ready
+            }
+            _ => State::Ready,
+        };
+    }
+
+    /// Runs a command given after the unlock sequence
+    fn command(&mut self, offset: usize, value: u8) {
+        let erase_armed = std::mem::replace(&mut self.erase_armed, false);
+
+        match (offset, value) {
+            (0x5555, 0x90) => self.id_mode = true,
+            (0x5555, 0xF0) => self.id_mode = false,
+            (0x5555, 0x80) => self.erase_armed = true,
+            (0x5555, 0x10) if erase_armed => {
+                for byte in self.data.iter_mut() {
+                    *byte = 0xFF;
+                }
+            }
+            (_, 0x30) if erase_armed => {
+                let start = self.bank * 0x1_0000 + (offset & !(SECTOR_SIZE - 1));
+                for byte in self.data[start..start + SECTOR_SIZE].iter_mut() {
+                    *byte = 0xFF;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub(crate) fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
@@ -0,0 +1,33 @@
+use crate::sizes;
+
+/// 32KiB of static RAM, mirrored across the whole save region
+#[derive(Clone)]
+pub struct Sram {
+    data: Box<[u8]>,
+}
+
+impl Default for Sram {
+    fn default() -> Self {
+        Self {
+            data: vec![0xFF; sizes::CART_SRAM_SIZE].into_boxed_slice(),
+        }
+    }
+}
+
+impl Sram {
+    pub fn read(&self, addr: u32) -> u8 {
+        self.data[addr as usize % sizes::CART_SRAM_SIZE]
+    }
+
+    pub fn write(&mut self, addr: u32, value: u8) {
+        self.data[addr as usize % sizes::CART_SRAM_SIZE] = value;
+    }
+
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub(crate) fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
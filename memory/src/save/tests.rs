@@ -0,0 +1,246 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::{sizes, MMU};
+
+    #[test]
+    fn test_save_sram_bus() {
+        let mut mmu = MMU::new();
+        mmu.save = Save::new(Chip::Sram(Default::default()));
+
+        mmu.store8(0x0E00_0010, 0x42);
+        assert_eq!(mmu.load8(0x0E00_0010), 0x42);
+        // mirrored every 32KiB
+        assert_eq!(mmu.load8(0x0E00_8010), 0x42);
+        assert_eq!(mmu.load8(0x0F00_0010), 0x42);
+
+        // wide reads repeat the byte, wide writes only store the addressed byte
+        assert_eq!(mmu.load16(0x0E00_0010), 0x4242);
+        assert_eq!(mmu.load32(0x0E00_0010), 0x4242_4242);
+        mmu.store32(0x0E00_0021, 0x4433_2211);
+        assert_eq!(mmu.load8(0x0E00_0020), 0xFF);
+        assert_eq!(mmu.load8(0x0E00_0021), 0x22);
+        assert_eq!(mmu.load8(0x0E00_0022), 0xFF);
+    }
+
+    #[test]
+    fn test_save_no_chip() {
+        let mut mmu = MMU::new();
+        mmu.store8(0x0E00_0000, 0x12);
+        assert_eq!(mmu.load8(0x0E00_0000), 0xFF);
+    }
+
+    #[test]
+    fn test_save_file_round_trip() {
+        let rom = std::env::temp_dir().join(format!("velera-save-{}.gba", std::process::id()));
+        let path = Save::path_for(&rom);
+        assert_eq!(path.extension().unwrap(), "sav");
+        let _ = std::fs::remove_file(&path);
+
+        let mut save = Save::open(&path, Chip::Sram(Default::default())).unwrap();
+        // nothing to write yet
+        save.flush().unwrap();
+        assert!(!path.exists());
+
+        save.write8(0x0E00_1234, 0xAB);
+        save.flush().unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0x8000);
+
+        let save = Save::open(&path, Chip::Sram(Default::default())).unwrap();
+        assert_eq!(save.read8(0x0E00_1234), 0xAB);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_save_flush_when_quiet() {
+        let rom = std::env::temp_dir().join(format!("velera-quiet-{}.gba", std::process::id()));
+        let path = Save::path_for(&rom);
+        let _ = std::fs::remove_file(&path);
+
+        let mut save = Save::open(&path, Chip::Sram(Default::default())).unwrap();
+        save.step(FLUSH_DELAY).unwrap();
+        assert!(!path.exists());
+
+        // every write puts the flush off
+        save.write8(0x0E00_0000, 0x12);
+        save.step(FLUSH_DELAY - 1).unwrap();
+        save.write8(0x0E00_0001, 0x34);
+        save.step(FLUSH_DELAY - 1).unwrap();
+        assert!(!path.exists());
+
+        save.step(1).unwrap();
+        let contents = std::fs::read(&path).unwrap();
+        assert_eq!(&contents[..2], &[0x12, 0x34]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Writes the unlock sequence followed by a command
+    fn flash_command(save: &mut Save, command: u8) {
+        save.write8(0x0E00_5555, 0xAA);
+        save.write8(0x0E00_2AAA, 0x55);
+        save.write8(0x0E00_5555, command);
+    }
+
+    #[test]
+    fn test_save_flash_commands() {
+        let mut save = Save::new(Chip::Flash(Flash::new(FlashId::Macronix64)));
+
+        flash_command(&mut save, 0x90);
+        assert_eq!(save.read8(0x0E00_0000), 0xC2);
+        assert_eq!(save.read8(0x0E00_0001), 0x1C);
+        flash_command(&mut save, 0xF0);
+        assert_eq!(save.read8(0x0E00_0000), 0xFF);
+
+        // writes without the program command are ignored
+        save.write8(0x0E00_1000, 0x12);
+        assert_eq!(save.read8(0x0E00_1000), 0xFF);
+        flash_command(&mut save, 0xA0);
+        save.write8(0x0E00_1000, 0x12);
+        flash_command(&mut save, 0xA0);
+        save.write8(0x0E00_2000, 0x34);
+        assert_eq!(save.read8(0x0E00_1000), 0x12);
+
+        // sector erase only clears the 4KiB sector
+        flash_command(&mut save, 0x80);
+        save.write8(0x0E00_5555, 0xAA);
+        save.write8(0x0E00_2AAA, 0x55);
+        save.write8(0x0E00_1000, 0x30);
+        assert_eq!(save.read8(0x0E00_1000), 0xFF);
+        assert_eq!(save.read8(0x0E00_2000), 0x34);
+
+        flash_command(&mut save, 0x80);
+        flash_command(&mut save, 0x10);
+        assert_eq!(save.read8(0x0E00_2000), 0xFF);
+    }
+
+    #[test]
+    fn test_save_flash_banks() {
+        let mut save = Save::new(Chip::Flash(Flash::new_128k()));
+        assert_eq!(save.chip.data().len(), 0x2_0000);
+
+        flash_command(&mut save, 0xB0);
+        save.write8(0x0E00_0000, 1);
+        flash_command(&mut save, 0xA0);
+        save.write8(0x0E00_0010, 0x56);
+        assert_eq!(save.read8(0x0E00_0010), 0x56);
+        assert_eq!(save.chip.data()[0x1_0010], 0x56);
+
+        flash_command(&mut save, 0xB0);
+        save.write8(0x0E00_0000, 0);
+        assert_eq!(save.read8(0x0E00_0010), 0xFF);
+
+        // 64KiB chips have no banks
+        let mut save = Save::new(Chip::Flash(Flash::new_64k()));
+        flash_command(&mut save, 0xB0);
+        save.write8(0x0E00_0000, 1);
+        flash_command(&mut save, 0x90);
+        assert_eq!(save.read8(0x0E00_0001), 0x1B);
+    }
+
+    /// Puts a request in EWRAM as one bit per halfword and sends it with DMA3
+    fn eeprom_request(mmu: &mut MMU, bits: &[u8]) {
+        for (i, &bit) in bits.iter().enumerate() {
+            mmu.store16(0x200_0000 + i as u32 * 2, bit as u16);
+        }
+        mmu.store32(0x400_00D4, 0x200_0000);
+        mmu.store32(0x400_00D8, 0x0D00_0000);
+        mmu.store16(0x400_00DC, bits.len() as u16);
+        mmu.store16(0x400_00DE, 0x8000);
+        mmu.run_dma();
+    }
+
+    fn bits(value: u64, width: usize) -> Vec<u8> {
+        (0..width)
+            .rev()
+            .map(|bit| (value >> bit) as u8 & 1)
+            .collect()
+    }
+
+    #[test]
+    fn test_save_eeprom_protocol() {
+        let mut mmu = MMU::new();
+        mmu.save = Save::new(Chip::Eeprom(Default::default()));
+
+        // write 6 bit address 3, the request length gives away the 512 byte chip
+        let mut request = vec![1, 0];
+        request.extend(bits(3, 6));
+        request.extend(bits(0x0123_4567_89AB_CDEF, 64));
+        request.push(0);
+        eeprom_request(&mut mmu, &request);
+        assert_eq!(mmu.save.chip.data().len(), 0x200);
+        assert_eq!(
+            mmu.save.chip.data()[24..32],
+            [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF]
+        );
+        // ready
+        assert_eq!(mmu.load16(0x0D00_0000), 1);
+
+        let mut request = vec![1, 1];
+        request.extend(bits(3, 6));
+        request.push(0);
+        eeprom_request(&mut mmu, &request);
+
+        let response: Vec<u8> = (0..68).map(|_| mmu.load16(0x0DFF_FF00) as u8).collect();
+        assert_eq!(response[..4], [0, 0, 0, 0]);
+        assert_eq!(response[4..], bits(0x0123_4567_89AB_CDEF, 64)[..]);
+    }
+
+    #[test]
+    fn test_save_eeprom_8k() {
+        let mut mmu = MMU::new();
+        mmu.save = Save::new(Chip::Eeprom(Default::default()));
+
+        let mut request = vec![1, 0];
+        request.extend(bits(0x3FF, 14));
+        request.extend(bits(0xFF00_0000_0000_00AA, 64));
+        request.push(0);
+        eeprom_request(&mut mmu, &request);
+        assert_eq!(mmu.save.chip.data().len(), 0x2000);
+        assert_eq!(mmu.save.chip.data()[0x1FF8], 0xFF);
+        assert_eq!(mmu.save.chip.data()[0x1FFF], 0xAA);
+
+        // the save region does not reach the EEPROM
+        assert_eq!(mmu.load8(0x0E00_0000), 0xFF);
+    }
+    fn rom_with(offset: usize, bytes: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x1000];
+        rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        rom
+    }
+
+    #[test]
+    fn test_save_detect_library_ids() {
+        assert_eq!(detect(&rom_with(0x800, b"SRAM_V113")), SaveType::Sram);
+        assert_eq!(detect(&rom_with(0x800, b"EEPROM_V124")), SaveType::Eeprom);
+        assert_eq!(detect(&rom_with(0x800, b"FLASH_V126")), SaveType::Flash64K);
+        assert_eq!(
+            detect(&rom_with(0x800, b"FLASH512_V131")),
+            SaveType::Flash64K
+        );
+        assert_eq!(
+            detect(&rom_with(0x800, b"FLASH1M_V103")),
+            SaveType::Flash128K
+        );
+        // nothing found
+        assert_eq!(detect(&rom_with(0x800, b"SRAM")), SaveType::Sram);
+
+        match detect(&rom_with(0x800, b"FLASH1M_V103")).chip() {
+            Chip::Flash(flash) => assert_eq!(flash.id().size(), sizes::CART_FLASH1M_SIZE),
+            _ => panic!("expected flash"),
+        }
+    }
+
+    #[test]
+    fn test_save_detect_overrides() {
+        // Pokemon Emerald, the game code wins over the library string
+        let mut rom = rom_with(0x800, b"SRAM_V113");
+        rom[0xAC..0xB0].copy_from_slice(b"BPEE");
+        assert_eq!(detect(&rom), SaveType::Flash128K);
+
+        assert_eq!(SaveType::from_name("EEPROM8K"), Some(SaveType::Eeprom8K));
+        assert_eq!(SaveType::from_name("flash"), None);
+        for name in SaveType::NAMES {
+            assert!(SaveType::from_name(name).is_some());
+        }
+    }
+}
@@ -0,0 +1,504 @@
+// The serial port, run in the mode RCNT and SIOCNT select. The other GBAs, or a GameCube on
+// the JOY bus, are behind a Link. With nothing plugged in the port acts as if the cable is
+// unplugged. Based on https://problemkaputt.de/gbatek.htm#gbacommunicationports
+
+use crate::io::interrupts;
+use crate::MMU;
+
+pub mod cable;
+
+/// Addresses of the serial registers
+pub mod registers {
+    pub const SIODATA32: u32 = 0x400_0120;
+    pub const SIOMULTI0: u32 = 0x400_0120;
+    pub const SIOCNT: u32 = 0x400_0128;
+    pub const SIOMLT_SEND: u32 = 0x400_012A;
+    pub const SIODATA8: u32 = 0x400_012A;
+    pub const RCNT: u32 = 0x400_0134;
+    pub const JOYCNT: u32 = 0x400_0140;
+    pub const JOY_RECV: u32 = 0x400_0150;
+    pub const JOY_TRANS: u32 = 0x400_0154;
+    pub const JOYSTAT: u32 = 0x400_0158;
+
+    /// Data of GBA n in multiplayer mode
+    pub const fn multi(n: usize) -> u32 {
+        SIOMULTI0 + n as u32 * 2
+    }
+}
+
+/// Bits of SIOCNT, their meaning depends on the mode
+pub mod control {
+    // Normal
+    pub const INTERNAL_CLOCK: u16 = 1 << 0;
+    /// 2MHz instead of 256KHz
+    pub const FAST_CLOCK: u16 = 1 << 1;
+    /// SO of the partner, high when there is none
+    pub const SI: u16 = 1 << 2;
+    pub const LENGTH_32: u16 = 1 << 12;
+
+    // Multiplayer and UART
+    pub const BAUD_RATE: u16 = 0b11;
+    pub const CHILD: u16 = 1 << 2;
+    pub const ALL_READY: u16 = 1 << 3;
+    pub const ID_SHIFT: u16 = 4;
+    pub const ID: u16 = 0b11 << ID_SHIFT;
+    pub const ERROR: u16 = 1 << 6;
+
+    // UART
+    pub const CTS: u16 = 1 << 2;
+    pub const SEND_FULL: u16 = 1 << 4;
+    pub const RECEIVE_EMPTY: u16 = 1 << 5;
+    pub const SEND_ENABLE: u16 = 1 << 10;
+    pub const RECEIVE_ENABLE: u16 = 1 << 11;
+
+    pub const START: u16 = 1 << 7;
+    pub const IRQ: u16 = 1 << 14;
+}
+
+/// Bits of JOYCNT, writing 1 to the flags clears them
+pub mod joy_control {
+    pub const RESET: u16 = 1 << 0;
+    pub const RECEIVED: u16 = 1 << 1;
+    pub const SENT: u16 = 1 << 2;
+    pub const IRQ: u16 = 1 << 6;
+}
+
+/// Bits of JOYSTAT
+pub mod joy_status {
+    /// JOY_RECV was written by the host
+    pub const RECEIVED: u16 = 1 << 1;
+    /// JOY_TRANS was written and the host has not read it yet
+    pub const SENDING: u16 = 1 << 3;
+}
+
+const CYCLES_PER_SECOND: u32 = 16_777_216;
+const BAUD_RATES: [u32; 4] = [9600, 38400, 57600, 115_200];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Normal8,
+    Normal32,
+    Multiplayer,
+    Uart,
+    GeneralPurpose,
+    JoyBus,
+}
+
+/// Commands of a JOY bus host, the GBA is always the device
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JoyCommand {
+    Reset,
+    Status,
+    /// Read JOY_TRANS
+    Read,
+    /// Write to JOY_RECV
+    Write(u32),
+}
+
+/// The other end of the link cable. Calls are made as the emulated time passes, the defaults
+/// behave like an unplugged cable so links only implement the modes they carry.
+pub trait Link {
+    /// Time passing in cycles, lets a link keep the linked GBAs in step
+    fn step(&mut self, _cycles: u32) {}
+
+    /// Normal mode with this GBA as the clock, returns what the partner had ready
+    fn exchange_normal(&mut self, _data: u32, _bits: u32) -> Option<u32> {
+        None
+    }
+
+    /// Normal mode with the partner as the clock, what it sent once it ran a transfer
+    fn poll_normal(&mut self, _data: u32, _bits: u32) -> Option<u32> {
+        None
+    }
+
+    /// Multiplayer id of this GBA, 0 being the parent, or None while not every GBA is ready
+    fn multiplayer_id(&mut self) -> Option<u8> {
+        None
+    }
+
+    /// Multiplayer transfer run by the parent, returns the data of every GBA with 0xFFFF for
+    /// the missing ones
+    fn exchange_multiplayer(&mut self, data: u16) -> [u16; 4] {
+        [data, 0xFFFF, 0xFFFF, 0xFFFF]
+    }
+
+    /// Multiplayer as a child, the data of every GBA once the parent ran a transfer
+    fn poll_multiplayer(&mut self, _data: u16) -> Option<[u16; 4]> {
+        None
+    }
+
+    fn send_uart(&mut self, _byte: u8) {}
+
+    fn receive_uart(&mut self) -> Option<u8> {
+        None
+    }
+
+    /// Next command of the JOY bus host
+    fn joybus_command(&mut self) -> Option<JoyCommand> {
+        None
+    }
+
+    /// Answer to the last JOY bus command
+    fn joybus_reply(&mut self, _reply: &[u8]) {}
+}
+
+/// Data a running transfer stores once it completes
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Transfer {
+    Normal(u32),
+    Multiplayer([u16; 4]),
+    /// Sending a byte over UART
+    Uart,
+}
+
+#[derive(Default)]
+pub struct Serial {
+    link: Option<Box<dyn Link>>,
+    transfer: Option<Transfer>,
+    /// Cycles until the transfer completes
+    remaining: u32,
+    /// Cycles until the byte received over UART counts as read
+    unread: u32,
+}
+
+impl Clone for Serial {
+    /// Links can not be cloned, the copy is unplugged
+    fn clone(&self) -> Self {
+        Self {
+            link: None,
+            transfer: self.transfer,
+            remaining: self.remaining,
+            unread: self.unread,
+        }
+    }
+}
+
+/// Bits of SIOCNT the CPU can not write in a mode
+fn read_only_bits(mode: Mode) -> u16 {
+    match mode {
+        Mode::Normal8 | Mode::Normal32 => control::SI,
+        Mode::Multiplayer => control::CHILD | control::ALL_READY | control::ID | control::ERROR,
+        Mode::Uart => control::SEND_FULL | control::RECEIVE_EMPTY | control::ERROR,
+        Mode::GeneralPurpose | Mode::JoyBus => 0,
+    }
+}
+
+/// Cycles taken to send a bit at a baud rate setting
+fn bit_cycles(control: u16) -> u32 {
+    CYCLES_PER_SECOND / BAUD_RATES[(control & control::BAUD_RATE) as usize]
+}
+
+/// Writing SIOCNT keeps the status bits and may start a transfer
+pub(crate) fn control_written(mmu: &mut MMU, addr: u32, old: u16, _: u16, _: u16) {
+    let read_only = read_only_bits(mmu.serial_mode());
+    let value = mmu.io_raw(addr) & !read_only | old & read_only;
+    mmu.set_io_raw(addr, value);
+
+    mmu.update_serial_status();
+    if value & control::START != 0 && old & control::START == 0 {
+        mmu.start_transfer();
+    }
+}
+
+/// RCNT picks between the SIOCNT modes, general purpose and the JOY bus
+pub(crate) fn mode_written(mmu: &mut MMU, _: u32, _: u16, _: u16, _: u16) {
+    mmu.update_serial_status();
+}
+
+/// Bytes written to SIODATA8 in UART mode are sent
+pub(crate) fn data_written(mmu: &mut MMU, addr: u32, _: u16, _: u16, _: u16) {
+    let control = mmu.io_raw(registers::SIOCNT);
+    if mmu.serial_mode() != Mode::Uart || control & control::SEND_ENABLE == 0 {
+        return;
+    }
+
+    let byte = mmu.io_raw(addr) as u8;
+    if let Some(link) = mmu.serial.link.as_mut() {
+        link.send_uart(byte);
+    }
+    // a start bit, 8 data bits and a stop bit
+    mmu.serial.transfer = Some(Transfer::Uart);
+    mmu.serial.remaining = bit_cycles(control) * 10;
+    mmu.set_io_raw(registers::SIOCNT, control | control::SEND_FULL);
+}
+
+/// The JOY bus flags are cleared by writing 1 to them
+pub(crate) fn joy_control_written(mmu: &mut MMU, addr: u32, old: u16, written: u16, _: u16) {
+    let flags = joy_control::RESET | joy_control::RECEIVED | joy_control::SENT;
+    let value = old & flags & !written | written & joy_control::IRQ;
+    mmu.set_io_raw(addr, value);
+}
+
+/// Writing JOY_TRANS flags it for the host to read
+pub(crate) fn joy_trans_written(mmu: &mut MMU, _: u32, _: u16, _: u16, _: u16) {
+    let status = mmu.io_raw(registers::JOYSTAT);
+    mmu.set_io_raw(registers::JOYSTAT, status | joy_status::SENDING);
+}
+
+impl MMU {
+    /// Plug a link cable into the serial port, None unplugs it
+    pub fn set_link(&mut self, link: Option<Box<dyn Link>>) {
+        self.serial.link = link;
+        self.update_serial_status();
+    }
+
+    pub fn serial_mode(&self) -> Mode {
+        let rcnt = self.io_raw(registers::RCNT);
+        let control = self.io_raw(registers::SIOCNT);
+        match (rcnt >> 14, control >> 12 & 0b11) {
+            (0..=1, 0) => Mode::Normal8,
+            (0..=1, 1) => Mode::Normal32,
+            (0..=1, 2) => Mode::Multiplayer,
+            (0..=1, _) => Mode::Uart,
+            (2, _) => Mode::GeneralPurpose,
+            _ => Mode::JoyBus,
+        }
+    }
+
+    /// Sets the SIOCNT bits showing the state of the link
+    pub(crate) fn update_serial_status(&mut self) {
+        let mode = self.serial_mode();
+        let mut control = self.io_raw(registers::SIOCNT) & !read_only_bits(mode);
+        match mode {
+            // nothing is driving SI without a partner, it stays high
+            Mode::Normal8 | Mode::Normal32 => control |= control::SI,
+            Mode::Multiplayer => {
+                let id = self
+                    .serial
+                    .link
+                    .as_mut()
+                    .and_then(|link| link.multiplayer_id());
+                control |= match id {
+                    Some(0) => control::ALL_READY,
+                    Some(id) => {
+                        control::CHILD | control::ALL_READY | (id as u16) << control::ID_SHIFT
+                    }
+                    None => control::CHILD,
+                };
+            }
+            Mode::Uart => {
+                if self.serial.transfer == Some(Transfer::Uart) {
+                    control |= control::SEND_FULL;
+                }
+                if self.serial.unread == 0 {
+                    control |= control::RECEIVE_EMPTY;
+                }
+            }
+            Mode::GeneralPurpose | Mode::JoyBus => (),
+        }
+        self.set_io_raw(registers::SIOCNT, control);
+    }
+
+    /// Start bit set by the CPU. Without a partner, transfers this GBA clocks complete with
+    /// all bits set while the ones clocked by the partner wait forever, as on hardware.
+    fn start_transfer(&mut self) {
+        let control = self.io_raw(registers::SIOCNT);
+        let (transfer, cycles) = match self.serial_mode() {
+            Mode::Normal8 | Mode::Normal32 if control & control::INTERNAL_CLOCK != 0 => {
+                let bits = if control & control::LENGTH_32 != 0 {
+                    32
+                } else {
+                    8
+                };
+                let data = self.serial_data(bits);
+                let received = self
+                    .serial
+                    .link
+                    .as_mut()
+                    .and_then(|link| link.exchange_normal(data, bits))
+                    .unwrap_or(!0);
+                let bit_cycles = if control & control::FAST_CLOCK != 0 {
+                    8
+                } else {
+                    64
+                };
+                (Transfer::Normal(received), bits * bit_cycles)
+            }
+            // only the parent runs transfers, children wait in step_serial
+            Mode::Multiplayer if control & control::CHILD == 0 => {
+                let data = self.io_raw(registers::SIOMLT_SEND);
+                let received = match self.serial.link.as_mut() {
+                    Some(link) => link.exchange_multiplayer(data),
+                    None => [data, 0xFFFF, 0xFFFF, 0xFFFF],
+                };
+                (Transfer::Multiplayer(received), multiplayer_cycles(control))
+            }
+            _ => return,
+        };
+        self.serial.transfer = Some(transfer);
+        self.serial.remaining = cycles;
+    }
+
+    /// Data to send in normal mode
+    fn serial_data(&self, bits: u32) -> u32 {
+        if bits == 32 {
+            self.io_raw(registers::SIODATA32) as u32
+                | (self.io_raw(registers::SIODATA32 + 2) as u32) << 16
+        } else {
+            self.io_raw(registers::SIODATA8) as u8 as u32
+        }
+    }
+
+    /// Runs the serial port for a number of cycles
+    pub fn step_serial(&mut self, cycles: u32) {
+        if self.serial.link.is_none() && self.serial.transfer.is_none() {
+            return;
+        }
+        if let Some(link) = self.serial.link.as_mut() {
+            link.step(cycles);
+        }
+
+        if self.serial.unread > 0 {
+            self.serial.unread = self.serial.unread.saturating_sub(cycles);
+            if self.serial.unread == 0 {
+                let control = self.io_raw(registers::SIOCNT);
+                self.set_io_raw(registers::SIOCNT, control | control::RECEIVE_EMPTY);
+            }
+        }
+
+        if self.serial.transfer.is_some() {
+            if self.serial.remaining > cycles {
+                self.serial.remaining -= cycles;
+            } else {
+                self.complete_transfer();
+            }
+        } else {
+            self.poll_link();
+        }
+    }
+
+    /// Checks for transfers the partners start and data they send
+    fn poll_link(&mut self) {
+        let control = self.io_raw(registers::SIOCNT);
+        let mode = self.serial_mode();
+        if self.serial.link.is_none() {
+            return;
+        }
+
+        match mode {
+            Mode::Normal8 | Mode::Normal32 => {
+                if control & control::START == 0 || control & control::INTERNAL_CLOCK != 0 {
+                    return;
+                }
+                let bits = if mode == Mode::Normal32 { 32 } else { 8 };
+                let data = self.serial_data(bits);
+                let link = self.serial.link.as_mut().unwrap();
+                if let Some(received) = link.poll_normal(data, bits) {
+                    self.serial.transfer = Some(Transfer::Normal(received));
+                    self.serial.remaining = 1;
+                }
+            }
+            Mode::Multiplayer => {
+                if control & control::CHILD == 0 {
+                    return;
+                }
+                let data = self.io_raw(registers::SIOMLT_SEND);
+                let link = self.serial.link.as_mut().unwrap();
+                if let Some(received) = link.poll_multiplayer(data) {
+                    // the transfer runs while the parent's does
+                    self.serial.transfer = Some(Transfer::Multiplayer(received));
+                    self.serial.remaining = multiplayer_cycles(control);
+                    self.set_io_raw(registers::SIOCNT, control | control::START);
+                }
+            }
+            Mode::Uart => {
+                if control & control::RECEIVE_ENABLE == 0 || control & control::RECEIVE_EMPTY == 0 {
+                    return;
+                }
+                let link = self.serial.link.as_mut().unwrap();
+                if let Some(byte) = link.receive_uart() {
+                    // reads have no side effects here, so the byte counts as read once the
+                    // next one could have arrived
+                    self.serial.unread = bit_cycles(control) * 10;
+                    self.set_io_raw(registers::SIODATA8, byte as u16);
+                    self.set_io_raw(registers::SIOCNT, control & !control::RECEIVE_EMPTY);
+                    if control & control::IRQ != 0 {
+                        self.request_interrupt(interrupts::SERIAL);
+                    }
+                }
+            }
+            Mode::JoyBus => {
+                let link = self.serial.link.as_mut().unwrap();
+                if let Some(command) = link.joybus_command() {
+                    self.joybus_command(command);
+                }
+            }
+            Mode::GeneralPurpose => (),
+        }
+    }
+
+    fn complete_transfer(&mut self) {
+        let transfer = match self.serial.transfer.take() {
+            Some(transfer) => transfer,
+            None => return,
+        };
+        self.serial.remaining = 0;
+
+        let mut control = self.io_raw(registers::SIOCNT);
+        match transfer {
+            Transfer::Normal(received) => {
+                if control & control::LENGTH_32 != 0 {
+                    self.set_io_raw(registers::SIODATA32, received as u16);
+                    self.set_io_raw(registers::SIODATA32 + 2, (received >> 16) as u16);
+                } else {
+                    self.set_io_raw(registers::SIODATA8, received as u8 as u16);
+                }
+                control &= !control::START;
+            }
+            Transfer::Multiplayer(received) => {
+                for (n, &data) in received.iter().enumerate() {
+                    self.set_io_raw(registers::multi(n), data);
+                }
+                control &= !(control::START | control::ERROR);
+            }
+            Transfer::Uart => control &= !control::SEND_FULL,
+        }
+        self.set_io_raw(registers::SIOCNT, control);
+        if control & control::IRQ != 0 {
+            self.request_interrupt(interrupts::SERIAL);
+        }
+    }
+
+    /// Answers a command of the JOY bus host
+    fn joybus_command(&mut self, command: JoyCommand) {
+        let joy_control = self.io_raw(registers::JOYCNT);
+        let mut status = self.io_raw(registers::JOYSTAT);
+        let (flag, reply) = match command {
+            JoyCommand::Reset => (joy_control::RESET, vec![0x00, 0x04, status as u8]),
+            JoyCommand::Status => (0, vec![0x00, 0x04, status as u8]),
+            JoyCommand::Write(data) => {
+                self.set_io_raw(registers::JOY_RECV, data as u16);
+                self.set_io_raw(registers::JOY_RECV + 2, (data >> 16) as u16);
+                status |= joy_status::RECEIVED;
+                (joy_control::RECEIVED, vec![status as u8])
+            }
+            JoyCommand::Read => {
+                let low = self.io_raw(registers::JOY_TRANS).to_le_bytes();
+                let high = self.io_raw(registers::JOY_TRANS + 2).to_le_bytes();
+                status &= !joy_status::SENDING;
+                (
+                    joy_control::SENT,
+                    vec![low[0], low[1], high[0], high[1], status as u8],
+                )
+            }
+        };
+
+        self.set_io_raw(registers::JOYSTAT, status);
+        if let Some(link) = self.serial.link.as_mut() {
+            link.joybus_reply(&reply);
+        }
+        if flag != 0 {
+            self.set_io_raw(registers::JOYCNT, joy_control | flag);
+            if joy_control & joy_control::IRQ != 0 {
+                self.request_interrupt(interrupts::SERIAL);
+            }
+        }
+    }
+}
+
+/// Cycles of a multiplayer transfer, a start bit, 16 data bits and a stop bit for each GBA
+fn multiplayer_cycles(control: u16) -> u32 {
+    bit_cycles(control) * 18 * 4
+}
+
+pub mod tests;
@@ -0,0 +1,346 @@
+// A link cable between two emulator instances, over a Unix domain socket or a TCP connection.
+// The instance that listens is the parent. The instances run in lockstep: the child only runs a
+// cycle once the parent is past it or has started a transfer on it, and the parent stays at most
+// LEAD cycles ahead. So every transfer the parent starts reaches the child on the same cycle, and
+// linked sessions play out the same way however fast each instance runs.
+
+use super::Link;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+/// Sent first by both ends, with the protocol version
+const MAGIC: [u8; 4] = *b"VLR1";
+
+/// Cycles between the messages telling the partner how far along an end is
+const SYNC_INTERVAL: u64 = 4096;
+/// How many cycles the parent can run ahead of the child
+const LEAD: u64 = 2 * SYNC_INTERVAL;
+
+// Message tags, followed by their fields in little endian
+/// The sender reached a cycle: u64 cycle
+const SYNC: u8 = 0;
+/// The parent starts a multiplayer transfer: u64 cycle, u16 data
+const MULTIPLAYER: u8 = 1;
+/// What the child had ready for a multiplayer transfer: u16 data
+const MULTIPLAYER_REPLY: u8 = 2;
+/// The parent clocks a normal transfer: u64 cycle, u32 data
+const NORMAL: u8 = 3;
+/// Whether the child took part in a normal transfer and its data: u8, u32 data
+const NORMAL_REPLY: u8 = 4;
+
+/// A stream the cable runs over
+pub trait Stream: Read + Write + Send {}
+
+impl Stream for UnixStream {}
+impl Stream for TcpStream {}
+
+/// A transfer the parent started, for the child to take part in on its cycle
+#[derive(Clone, Copy, Debug)]
+enum Transfer {
+    Multiplayer(u16),
+    Normal(u32),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Reply {
+    Multiplayer(u16),
+    Normal(Option<u32>),
+}
+
+/// A host:port is a TCP address, anything else the path of a Unix domain socket
+fn is_tcp(address: &str) -> bool {
+    !address.contains('/') && address.to_socket_addrs().is_ok()
+}
+
+pub struct Cable {
+    stream: Box<dyn Stream>,
+    parent: bool,
+    /// Cleared once the partner is gone, the port then acts as if the cable is unplugged
+    connected: bool,
+    /// Cycles this end has run
+    cycle: u64,
+    /// Cycle the partner said it reached
+    partner_cycle: u64,
+    /// Transfer started by the parent and the cycle it started on
+    pending: Option<(u64, Transfer)>,
+    reply: Option<Reply>,
+}
+
+impl Cable {
+    /// Link over a connected stream, the parent being the end that runs the transfers
+    pub fn new(mut stream: Box<dyn Stream>, parent: bool) -> Result<Self, String> {
+        let mut magic = [0; 4];
+        stream
+            .write_all(&MAGIC)
+            .and_then(|_| stream.read_exact(&mut magic))
+            .map_err(|error| format!("Link cable handshake failed: {}", error))?;
+        if magic != MAGIC {
+            return Err("The other end of the link cable is not a compatible emulator".to_string());
+        }
+
+        Ok(Self {
+            stream,
+            parent,
+            connected: true,
+            cycle: 0,
+            partner_cycle: 0,
+            pending: None,
+            reply: None,
+        })
+    }
+
+    /// Wait for the other instance to connect on address, this end becomes the parent
+    pub fn listen(address: &str) -> Result<Self, String> {
+        let error = |error: std::io::Error| format!("Unable to listen on {}: {}", address, error);
+        eprintln!("Waiting for the link cable partner on {}", address);
+        let stream: Box<dyn Stream> = if is_tcp(address) {
+            let (stream, _) = TcpListener::bind(address)
+                .and_then(|listener| listener.accept())
+                .map_err(error)?;
+            stream.set_nodelay(true).map_err(error)?;
+            Box::new(stream)
+        } else {
+            // a socket left behind by an earlier session would make bind fail
+            let path = Path::new(address);
+            if let Ok(metadata) = std::fs::metadata(path) {
+                if metadata.file_type().is_socket() {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+            let listener = UnixListener::bind(path).map_err(error)?;
+            let accepted = listener.accept();
+            let _ = std::fs::remove_file(path);
+            Box::new(accepted.map_err(error)?.0)
+        };
+        Self::new(stream, true)
+    }
+
+    /// Connect to the instance listening on address, this end becomes the child
+    pub fn connect(address: &str) -> Result<Self, String> {
+        let error = |error: std::io::Error| format!("Unable to connect to {}: {}", address, error);
+        let stream: Box<dyn Stream> = if is_tcp(address) {
+            let stream = TcpStream::connect(address).map_err(error)?;
+            stream.set_nodelay(true).map_err(error)?;
+            Box::new(stream)
+        } else {
+            Box::new(UnixStream::connect(address).map_err(error)?)
+        };
+        Self::new(stream, false)
+    }
+
+    pub fn connected(&self) -> bool {
+        self.connected
+    }
+
+    fn disconnect(&mut self, error: std::io::Error) {
+        if self.connected {
+            eprintln!("Link cable disconnected: {}", error);
+        }
+        self.connected = false;
+        self.pending = None;
+    }
+
+    fn send(&mut self, message: &[u8]) {
+        if !self.connected {
+            return;
+        }
+        if let Err(error) = self.stream.write_all(message) {
+            self.disconnect(error);
+        }
+    }
+
+    fn read<const N: usize>(&mut self) -> std::io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.stream.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_message(&mut self) -> std::io::Result<()> {
+        let [tag] = self.read()?;
+        match tag {
+            SYNC => self.partner_cycle = u64::from_le_bytes(self.read()?),
+            MULTIPLAYER | NORMAL => {
+                let cycle = u64::from_le_bytes(self.read()?);
+                let transfer = if tag == MULTIPLAYER {
+                    Transfer::Multiplayer(u16::from_le_bytes(self.read()?))
+                } else {
+                    Transfer::Normal(u32::from_le_bytes(self.read()?))
+                };
+                self.partner_cycle = cycle;
+                self.pending = Some((cycle, transfer));
+            }
+            MULTIPLAYER_REPLY => {
+                self.reply = Some(Reply::Multiplayer(u16::from_le_bytes(self.read()?)))
+            }
+            NORMAL_REPLY => {
+                let [present] = self.read()?;
+                let data = u32::from_le_bytes(self.read()?);
+                self.reply = Some(Reply::Normal(Some(data).filter(|_| present != 0)));
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("unknown message {}", tag),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for the next message of the partner
+    fn receive(&mut self) {
+        if let Err(error) = self.read_message() {
+            self.disconnect(error);
+        }
+    }
+
+    /// Tell the partner how far this end is every SYNC_INTERVAL cycles
+    fn sync(&mut self, cycles: u64) {
+        if (self.cycle - cycles) / SYNC_INTERVAL != self.cycle / SYNC_INTERVAL {
+            let mut message = vec![SYNC];
+            message.extend_from_slice(&self.cycle.to_le_bytes());
+            self.send(&message);
+        }
+    }
+
+    /// Answer a transfer the child was not ready for on its cycle, the child takes no part in it
+    fn answer_missed(&mut self) {
+        match self.pending {
+            Some((cycle, transfer)) if cycle < self.cycle => {
+                self.pending = None;
+                match transfer {
+                    Transfer::Multiplayer(_) => self.send_multiplayer_reply(0xFFFF),
+                    Transfer::Normal(_) => self.send_normal_reply(None),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// The transfer starting on this cycle, for the child
+    fn take_pending(&mut self) -> Option<Transfer> {
+        match self.pending {
+            Some((cycle, transfer)) if cycle == self.cycle => {
+                self.pending = None;
+                Some(transfer)
+            }
+            _ => None,
+        }
+    }
+
+    fn send_multiplayer_reply(&mut self, data: u16) {
+        let mut message = vec![MULTIPLAYER_REPLY];
+        message.extend_from_slice(&data.to_le_bytes());
+        self.send(&message);
+    }
+
+    fn send_normal_reply(&mut self, data: Option<u32>) {
+        let mut message = vec![NORMAL_REPLY, data.is_some() as u8];
+        message.extend_from_slice(&data.unwrap_or(0).to_le_bytes());
+        self.send(&message);
+    }
+
+    /// Start a transfer on the child and wait for its answer
+    fn start(&mut self, tag: u8, data: &[u8]) -> Option<Reply> {
+        let mut message = vec![tag];
+        message.extend_from_slice(&self.cycle.to_le_bytes());
+        message.extend_from_slice(data);
+        self.send(&message);
+
+        self.reply = None;
+        while self.connected && self.reply.is_none() {
+            self.receive();
+        }
+        self.reply.take()
+    }
+}
+
+impl Link for Cable {
+    fn step(&mut self, cycles: u32) {
+        if !self.connected {
+            return;
+        }
+        let cycles = cycles as u64;
+        self.cycle += cycles;
+
+        if self.parent {
+            self.sync(cycles);
+            while self.connected && self.cycle > self.partner_cycle + LEAD {
+                self.receive();
+            }
+        } else {
+            // the parent can still start a transfer on the cycle it synced on
+            self.answer_missed();
+            while self.connected && self.cycle >= self.partner_cycle && self.pending.is_none() {
+                self.receive();
+                self.answer_missed();
+            }
+            self.sync(cycles);
+        }
+    }
+
+    fn exchange_normal(&mut self, data: u32, _bits: u32) -> Option<u32> {
+        // the child running the clock is not kept in step, its partner is not there for it
+        if !self.parent || !self.connected {
+            return None;
+        }
+        match self.start(NORMAL, &data.to_le_bytes()) {
+            Some(Reply::Normal(received)) => received,
+            _ => None,
+        }
+    }
+
+    fn poll_normal(&mut self, data: u32, _bits: u32) -> Option<u32> {
+        match self.take_pending() {
+            Some(Transfer::Normal(received)) => {
+                self.send_normal_reply(Some(data));
+                Some(received)
+            }
+            Some(Transfer::Multiplayer(_)) => {
+                self.send_multiplayer_reply(0xFFFF);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn multiplayer_id(&mut self) -> Option<u8> {
+        if !self.connected {
+            None
+        } else if self.parent {
+            Some(0)
+        } else {
+            Some(1)
+        }
+    }
+
+    fn exchange_multiplayer(&mut self, data: u16) -> [u16; 4] {
+        if !self.parent || !self.connected {
+            return [data, 0xFFFF, 0xFFFF, 0xFFFF];
+        }
+        match self.start(MULTIPLAYER, &data.to_le_bytes()) {
+            Some(Reply::Multiplayer(received)) => [data, received, 0xFFFF, 0xFFFF],
+            _ => [data, 0xFFFF, 0xFFFF, 0xFFFF],
+        }
+    }
+
+    fn poll_multiplayer(&mut self, data: u16) -> Option<[u16; 4]> {
+        match self.take_pending() {
+            Some(Transfer::Multiplayer(received)) => {
+                self.send_multiplayer_reply(data);
+                Some([received, data, 0xFFFF, 0xFFFF])
+            }
+            Some(Transfer::Normal(_)) => {
+                self.send_normal_reply(None);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+pub mod tests;
@@ -0,0 +1,88 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::serial::{control, registers};
+    use crate::MMU;
+
+    const MULTIPLAYER: u16 = 0x2000;
+
+    /// Runs a GBA in multiplayer mode at 115200 bauds, starting a transfer on a cycle. Returns
+    /// the multiplayer data once done and the cycle the transfer started on.
+    fn run(
+        stream: UnixStream,
+        parent: bool,
+        data: u16,
+        start: Option<u64>,
+        cycles: u64,
+    ) -> ([u16; 4], Option<u64>) {
+        let cable = Cable::new(Box::new(stream), parent).unwrap();
+        let mut mmu = MMU::new();
+        mmu.set_link(Some(Box::new(cable)));
+        mmu.store16(registers::SIOCNT, MULTIPLAYER | 3);
+        mmu.store16(registers::SIOMLT_SEND, data);
+
+        let mut started = None;
+        for cycle in 1..=cycles {
+            mmu.step_serial(1);
+            if start == Some(cycle) {
+                mmu.store16(registers::SIOCNT, MULTIPLAYER | 3 | control::START);
+            }
+            if started.is_none() && mmu.load16(registers::SIOCNT) & control::START != 0 {
+                started = Some(cycle);
+            }
+        }
+
+        let mut received = [0; 4];
+        for (n, data) in received.iter_mut().enumerate() {
+            *data = mmu.load16(registers::multi(n));
+        }
+        (received, started)
+    }
+
+    /// Links two GBAs and has the parent start a multiplayer transfer on a cycle
+    fn multiplayer(start: u64) {
+        let (parent, child) = UnixStream::pair().unwrap();
+        let child = std::thread::spawn(move || run(child, false, 0x5678, None, 30_000));
+        let parent = run(parent, true, 0x1234, Some(start), 30_000);
+        let child = child.join().unwrap();
+
+        // both GBAs start the transfer on the same cycle
+        assert_eq!(parent, ([0x1234, 0x5678, 0xFFFF, 0xFFFF], Some(start)));
+        assert_eq!(child, ([0x1234, 0x5678, 0xFFFF, 0xFFFF], Some(start)));
+    }
+
+    #[test]
+    fn test_cable_multiplayer() {
+        multiplayer(10_000);
+        // on the cycle the parent tells the child how far it is
+        multiplayer(2 * SYNC_INTERVAL);
+    }
+
+    #[test]
+    fn test_cable_ids() {
+        let (parent, child) = UnixStream::pair().unwrap();
+        let child = std::thread::spawn(move || {
+            let mut cable = Cable::new(Box::new(child), false).unwrap();
+            cable.multiplayer_id()
+        });
+        let mut parent = Cable::new(Box::new(parent), true).unwrap();
+        assert_eq!(parent.multiplayer_id(), Some(0));
+        assert_eq!(child.join().unwrap(), Some(1));
+
+        // the child is gone, the parent now acts as if the cable is unplugged
+        assert_eq!(
+            parent.exchange_multiplayer(0x1234),
+            [0x1234, 0xFFFF, 0xFFFF, 0xFFFF]
+        );
+        assert!(!parent.connected());
+        assert_eq!(parent.multiplayer_id(), None);
+    }
+
+    #[test]
+    fn test_cable_addresses() {
+        assert!(is_tcp("127.0.0.1:5000"));
+        assert!(is_tcp("localhost:5000"));
+        assert!(!is_tcp("/tmp/velera.sock"));
+        assert!(!is_tcp("velera.sock"));
+    }
+}
@@ -0,0 +1,219 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    /// GBA 1 of a multiplayer session, the parent sends 0x1111
+    struct Child {
+        started: bool,
+    }
+
+    impl Link for Child {
+        fn multiplayer_id(&mut self) -> Option<u8> {
+            Some(1)
+        }
+
+        fn poll_multiplayer(&mut self, data: u16) -> Option<[u16; 4]> {
+            if self.started {
+                return None;
+            }
+            self.started = true;
+            Some([0x1111, data, 0xFFFF, 0xFFFF])
+        }
+    }
+
+    /// The parent of two GBAs, with a partner answering normal transfers and the JOY bus
+    #[derive(Default)]
+    struct Parent {
+        replies: std::rc::Rc<std::cell::RefCell<Vec<Vec<u8>>>>,
+        commands: Vec<JoyCommand>,
+    }
+
+    impl Link for Parent {
+        fn exchange_normal(&mut self, data: u32, _: u32) -> Option<u32> {
+            Some(!data)
+        }
+
+        fn multiplayer_id(&mut self) -> Option<u8> {
+            Some(0)
+        }
+
+        fn exchange_multiplayer(&mut self, data: u16) -> [u16; 4] {
+            [data, 0x2222, 0xFFFF, 0xFFFF]
+        }
+
+        fn receive_uart(&mut self) -> Option<u8> {
+            Some(b'!')
+        }
+
+        fn joybus_command(&mut self) -> Option<JoyCommand> {
+            self.commands.pop()
+        }
+
+        fn joybus_reply(&mut self, reply: &[u8]) {
+            self.replies.borrow_mut().push(reply.to_vec());
+        }
+    }
+
+    const MULTIPLAYER: u16 = 0x2000;
+    const UART: u16 = 0x3000;
+
+    fn serial_irq(mmu: &MMU) -> bool {
+        mmu.io_raw(crate::io::registers::IF) & interrupts::SERIAL != 0
+    }
+
+    #[test]
+    fn test_serial_normal_without_link() {
+        let mut mmu = MMU::new();
+        assert_eq!(mmu.serial_mode(), Mode::Normal8);
+        assert_ne!(mmu.load16(registers::SIOCNT) & control::SI, 0);
+
+        mmu.store16(registers::SIODATA8, 0x12);
+        mmu.store16(
+            registers::SIOCNT,
+            control::START | control::INTERNAL_CLOCK | control::IRQ,
+        );
+        mmu.step_serial(8 * 64 - 1);
+        assert_ne!(mmu.load16(registers::SIOCNT) & control::START, 0);
+        assert!(!serial_irq(&mmu));
+
+        // nothing drives SI, every bit received is 1
+        mmu.step_serial(1);
+        assert_eq!(mmu.load16(registers::SIOCNT) & control::START, 0);
+        assert_eq!(mmu.load16(registers::SIODATA8), 0xFF);
+        assert!(serial_irq(&mmu));
+
+        // with the partner as the clock the transfer never ends
+        mmu.store16(registers::SIOCNT, control::START);
+        mmu.step_serial(100_000);
+        assert_ne!(mmu.load16(registers::SIOCNT) & control::START, 0);
+    }
+
+    #[test]
+    fn test_serial_normal_32() {
+        let mut mmu = MMU::new();
+        mmu.set_link(Some(Box::new(Parent::default())));
+        mmu.store32(registers::SIODATA32, 0x1234_5678);
+        mmu.store16(
+            registers::SIOCNT,
+            control::LENGTH_32 | control::START | control::INTERNAL_CLOCK | control::FAST_CLOCK,
+        );
+        assert_eq!(mmu.serial_mode(), Mode::Normal32);
+
+        mmu.step_serial(32 * 8);
+        assert_eq!(mmu.load32(registers::SIODATA32), !0x1234_5678);
+    }
+
+    #[test]
+    fn test_serial_multiplayer_without_link() {
+        let mut mmu = MMU::new();
+        mmu.store16(registers::SIOCNT, MULTIPLAYER | control::START);
+
+        // a lone GBA is a child with a bad connection and can not start transfers
+        let control = mmu.load16(registers::SIOCNT);
+        assert_ne!(control & control::CHILD, 0);
+        assert_eq!(control & control::ALL_READY, 0);
+        mmu.step_serial(1_000_000);
+        assert_eq!(mmu.load16(registers::multi(1)), 0);
+    }
+
+    #[test]
+    fn test_serial_multiplayer_parent() {
+        let mut mmu = MMU::new();
+        mmu.set_link(Some(Box::new(Parent::default())));
+        mmu.store16(registers::SIOCNT, MULTIPLAYER | 3);
+        let control = mmu.load16(registers::SIOCNT);
+        assert_eq!(control & (control::CHILD | control::ID), 0);
+        assert_ne!(control & control::ALL_READY, 0);
+
+        // the status bits can not be written
+        mmu.store16(
+            registers::SIOCNT,
+            MULTIPLAYER | 3 | control::CHILD | control::ID,
+        );
+        assert_eq!(mmu.load16(registers::SIOCNT) & control::ID, 0);
+
+        mmu.store16(registers::SIOMLT_SEND, 0x1111);
+        mmu.store16(
+            registers::SIOCNT,
+            MULTIPLAYER | 3 | control::START | control::IRQ,
+        );
+        mmu.step_serial(16_777_216 / 115_200 * 18 * 4);
+        assert_eq!(mmu.load16(registers::SIOCNT) & control::START, 0);
+        assert_eq!(mmu.load16(registers::multi(0)), 0x1111);
+        assert_eq!(mmu.load16(registers::multi(1)), 0x2222);
+        assert_eq!(mmu.load16(registers::multi(2)), 0xFFFF);
+        assert!(serial_irq(&mmu));
+    }
+
+    #[test]
+    fn test_serial_multiplayer_child() {
+        let mut mmu = MMU::new();
+        mmu.set_link(Some(Box::new(Child { started: false })));
+        mmu.store16(registers::SIOCNT, MULTIPLAYER | control::IRQ);
+        assert_eq!(
+            mmu.load16(registers::SIOCNT) & control::ID,
+            1 << control::ID_SHIFT
+        );
+
+        mmu.store16(registers::SIOMLT_SEND, 0x2222);
+        mmu.step_serial(1);
+        assert_ne!(mmu.load16(registers::SIOCNT) & control::START, 0);
+        mmu.step_serial(16_777_216 / 9600 * 18 * 4);
+        assert_eq!(mmu.load16(registers::multi(0)), 0x1111);
+        assert_eq!(mmu.load16(registers::multi(1)), 0x2222);
+        assert!(serial_irq(&mmu));
+    }
+
+    #[test]
+    fn test_serial_uart() {
+        let mut mmu = MMU::new();
+        mmu.set_link(Some(Box::new(Parent::default())));
+        mmu.store16(
+            registers::SIOCNT,
+            UART | 3 | control::SEND_ENABLE | control::RECEIVE_ENABLE | control::IRQ,
+        );
+        assert_ne!(mmu.load16(registers::SIOCNT) & control::RECEIVE_EMPTY, 0);
+
+        mmu.step_serial(1);
+        assert_eq!(mmu.load16(registers::SIOCNT) & control::RECEIVE_EMPTY, 0);
+        assert_eq!(mmu.load16(registers::SIODATA8), b'!' as u16);
+        assert!(serial_irq(&mmu));
+
+        mmu.store16(registers::SIODATA8, b'?' as u16);
+        assert_ne!(mmu.load16(registers::SIOCNT) & control::SEND_FULL, 0);
+        mmu.step_serial(16_777_216 / 115_200 * 10);
+        assert_eq!(mmu.load16(registers::SIOCNT) & control::SEND_FULL, 0);
+    }
+
+    #[test]
+    fn test_serial_joybus() {
+        let replies = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut mmu = MMU::new();
+        mmu.set_link(Some(Box::new(Parent {
+            replies: replies.clone(),
+            // popped from the end
+            commands: vec![JoyCommand::Read, JoyCommand::Write(0xDEAD_BEEF)],
+        })));
+        mmu.store16(registers::RCNT, 0xC000);
+        assert_eq!(mmu.serial_mode(), Mode::JoyBus);
+        mmu.store16(registers::JOYCNT, joy_control::IRQ);
+        mmu.store32(registers::JOY_TRANS, 0x0102_0304);
+        assert_ne!(mmu.load16(registers::JOYSTAT) & joy_status::SENDING, 0);
+
+        mmu.step_serial(1);
+        assert_eq!(mmu.load32(registers::JOY_RECV), 0xDEAD_BEEF);
+        assert_ne!(mmu.load16(registers::JOYCNT) & joy_control::RECEIVED, 0);
+        assert!(serial_irq(&mmu));
+
+        mmu.step_serial(1);
+        assert_eq!(mmu.load16(registers::JOYSTAT) & joy_status::SENDING, 0);
+        assert_eq!(replies.borrow()[1][..4], [0x04, 0x03, 0x02, 0x01]);
+
+        // flags are cleared by writing 1
+        mmu.store16(registers::JOYCNT, joy_control::IRQ | joy_control::RECEIVED);
+        assert_eq!(
+            mmu.load16(registers::JOYCNT),
+            joy_control::IRQ | joy_control::SENT
+        );
+    }
+}
@@ -0,0 +1,155 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_mmu_ram_mirrors() {
+        let mut mmu = MMU::new();
+        mmu.store8(0x0204_0001, 0x11);
+        assert_eq!(mmu.load8(0x0200_0001), 0x11);
+        mmu.store8(0x03FF_FFFF, 0x22);
+        assert_eq!(mmu.load8(0x0300_7FFF), 0x22);
+        mmu.store8(0x0500_0400, 0x33);
+        assert_eq!(mmu.load8(0x0500_0000), 0x33);
+        mmu.store16(0x07FF_FC02, 0x44);
+        assert_eq!(mmu.load8(0x0700_0002), 0x44);
+    }
+
+    #[test]
+    fn test_mmu_vram_mirrors() {
+        let mut mmu = MMU::new();
+        mmu.store16(0x0601_0004, 0x55);
+        // the 32KiB after the end repeat the OBJ tiles
+        assert_eq!(mmu.load8(0x0601_8004), 0x55);
+        // the whole 96KiB repeat every 128KiB
+        assert_eq!(mmu.load8(0x0603_0004), 0x55);
+        mmu.store8(0x0602_0000, 0x66);
+        assert_eq!(mmu.load8(0x0600_0000), 0x66);
+    }
+
+    #[test]
+    fn test_mmu_rom() {
+        let mut mmu = MMU::new();
+        mmu.load_rom(&[1, 2, 3, 4]);
+        assert_eq!(mmu.load8(0x0800_0002), 3);
+        assert_eq!(mmu.load8(0x0A00_0002), 3);
+        assert_eq!(mmu.load8(0x0C00_0002), 3);
+        // writes are ignored
+        mmu.store8(0x0800_0002, 0xFF);
+        assert_eq!(mmu.load8(0x0800_0002), 3);
+
+        // past the end the bus shows the halfword address
+        assert_eq!(mmu.load8(0x0800_1234), 0x1A);
+        assert_eq!(mmu.load8(0x0800_1235), 0x09);
+        assert_eq!(mmu.load8(0x09FF_FFFE), 0xFF);
+    }
+
+    #[test]
+    fn test_mmu_open_bus() {
+        let mut mmu = MMU::new();
+        mmu.set_prefetched(0xE3A0_1001, false);
+        assert_eq!(mmu.load8(0x0000_4000), 0x01);
+        assert_eq!(mmu.load8(0x0100_0003), 0xE3);
+        assert_eq!(mmu.load8(0x0400_0402), 0xA0);
+        assert_eq!(mmu.load8(0x1000_0001), 0x10);
+
+        mmu.set_prefetched(0x2001, true);
+        assert_eq!(mmu.load8(0x1000_0000), 0x01);
+        assert_eq!(mmu.load8(0x1000_0003), 0x20);
+
+        // writes go nowhere
+        mmu.store8(0x0000_4000, 0xFF);
+        assert_eq!(mmu.load8(0x0000_4000), 0x01);
+    }
+
+    #[test]
+    fn test_mmu_video_byte_writes() {
+        let mut mmu = MMU::new();
+        // palette RAM and BG VRAM repeat the byte in both halves
+        mmu.store8(0x0500_0003, 0x12);
+        assert_eq!(mmu.load8(0x0500_0002), 0x12);
+        assert_eq!(mmu.load8(0x0500_0003), 0x12);
+        mmu.store8(0x0600_FFFE, 0x34);
+        assert_eq!(mmu.load8(0x0600_FFFF), 0x34);
+
+        // OBJ VRAM and OAM ignore them
+        mmu.store8(0x0601_0000, 0x56);
+        assert_eq!(mmu.load8(0x0601_0000), 0);
+        mmu.store8(0x0700_0000, 0x78);
+        assert_eq!(mmu.load8(0x0700_0000), 0);
+        // halfwords still get through
+        mmu.store16(0x0700_0000, 0x789A);
+        assert_eq!(mmu.load8(0x0700_0000), 0x9A);
+        assert_eq!(mmu.load8(0x0700_0001), 0x78);
+
+        // in the bitmap modes BG VRAM goes up to 0x06014000
+        mmu.store8(0x0601_0000, 0x56);
+        assert_eq!(mmu.load8(0x0601_0000), 0);
+        mmu.store16(io::registers::DISPCNT, 3);
+        mmu.store8(0x0601_0000, 0x56);
+        assert_eq!(mmu.load8(0x0601_0001), 0x56);
+        mmu.store8(0x0601_4000, 0x56);
+        assert_eq!(mmu.load8(0x0601_4000), 0);
+    }
+
+    /// A read/write address in each region, wave RAM standing in for the registers
+    const RAM_ADDRESSES: [u32; 7] = [
+        0x0000_0100,
+        0x0200_0100,
+        0x0300_0100,
+        0x0400_0090,
+        0x0500_0100,
+        0x0600_0100,
+        0x0700_0100,
+    ];
+
+    #[test]
+    fn test_mmu_little_endian_round_trip() {
+        let mut mmu = MMU::new();
+        for &addr in RAM_ADDRESSES.iter() {
+            mmu.store32(addr, 0x1234_5678);
+            assert_eq!(mmu.load32(addr), 0x1234_5678, "{:#x}", addr);
+            assert_eq!(mmu.load16(addr), 0x5678, "{:#x}", addr);
+            assert_eq!(mmu.load16(addr + 2), 0x1234, "{:#x}", addr);
+            assert_eq!(mmu.load8(addr), 0x78, "{:#x}", addr);
+            assert_eq!(mmu.load8(addr + 3), 0x12, "{:#x}", addr);
+
+            mmu.store16(addr + 2, 0xABCD);
+            assert_eq!(mmu.load32(addr), 0xABCD_5678, "{:#x}", addr);
+            assert_eq!(mmu.load16(addr + 2), 0xABCD, "{:#x}", addr);
+        }
+    }
+
+    #[test]
+    fn test_mmu_unaligned_accesses() {
+        let mut mmu = MMU::new();
+        for &addr in RAM_ADDRESSES.iter() {
+            // the address is aligned down like on the ARM7TDMI bus
+            mmu.store32(addr + 3, 0x1122_3344);
+            assert_eq!(mmu.load32(addr), 0x1122_3344, "{:#x}", addr);
+            assert_eq!(mmu.load32(addr + 2), 0x1122_3344, "{:#x}", addr);
+            assert_eq!(mmu.load16(addr + 1), 0x3344, "{:#x}", addr);
+
+            mmu.store16(addr + 1, 0x5566);
+            assert_eq!(mmu.load16(addr), 0x5566, "{:#x}", addr);
+        }
+    }
+
+    #[test]
+    fn test_mmu_wide_rom_and_save() {
+        let mut mmu = MMU::new();
+        mmu.load_rom(&[0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(mmu.load16(0x0800_0002), 0x0403);
+        assert_eq!(mmu.load32(0x0800_0000), 0x0403_0201);
+        assert_eq!(mmu.load32(0x0800_1000), 0x0801_0800);
+
+        mmu.set_prefetched(0xE3A0_1001, false);
+        assert_eq!(mmu.load32(0x1000_0000), 0xE3A0_1001);
+        assert_eq!(mmu.load16(0x1000_0002), 0xE3A0);
+
+        mmu.save = save::Save::new(save::SaveType::Sram.chip());
+        mmu.store32(0x0E00_0002, 0x1122_3344);
+        assert_eq!(mmu.load8(0x0E00_0002), 0x22);
+        assert_eq!(mmu.load32(0x0E00_0002), 0x2222_2222);
+    }
+}
@@ -0,0 +1,110 @@
+// Tilt sensor of Yoshi Topsy-Turvy and Koro Koro Puzzle, an accelerometer with registers in the
+// save region as these cartridges keep their save in an EEPROM.
+// Based on https://problemkaputt.de/gbatek.htm#gbacarttiltsensor
+
+use crate::cartridge::Header;
+use crate::gpio::Device;
+use crate::MMU;
+
+/// Addresses of the tilt sensor registers, each a byte
+pub mod registers {
+    /// Writing 0x55 then 0xAA to SAMPLE takes a sample
+    pub const ARM: u32 = 0xE00_8000;
+    pub const SAMPLE: u32 = 0xE00_8100;
+    pub const X_LOW: u32 = 0xE00_8200;
+    /// Bits 8-11 of X, bit 7 is set once the sample is ready
+    pub const X_HIGH: u32 = 0xE00_8300;
+    pub const Y_LOW: u32 = 0xE00_8400;
+    pub const Y_HIGH: u32 = 0xE00_8500;
+}
+
+/// Value of both axes when level
+const CENTRE: i32 = 0x3A0;
+
+#[derive(Clone, Debug, Default)]
+pub struct TiltSensor {
+    /// How far the GBA is tilted, from -32768 to 32767 to the right and towards the player
+    pub tilt: (i16, i16),
+    armed: bool,
+    x: u16,
+    y: u16,
+}
+
+/// The tilt sensor registers, which are in the save region
+pub fn is_register(addr: u32) -> bool {
+    (registers::ARM..=registers::Y_HIGH).contains(&addr)
+}
+
+impl TiltSensor {
+    pub fn read(&self, addr: u32) -> u8 {
+        match addr {
+            registers::X_LOW => self.x as u8,
+            registers::X_HIGH => (self.x >> 8) as u8 & 0xF | 0x80,
+            registers::Y_LOW => self.y as u8,
+            registers::Y_HIGH => (self.y >> 8) as u8 & 0xF,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, addr: u32, value: u8) {
+        match addr {
+            registers::ARM => self.armed = value == 0x55,
+            registers::SAMPLE if self.armed && value == 0xAA => {
+                self.armed = false;
+                // about 1024 either side of the centre
+                self.x = (CENTRE + (self.tilt.0 as i32 >> 5)) as u16;
+                self.y = (CENTRE + (self.tilt.1 as i32 >> 5)) as u16;
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Game codes, without the region letter, of the cartridges with a tilt sensor
+const TILT_GAMES: &[&str] = &[
+    "KHP", // Koro Koro Puzzle: Happy Panechu!
+    "KYG", // Yoshi Topsy-Turvy
+];
+
+/// The tilt sensor of a game, from its game code
+pub fn detect(rom: &[u8]) -> Option<TiltSensor> {
+    let code = Header::parse(rom).ok()?.game_code;
+    if TILT_GAMES.iter().any(|game| code.starts_with(game)) {
+        Some(Default::default())
+    } else {
+        None
+    }
+}
+
+impl MMU {
+    /// How far the player tilts the GBA, from -32768 to 32767 to the right and towards them.
+    /// Gyros take the tilt to the right as the GBA turning clockwise.
+    pub fn set_tilt(&mut self, x: i16, y: i16) {
+        if let Some(ref mut tilt) = self.tilt {
+            tilt.tilt = (x, y);
+        }
+        for device in self.gpio.devices.iter_mut() {
+            if let Device::Gyro(gyro) = device {
+                gyro.rotation = x;
+            }
+        }
+    }
+
+    /// Reads a byte of the save region, where the tilt sensor takes the place of the save
+    pub(crate) fn save_read8(&self, addr: u32) -> u8 {
+        match self.tilt {
+            Some(ref tilt) if is_register(addr) => tilt.read(addr),
+            _ => self.save.read8(addr),
+        }
+    }
+
+    /// Writes a byte of the save region, where the tilt sensor takes the place of the save
+    pub(crate) fn save_write8(&mut self, addr: u32, value: u8) {
+        match self.tilt {
+            Some(ref mut tilt) if is_register(addr) => tilt.write(addr, value),
+            _ => self.save.write8(addr, value),
+        }
+    }
+}
+
+pub mod tests;
@@ -0,0 +1,46 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::gpio::{self, Gyro};
+
+    #[test]
+    fn test_tilt_sensor() {
+        let mut mmu = MMU::new();
+        mmu.save = crate::save::Save::new(crate::save::SaveType::Eeprom.chip());
+        mmu.tilt = Some(Default::default());
+        mmu.set_tilt(-0x4000, 0x2000);
+
+        // nothing is sampled without arming first
+        mmu.store8(registers::SAMPLE, 0xAA);
+        assert_eq!(mmu.load8(registers::X_LOW), 0);
+
+        mmu.store8(registers::ARM, 0x55);
+        mmu.store8(registers::SAMPLE, 0xAA);
+        let x = mmu.load8(registers::X_LOW) as u16 | (mmu.load8(registers::X_HIGH) as u16) << 8;
+        let y = mmu.load8(registers::Y_LOW) as u16 | (mmu.load8(registers::Y_HIGH) as u16) << 8;
+        assert_eq!(x, 0x8000 | (0x3A0 - 0x200));
+        assert_eq!(y, 0x3A0 + 0x100);
+
+        // the rest of the save region is the save chip's
+        assert_eq!(mmu.load8(0x0E00_0000), 0xFF);
+    }
+
+    #[test]
+    fn test_tilt_gyro_rotation() {
+        let mut mmu = MMU::new();
+        mmu.gpio = gpio::Gpio::new(vec![Device::Gyro(Gyro::default())]);
+        mmu.set_tilt(0x1000, 0);
+        match mmu.gpio.devices[0] {
+            Device::Gyro(ref gyro) => assert_eq!(gyro.rotation, 0x1000),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_tilt_detect() {
+        let mut rom = vec![0; 0x200];
+        assert!(detect(&rom).is_none());
+        rom[0xAC..0xB0].copy_from_slice(b"KYGE");
+        assert!(detect(&rom).is_some());
+    }
+}
@@ -0,0 +1,114 @@
+// The four hardware timers. TMxCNT_L holds the reload value, reading it returns the counter.
+
+use crate::io::interrupts;
+use crate::MMU;
+
+/// Addresses of the timer registers
+pub mod registers {
+    pub const TM0CNT_L: u32 = 0x400_0100;
+    pub const TM1CNT_L: u32 = 0x400_0104;
+    pub const TM2CNT_L: u32 = 0x400_0108;
+    pub const TM3CNT_L: u32 = 0x400_010C;
+
+    /// Counter and reload of timer n
+    pub const fn counter(n: usize) -> u32 {
+        TM0CNT_L + n as u32 * 4
+    }
+
+    /// Control of timer n
+    pub const fn control(n: usize) -> u32 {
+        counter(n) + 2
+    }
+}
+
+/// Bits of TMxCNT_H
+pub mod control {
+    pub const PRESCALER: u16 = 0b11;
+    pub const COUNT_UP: u16 = 1 << 2;
+    pub const IRQ: u16 = 1 << 6;
+    pub const ENABLE: u16 = 1 << 7;
+}
+
+/// Cycles per tick for each prescaler setting
+const PRESCALERS: [u32; 4] = [1, 64, 256, 1024];
+
+#[derive(Clone, Copy, Default)]
+pub struct Timer {
+    counter: u16,
+    /// Cycles counted towards the next tick
+    cycles: u32,
+}
+
+fn timer_index(addr: u32) -> usize {
+    ((addr - registers::TM0CNT_L) / 4) as usize
+}
+
+/// Reading TMxCNT_L gives the current count
+pub(crate) fn counter_read(mmu: &MMU, addr: u32) -> u16 {
+    mmu.timers[timer_index(addr)].counter
+}
+
+/// Starting a timer loads the reload value
+pub(crate) fn control_written(mmu: &mut MMU, addr: u32, old: u16, _: u16, _: u16) {
+    let n = timer_index(addr);
+    if mmu.io_raw(addr) & control::ENABLE != 0 && old & control::ENABLE == 0 {
+        mmu.timers[n] = Timer {
+            counter: mmu.io_raw(registers::counter(n)),
+            cycles: 0,
+        };
+    }
+}
+
+impl MMU {
+    /// Runs the timers for a number of cycles, raising the overflow IRQs
+    pub fn step_timers(&mut self, cycles: u32) {
+        let mut overflows = 0;
+        for n in 0..4 {
+            let control = self.io_raw(registers::control(n));
+            if control & control::ENABLE == 0 {
+                overflows = 0;
+                continue;
+            }
+
+            let timer = &mut self.timers[n];
+            // count-up timers tick when the previous one overflows, timer 0 can not cascade
+            let ticks = if n > 0 && control & control::COUNT_UP != 0 {
+                overflows
+            } else {
+                let prescaler = PRESCALERS[(control & control::PRESCALER) as usize];
+                timer.cycles += cycles;
+                let ticks = timer.cycles / prescaler;
+                timer.cycles %= prescaler;
+                ticks
+            };
+
+            let reload = self.io_raw(registers::counter(n)) as u32;
+            let count = self.timers[n].counter as u32 + ticks;
+            overflows = if count > 0xFFFF {
+                let period = 0x1_0000 - reload;
+                let past = count - 0x1_0000;
+                self.timers[n].counter = (reload + past % period) as u16;
+                1 + past / period
+            } else {
+                self.timers[n].counter = count as u16;
+                0
+            };
+
+            if overflows > 0 {
+                if n < 2 {
+                    self.fifo_ticks[n] += overflows;
+                }
+                if control & control::IRQ != 0 {
+                    self.request_interrupt(interrupts::timer(n));
+                }
+            }
+        }
+    }
+
+    /// Overflows of timers 0 and 1 since the last call, these clock the DirectSound FIFOs
+    pub fn take_fifo_ticks(&mut self) -> [u32; 2] {
+        std::mem::take(&mut self.fifo_ticks)
+    }
+}
+
+pub mod tests;
@@ -0,0 +1,59 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_timer_prescaler_and_reload() {
+        let mut mmu = MMU::new();
+
+        mmu.store16(registers::counter(0), 0xFFF0);
+        // the reload value is only loaded when the timer starts
+        assert_eq!(mmu.io_read16(registers::counter(0)), 0);
+        mmu.store16(registers::control(0), control::ENABLE | 1);
+        assert_eq!(mmu.io_read16(registers::counter(0)), 0xFFF0);
+
+        mmu.step_timers(63);
+        assert_eq!(mmu.io_read16(registers::counter(0)), 0xFFF0);
+        mmu.step_timers(1);
+        assert_eq!(mmu.io_read16(registers::counter(0)), 0xFFF1);
+
+        // 16 ticks overflow once and reload
+        mmu.step_timers(64 * 16);
+        assert_eq!(mmu.io_read16(registers::counter(0)), 0xFFF1);
+        assert_eq!(mmu.take_fifo_ticks(), [1, 0]);
+        assert_eq!(mmu.take_fifo_ticks(), [0, 0]);
+    }
+
+    #[test]
+    fn test_timer_cascade_irq() {
+        let mut mmu = MMU::new();
+
+        mmu.store16(registers::counter(1), 0xFFFE);
+        mmu.store16(registers::counter(2), 0xFFFF);
+        mmu.store16(registers::control(2), control::ENABLE | control::COUNT_UP | control::IRQ);
+        mmu.store16(registers::control(1), control::ENABLE);
+
+        mmu.step_timers(1);
+        assert_eq!(mmu.io_raw(0x400_0202), 0);
+        // timer 1 overflows and ticks timer 2 over too
+        mmu.step_timers(1);
+        assert_eq!(mmu.io_read16(registers::counter(1)), 0xFFFE);
+        assert_eq!(mmu.io_read16(registers::counter(2)), 0xFFFF);
+        assert_eq!(mmu.io_raw(0x400_0202), interrupts::TIMER2);
+        assert_eq!(mmu.take_fifo_ticks(), [0, 1]);
+    }
+
+    #[test]
+    fn test_timer_sound_writes() {
+        let mut mmu = MMU::new();
+
+        mmu.store32(0x400_00A0, 0x0403_0201);
+        mmu.store16(0x400_0082, 0x0800);
+        assert_eq!(
+            mmu.sound_writes,
+            vec![(0x400_00A0, 0x0201), (0x400_00A2, 0x0403), (0x400_0082, 0x0800)]
+        );
+        // the FIFO reset does not stick
+        assert_eq!(mmu.io_raw(0x400_0082), 0);
+    }
+}
@@ -3,21 +3,396 @@ use graphics;
 use cpu;
 use audio;
 
+use graphics::bindings::Bindings;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Clocks in a Game Boy frame, 154 lines of 456 dots
+const GB_CYCLES_PER_FRAME: u32 = 70224;
+/// Duration of a Game Boy frame, about 59.7 frames per second
+const GB_FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+/// Cycles in a GBA frame, 228 lines of 1232 cycles
+const GBA_CYCLES_PER_FRAME: u32 = 280_896;
+/// Duration of a GBA frame, the same as a Game Boy one
+const GBA_FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+/// How often input is checked while paused
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Command line options
+struct Options {
+    rom: Option<String>,
+    screen_mode: graphics::ScreenMode,
+    /// Overrides the save chip detected in GBA cartridges
+    save_type: Option<memory::save::SaveType>,
+    /// Patch applied to GBA cartridges instead of the one next to the ROM
+    patch: Option<String>,
+    /// Key bindings file used instead of the one in the config directory
+    bindings: Option<String>,
+    /// Also read the keyboard from the terminal, not only from the input devices
+    #[cfg(feature = "fbdev")]
+    raw_keyboard: bool,
+    /// Link cable to another instance
+    link: Option<LinkCable>,
+    /// Light falling on the solar sensor of the cartridges that have one
+    light: u8,
+}
+
+/// Light on the solar sensor unless given, a sunny day
+const DEFAULT_LIGHT: u8 = 0xC0;
+
+/// End of the link cable, an address is a host:port for TCP or the path of a Unix domain socket
+enum LinkCable {
+    /// Wait for the other instance, this GBA is the parent
+    Listen(String),
+    Connect(String),
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options {
+        rom: None,
+        screen_mode: Default::default(),
+        save_type: None,
+        patch: None,
+        bindings: None,
+        #[cfg(feature = "fbdev")]
+        raw_keyboard: false,
+        link: None,
+        light: DEFAULT_LIGHT,
+    };
+
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--stretch" => options.screen_mode = graphics::ScreenMode::Stretched,
+            "--border" => options.screen_mode = graphics::ScreenMode::Bordered,
+            #[cfg(feature = "fbdev")]
+            "--raw-keyboard" => options.raw_keyboard = true,
+            _ if arg.starts_with("--bindings=") => {
+                options.bindings = Some(arg["--bindings=".len()..].to_string())
+            }
+            _ if arg.starts_with("--link-listen=") => {
+                options.link = Some(LinkCable::Listen(arg["--link-listen=".len()..].to_string()))
+            }
+            _ if arg.starts_with("--link=") => {
+                options.link = Some(LinkCable::Connect(arg["--link=".len()..].to_string()))
+            }
+            _ if arg.starts_with("--light=") => {
+                let level = &arg["--light=".len()..];
+                match level.parse() {
+                    Ok(level) => options.light = level,
+                    Err(_) => return Err(format!("Invalid light level {}, expected 0 to 255", level)),
+                }
+            }
+            _ if arg.starts_with("--patch=") => {
+                options.patch = Some(arg["--patch=".len()..].to_string())
+            }
+            _ if arg.starts_with("--save=") => {
+                let name = &arg["--save=".len()..];
+                match memory::save::SaveType::from_name(name) {
+                    Some(save_type) => options.save_type = Some(save_type),
+                    None => {
+                        return Err(format!(
+                            "Unknown save type {}, expected one of {}",
+                            name,
+                            memory::save::SaveType::NAMES.join(", ")
+                        ))
+                    }
+                }
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => options.rom = Some(arg),
+        }
+    }
+
+    Ok(options)
+}
+
+/// Game Boy and Game Boy Color cartridges run on the LR35902
+fn is_gb_rom(path: &str) -> bool {
+    let path = path.to_lowercase();
+    path.ends_with(".gb") || path.ends_with(".gbc")
+}
+
+/// Key bindings from the given file, else from the config directory when there is a file there
+fn load_bindings(path: Option<&str>) -> Result<Bindings, String> {
+    match path {
+        Some(path) => Bindings::load(Path::new(path)),
+        None => match Bindings::default_path() {
+            Some(path) if path.is_file() => Bindings::load(&path),
+            _ => Ok(Bindings::default()),
+        },
+    }
+}
+
+/// Opens the display with the key bindings and keyboard from the options
+fn open_display(options: &Options) -> Result<graphics::Display, String> {
+    let bindings = load_bindings(options.bindings.as_deref())?;
+    let mut display = graphics::Display::init(4)?;
+    display.set_bindings(bindings);
+    #[cfg(feature = "fbdev")]
+    {
+        if options.raw_keyboard {
+            display.use_raw_keyboard()?;
+        }
+    }
+    Ok(display)
+}
+
+/// Connects the link cable given in the options
+fn open_link(options: &Options) -> Result<Option<Box<dyn memory::serial::Link>>, String> {
+    use memory::serial::cable::Cable;
+    Ok(match options.link {
+        Some(LinkCable::Listen(ref address)) => Some(Box::new(Cable::listen(address)?)),
+        Some(LinkCable::Connect(ref address)) => Some(Box::new(Cable::connect(address)?)),
+        None => None,
+    })
+}
+
+/// Opens the default audio device, the emulator goes on without sound when there is none
+fn open_speaker() -> Option<audio::dmg::Speaker> {
+    match audio::dmg::Speaker::create() {
+        Ok(speaker) => Some(speaker),
+        Err(error) => {
+            eprintln!("{}, continuing without sound", error);
+            None
+        }
+    }
+}
+
+/// Hands the sound writes and timer overflows since the last call to DirectSound, and starts
+/// the DMA of the FIFOs running low
+fn clock_direct_sound(
+    mmu: &mut memory::MMU,
+    sound: &mut audio::directsound::DirectSound,
+    cycles: u32,
+) {
+    for (address, value) in mmu.sound_writes.drain(..) {
+        sound.write(address, value);
+    }
+    for (timer, &overflows) in mmu.take_fifo_ticks().iter().enumerate() {
+        for _ in 0..overflows {
+            for &fifo in sound.timer_overflow(timer).iter().flatten() {
+                mmu.dma_sound_fifo(fifo);
+            }
+        }
+    }
+    sound.step(cycles);
+}
+
+/// Acts on the hotkeys pressed since the last poll. Screenshots are named after the game.
+fn handle_hotkeys(display: &graphics::Display, paused: &mut bool, name: &str) {
+    let input = display.input();
+    if input.pause {
+        *paused = !*paused;
+    }
+    if input.screenshot {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let path = format!("{}-{}.ppm", name, time.as_secs());
+        match display.screenshot(Path::new(&path)) {
+            Ok(()) => eprintln!("Saved screenshot {}", path),
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+}
+
+/// Sleeps until the next frame is due, unless fast forward is held or the emulator is late
+fn wait_for_frame(display: &graphics::Display, next_frame: &mut Instant, duration: Duration) {
+    let now = Instant::now();
+    if display.input().fast_forward {
+        *next_frame = now + duration;
+    } else if *next_frame > now {
+        std::thread::sleep(*next_frame - now);
+        *next_frame += duration;
+    } else {
+        *next_frame = now + duration;
+    }
+}
+
+/// While paused only the hotkeys are handled, returns false when the user exits
+fn wait_while_paused(display: &mut graphics::Display, paused: &mut bool, name: &str) -> bool {
+    while *paused {
+        if display.poll_input().exit {
+            return false;
+        }
+        handle_hotkeys(display, paused, name);
+        std::thread::sleep(PAUSED_POLL_INTERVAL);
+    }
+    true
+}
+
 fn main() {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}", error);
+            eprintln!("Usage: velera [--stretch | --border] [--save=<type>] [--patch=<file>] [--bindings=<file>] [--raw-keyboard] [--link-listen=<address> | --link=<address>] [--light=<0-255>] [rom]");
+            std::process::exit(1);
+        }
+    };
+    let link = match open_link(&options) {
+        Ok(link) => link,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    let display = match open_display(&options) {
+        Ok(display) => display,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    match options.rom {
+        Some(ref rom) if is_gb_rom(rom) => run_gb(rom, options.screen_mode, display),
+        Some(ref rom) => run_gba(
+            rom,
+            options.save_type,
+            options.patch.as_deref(),
+            link,
+            options.light,
+            display,
+        ),
+        None => run_demo(display),
+    }
+}
+
+/// Run a Game Boy cartridge in the GBA's backwards compatibility mode
+fn run_gb(path: &str, screen_mode: graphics::ScreenMode, mut display: graphics::Display) {
+    let rom = match std::fs::read(path) {
+        Ok(rom) => rom,
+        Err(error) => return eprintln!("Unable to read {}: {}", path, error),
+    };
+
+    let mut cpu = cpu::cpu::CPU::default();
+    if let Err(error) = cpu::cpu::load_gb_rom(&mut cpu, rom) {
+        return eprintln!("{}: {}", path, error);
+    }
+
+    let name = Path::new(path).file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let mut paused = false;
+    let mut ppu = graphics::dmg::PPU::default();
+    let mut apu = audio::dmg::Apu::create();
+    let speaker = open_speaker();
+
+    let mut frame_cycles = 0;
+    let mut next_frame = Instant::now() + GB_FRAME_DURATION;
+    loop {
+        let gb_memory = &mut cpu.gb_memory;
+        let mut cycles = cpu.lr.step(gb_memory);
+        // video and sound stay on the normal speed clock
+        if gb_memory.double_speed() {
+            cycles /= 2;
+        }
+
+        ppu.step(gb_memory, cycles);
+        for (address, value) in gb_memory.sound_writes.drain(..) {
+            apu.write(address, value);
+        }
+        apu.step(cycles);
+        let nr52 = gb_memory.io(memory::dmg::registers::NR52);
+        gb_memory.set_io(memory::dmg::registers::NR52, nr52 & 0xF0 | apu.status());
+
+        // with the LCD off there are no frames, keep the frontend going anyway
+        frame_cycles += cycles;
+        let lcd_off = gb_memory.io(memory::dmg::registers::LCDC) & 0x80 == 0;
+        if !ppu.frame_ready && !(lcd_off && frame_cycles >= GB_CYCLES_PER_FRAME) {
+            continue;
+        }
+        ppu.frame_ready = false;
+        frame_cycles = 0;
+
+        let (state, keys) = display.present_dmg(&ppu, screen_mode);
+        if let graphics::State::Exited = state {
+            break;
+        }
+        gb_memory.set_keys(keys);
+        handle_hotkeys(&display, &mut paused, &name);
+        if !wait_while_paused(&mut display, &mut paused, &name) {
+            break;
+        }
+
+        let samples = apu.take_samples();
+        if let Some(ref speaker) = speaker {
+            speaker.play(samples);
+        }
+
+        wait_for_frame(&display, &mut next_frame, GB_FRAME_DURATION);
+    }
+}
+
+/// Run a GBA cartridge, with the save chip detected from the ROM unless overridden and
+/// patched when a patch is given or sits next to the ROM. The link cable is plugged in the
+/// serial port and the light level is what the solar sensor of the cartridge sees. DirectSound
+/// plays on the default audio device.
+fn run_gba(
+    path: &str,
+    save_type: Option<memory::save::SaveType>,
+    patch: Option<&str>,
+    link: Option<Box<dyn memory::serial::Link>>,
+    light: u8,
+    mut display: graphics::Display,
+) {
+    let patch = patch.map(std::path::Path::new);
+    let cartridge = match memory::cartridge::Cartridge::load(std::path::Path::new(path), patch) {
+        Ok(cartridge) => cartridge,
+        Err(error) => return eprintln!("{}", error),
+    };
+
+    let mut cpu = cpu::cpu::CPU::default();
+    cpu.save_type = save_type;
+    cpu::cpu::load_cartridge(&mut cpu, &cartridge);
+    cpu.mmu.set_link(link);
+    cpu.mmu.set_light(light);
+
+    display.set_title(&format!("Velera - {}", cartridge.name()));
+
+    let name = cartridge.name();
+    let mut paused = false;
+    let mut sound = audio::directsound::DirectSound::create();
+    let speaker = open_speaker();
+    let mut frame_cycles = 0;
+    let mut next_frame = Instant::now() + GBA_FRAME_DURATION;
+    loop {
+        // a graphics cycle is done every 4 cpu cycles
+        for _ in 0..4 {
+            cpu::cpu::cycle(&mut cpu);
+        }
+        clock_direct_sound(&mut cpu.mmu, &mut sound, 4);
+        frame_cycles += 4;
+        if frame_cycles >= GBA_CYCLES_PER_FRAME {
+            frame_cycles -= GBA_CYCLES_PER_FRAME;
+            let samples = sound.take_samples();
+            if let Some(ref speaker) = speaker {
+                speaker.play(samples);
+            }
+            wait_for_frame(&display, &mut next_frame, GBA_FRAME_DURATION);
+        }
+        if let (graphics::State::Exited, _) = display.cycle(&mut cpu.mmu) {
+            break;
+        }
+        handle_hotkeys(&display, &mut paused, &name);
+        if !wait_while_paused(&mut display, &mut paused, &name) {
+            break;
+        }
+    }
+
+    if let Err(error) = cpu.mmu.save.flush() {
+        eprintln!("{}", error);
+    }
+}
+
+fn run_demo(mut display: graphics::Display) {
     let mut memory = memory::MMU::new();
-    let mut display = graphics::Display::init(4).unwrap();
 
     // Simulate a test mode-3 cartridge
     {
         // Change graphics mode
         memory.store8(graphics::registers::DISPCNT, 0b00000011);
         // Draw rgb pixels at (80,80)
-        memory.store8(memory::base_addrs::VRAM_ADDR as u32 + 80 * 480 + 80 * 2, 0b00011111);
-        memory.store8(memory::base_addrs::VRAM_ADDR as u32 + 80 * 480 + 80 * 2 + 1 ,0b00000000);
-        memory.store8(memory::base_addrs::VRAM_ADDR as u32 + 80 * 480 + 81 * 2, 0b11100000);
-        memory.store8(memory::base_addrs::VRAM_ADDR as u32 + 80 * 480 + 81 * 2 + 1, 0b00000011);
-        memory.store8(memory::base_addrs::VRAM_ADDR as u32 + 80 * 480 + 82 * 2, 0b00000000);
-        memory.store8(memory::base_addrs::VRAM_ADDR as u32 + 80 * 480 + 82 * 2 + 1, 0b01111100);
+        memory.store16(memory::base_addrs::VRAM_ADDR as u32 + 80 * 480 + 80 * 2, 0b00000000_00011111);
+        memory.store16(memory::base_addrs::VRAM_ADDR as u32 + 80 * 480 + 81 * 2, 0b00000011_11100000);
+        memory.store16(memory::base_addrs::VRAM_ADDR as u32 + 80 * 480 + 82 * 2, 0b01111100_00000000);
     }
 
     use graphics::State;